@@ -0,0 +1,689 @@
+//! Solidity ABI encoding and decoding of function calls, return data and revert data.
+//!
+//! Types are written as in function signatures, e.g. `uint256`, `(address,bytes)[]`.
+
+use crate::eth::{from_hex, keccak256, to_hex};
+use candid::{CandidType, Deserialize, Int, Nat};
+use num::{BigInt, BigUint, Signed};
+
+const WORD: usize = 32;
+// Limits the recursion when parsing nested arrays and tuples.
+const MAX_TYPE_DEPTH: usize = 16;
+// Array elements may share their encoding, so the data length alone does not bound the
+// number of decoded elements.
+const MAX_DECODED_ELEMENTS: usize = 1 << 17;
+// Selectors of the `Error(string)` and `Panic(uint256)` errors raised by Solidity.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AbiType {
+    Uint(usize),
+    Int(usize),
+    Address,
+    Bool,
+    FixedBytes(usize),
+    Bytes,
+    String,
+    Array(Box<AbiType>),
+    FixedArray(Box<AbiType>, usize),
+    Tuple(Vec<AbiType>),
+}
+
+/// An ABI value. Addresses and byte arrays are `0x` prefixed hex.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum AbiValue {
+    Uint(Nat),
+    Int(Int),
+    Address(String),
+    Bool(bool),
+    FixedBytes(String),
+    Bytes(String),
+    String(String),
+    Array(Vec<AbiValue>),
+    Tuple(Vec<AbiValue>),
+}
+
+/// Decoded revert data of a failed call.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum RevertReason {
+    /// `require(condition, message)` and `revert(message)`.
+    Error(String),
+    /// Failed assertions, arithmetic overflow, out-of-bounds access, etc.
+    Panic(Nat),
+    /// A custom error matching one of the error signatures given by the caller.
+    CustomError {
+        signature: String,
+        args: Vec<AbiValue>,
+    },
+    /// Revert data that could not be decoded, possibly empty.
+    Unknown(String),
+}
+
+impl AbiType {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        Self::parse_nested(s, 0)
+    }
+
+    fn parse_nested(s: &str, depth: usize) -> Result<Self, String> {
+        let s = s.trim();
+        if depth > MAX_TYPE_DEPTH {
+            return Err(format!(
+                "types are nested at most {} levels deep",
+                MAX_TYPE_DEPTH
+            ));
+        }
+        if let Some(base) = s.strip_suffix(']') {
+            let open = base
+                .rfind('[')
+                .ok_or_else(|| format!("invalid type {}", s))?;
+            let element = Box::new(AbiType::parse_nested(&base[..open], depth + 1)?);
+            return match &base[open + 1..] {
+                "" => Ok(AbiType::Array(element)),
+                length => length
+                    .parse()
+                    .map(|length| AbiType::FixedArray(element, length))
+                    .map_err(|_| format!("invalid array length in {}", s)),
+            };
+        }
+        if s.starts_with('(') {
+            return parse_nested_type_list(s, depth + 1).map(AbiType::Tuple);
+        }
+        let bits = |prefix: &str| -> Result<usize, String> {
+            let bits = match &s[prefix.len()..] {
+                "" => 256,
+                bits => bits.parse().map_err(|_| format!("invalid type {}", s))?,
+            };
+            if bits == 0 || bits > 256 || bits % 8 != 0 {
+                return Err(format!("invalid type {}", s));
+            }
+            Ok(bits)
+        };
+        match s {
+            "address" => Ok(AbiType::Address),
+            "bool" => Ok(AbiType::Bool),
+            "bytes" => Ok(AbiType::Bytes),
+            "string" => Ok(AbiType::String),
+            _ if s.starts_with("uint") => Ok(AbiType::Uint(bits("uint")?)),
+            _ if s.starts_with("int") => Ok(AbiType::Int(bits("int")?)),
+            _ if s.starts_with("bytes") => match s["bytes".len()..].parse() {
+                Ok(size @ 1..=32) => Ok(AbiType::FixedBytes(size)),
+                _ => Err(format!("invalid type {}", s)),
+            },
+            _ => Err(format!("invalid type {}", s)),
+        }
+    }
+
+    /// The canonical name of the type as used in selectors, e.g. `uint256` for `uint`.
+    pub fn canonical(&self) -> String {
+        match self {
+            AbiType::Uint(bits) => format!("uint{}", bits),
+            AbiType::Int(bits) => format!("int{}", bits),
+            AbiType::Address => "address".to_string(),
+            AbiType::Bool => "bool".to_string(),
+            AbiType::FixedBytes(size) => format!("bytes{}", size),
+            AbiType::Bytes => "bytes".to_string(),
+            AbiType::String => "string".to_string(),
+            AbiType::Array(element) => format!("{}[]", element.canonical()),
+            AbiType::FixedArray(element, length) => {
+                format!("{}[{}]", element.canonical(), length)
+            }
+            AbiType::Tuple(types) => format!("({})", canonical_list(types)),
+        }
+    }
+
+    fn is_dynamic(&self) -> bool {
+        match self {
+            AbiType::Bytes | AbiType::String | AbiType::Array(_) => true,
+            AbiType::FixedArray(element, _) => element.is_dynamic(),
+            AbiType::Tuple(types) => types.iter().any(AbiType::is_dynamic),
+            _ => false,
+        }
+    }
+
+    // Size of the type in the head of an enclosing tuple.
+    fn head_size(&self) -> Result<usize, String> {
+        match self {
+            _ if self.is_dynamic() => Ok(WORD),
+            AbiType::FixedArray(element, length) => element
+                .head_size()?
+                .checked_mul(*length)
+                .ok_or_else(|| format!("{} is too large", self.canonical())),
+            AbiType::Tuple(types) => tuple_head_size(types),
+            _ => Ok(WORD),
+        }
+    }
+}
+
+fn tuple_head_size(types: &[AbiType]) -> Result<usize, String> {
+    types.iter().try_fold(0usize, |size, abi_type| {
+        size.checked_add(abi_type.head_size()?)
+            .ok_or_else(|| format!("({}) is too large", canonical_list(types)))
+    })
+}
+
+/// Parses a parenthesized, comma separated list of types such as `(address,uint256)`.
+pub fn parse_type_list(s: &str) -> Result<Vec<AbiType>, String> {
+    parse_nested_type_list(s, 0)
+}
+
+fn parse_nested_type_list(s: &str, depth: usize) -> Result<Vec<AbiType>, String> {
+    let inner = s
+        .trim()
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| format!("invalid type list {}", s))?;
+    if inner.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    let mut types = vec![];
+    let mut open = 0usize;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '(' => open += 1,
+            ')' => {
+                open = open
+                    .checked_sub(1)
+                    .ok_or_else(|| format!("unbalanced parentheses in {}", s))?
+            }
+            ',' if open == 0 => {
+                types.push(AbiType::parse_nested(&inner[start..i], depth)?);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if open != 0 {
+        return Err(format!("unbalanced parentheses in {}", s));
+    }
+    types.push(AbiType::parse_nested(&inner[start..], depth)?);
+    Ok(types)
+}
+
+fn canonical_list(types: &[AbiType]) -> String {
+    types
+        .iter()
+        .map(AbiType::canonical)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A function or error signature such as `transfer(address,uint256)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub inputs: Vec<AbiType>,
+}
+
+impl Signature {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let open = s
+            .find('(')
+            .ok_or_else(|| format!("invalid signature {}", s))?;
+        let name = &s[..open];
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("invalid signature {}", s));
+        }
+        Ok(Signature {
+            name: name.to_string(),
+            inputs: parse_type_list(&s[open..])?,
+        })
+    }
+
+    pub fn canonical(&self) -> String {
+        format!("{}({})", self.name, canonical_list(&self.inputs))
+    }
+
+    pub fn selector(&self) -> [u8; 4] {
+        let hash = keccak256(self.canonical().as_bytes());
+        [hash[0], hash[1], hash[2], hash[3]]
+    }
+
+    /// Calldata of a call with the given arguments.
+    pub fn encode_call(&self, args: &[AbiValue]) -> Result<Vec<u8>, String> {
+        Ok([self.selector().to_vec(), encode(&self.inputs, args)?].concat())
+    }
+}
+
+/// Encodes values as the tuple of the given types.
+pub fn encode(types: &[AbiType], values: &[AbiValue]) -> Result<Vec<u8>, String> {
+    if types.len() != values.len() {
+        return Err(format!(
+            "expected {} values, found {}",
+            types.len(),
+            values.len()
+        ));
+    }
+    let head_size = tuple_head_size(types)?;
+    let mut head = vec![];
+    let mut tail = vec![];
+    for (abi_type, value) in types.iter().zip(values) {
+        let encoded = encode_value(abi_type, value)?;
+        if abi_type.is_dynamic() {
+            head.extend(uint_word(&BigUint::from(head_size + tail.len())));
+            tail.extend(encoded);
+        } else {
+            head.extend(encoded);
+        }
+    }
+    head.extend(tail);
+    Ok(head)
+}
+
+fn encode_value(abi_type: &AbiType, value: &AbiValue) -> Result<Vec<u8>, String> {
+    match (abi_type, value) {
+        (AbiType::Uint(bits), AbiValue::Uint(n)) => {
+            if n.0.bits() > *bits as u64 {
+                return Err(format!("{} does not fit uint{}", n, bits));
+            }
+            Ok(uint_word(&n.0).to_vec())
+        }
+        (AbiType::Int(bits), AbiValue::Int(n)) => {
+            let bound = BigInt::from(1) << (bits - 1);
+            if n.0 >= bound || n.0 < -bound {
+                return Err(format!("{} does not fit int{}", n.0, bits));
+            }
+            let twos_complement = if n.0.is_negative() {
+                (BigInt::from(1) << 256) + &n.0
+            } else {
+                n.0.clone()
+            };
+            Ok(uint_word(&twos_complement.to_biguint().unwrap_or_default()).to_vec())
+        }
+        (AbiType::Address, AbiValue::Address(address)) => {
+            let bytes = from_hex(address)?;
+            if bytes.len() != 20 {
+                return Err(format!("invalid address {}", address));
+            }
+            Ok(pad_left(&bytes))
+        }
+        (AbiType::Bool, AbiValue::Bool(b)) => Ok(uint_word(&BigUint::from(*b as u8)).to_vec()),
+        (AbiType::FixedBytes(size), AbiValue::FixedBytes(data)) => {
+            let bytes = from_hex(data)?;
+            if bytes.len() != *size {
+                return Err(format!("{} is not a bytes{}", data, size));
+            }
+            Ok(pad_right(&bytes))
+        }
+        (AbiType::Bytes, AbiValue::Bytes(data)) => Ok(encode_bytes(&from_hex(data)?)),
+        (AbiType::String, AbiValue::String(s)) => Ok(encode_bytes(s.as_bytes())),
+        (AbiType::Array(element), AbiValue::Array(values)) => {
+            let types = vec![element.as_ref().clone(); values.len()];
+            Ok([
+                uint_word(&BigUint::from(values.len())).to_vec(),
+                encode(&types, values)?,
+            ]
+            .concat())
+        }
+        (AbiType::FixedArray(element, length), AbiValue::Array(values)) => {
+            if values.len() != *length {
+                return Err(format!(
+                    "expected {} array elements, found {}",
+                    length,
+                    values.len()
+                ));
+            }
+            encode(&vec![element.as_ref().clone(); *length], values)
+        }
+        (AbiType::Tuple(types), AbiValue::Tuple(values)) => encode(types, values),
+        (abi_type, value) => Err(format!("{:?} is not a {}", value, abi_type.canonical())),
+    }
+}
+
+fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+    [
+        uint_word(&BigUint::from(bytes.len())).to_vec(),
+        pad_right(bytes),
+    ]
+    .concat()
+}
+
+fn uint_word(n: &BigUint) -> [u8; WORD] {
+    let bytes = n.to_bytes_be();
+    let mut word = [0; WORD];
+    word[WORD - bytes.len()..].copy_from_slice(&bytes);
+    word
+}
+
+fn pad_left(bytes: &[u8]) -> Vec<u8> {
+    [vec![0; WORD - bytes.len()], bytes.to_vec()].concat()
+}
+
+fn pad_right(bytes: &[u8]) -> Vec<u8> {
+    let mut padded = bytes.to_vec();
+    padded.resize((bytes.len() + WORD - 1) / WORD * WORD, 0);
+    padded
+}
+
+/// Decodes data encoded as the tuple of the given types.
+pub fn decode(types: &[AbiType], data: &[u8]) -> Result<Vec<AbiValue>, String> {
+    let mut elements = MAX_DECODED_ELEMENTS;
+    decode_tuple(types, data, &mut elements)
+}
+
+// Decodes a tuple, decrementing `elements` by the number of array elements decoded.
+fn decode_tuple(
+    types: &[AbiType],
+    data: &[u8],
+    elements: &mut usize,
+) -> Result<Vec<AbiValue>, String> {
+    let mut offset = 0;
+    let mut values = Vec::with_capacity(types.len());
+    for abi_type in types {
+        let value = if abi_type.is_dynamic() {
+            let tail = read_offset(data, offset)?;
+            decode_value(abi_type, &data[tail..], elements)?
+        } else {
+            let head = data
+                .get(offset..)
+                .ok_or_else(|| "data too short".to_string())?;
+            decode_value(abi_type, head, elements)?
+        };
+        values.push(value);
+        offset += abi_type.head_size()?;
+    }
+    Ok(values)
+}
+
+fn decode_value(abi_type: &AbiType, data: &[u8], elements: &mut usize) -> Result<AbiValue, String> {
+    match abi_type {
+        AbiType::Uint(bits) => {
+            let n = BigUint::from_bytes_be(read_word(data, 0)?);
+            if n.bits() > *bits as u64 {
+                return Err(format!("{} does not fit uint{}", n, bits));
+            }
+            Ok(AbiValue::Uint(Nat(n)))
+        }
+        AbiType::Int(bits) => {
+            let n = BigInt::from_signed_bytes_be(read_word(data, 0)?);
+            let bound = BigInt::from(1) << (bits - 1);
+            if n >= bound || n < -bound {
+                return Err(format!("{} does not fit int{}", n, bits));
+            }
+            Ok(AbiValue::Int(Int(n)))
+        }
+        AbiType::Address => {
+            let word = read_word(data, 0)?;
+            if word[..12].iter().any(|b| *b != 0) {
+                return Err(format!("invalid address {}", to_hex(word)));
+            }
+            Ok(AbiValue::Address(to_hex(&word[12..])))
+        }
+        AbiType::Bool => match BigUint::from_bytes_be(read_word(data, 0)?) {
+            n if n == BigUint::from(0u8) => Ok(AbiValue::Bool(false)),
+            n if n == BigUint::from(1u8) => Ok(AbiValue::Bool(true)),
+            n => Err(format!("invalid bool {}", n)),
+        },
+        AbiType::FixedBytes(size) => {
+            let word = read_word(data, 0)?;
+            if word[*size..].iter().any(|b| *b != 0) {
+                return Err(format!("invalid bytes{} padding", size));
+            }
+            Ok(AbiValue::FixedBytes(to_hex(&word[..*size])))
+        }
+        AbiType::Bytes => Ok(AbiValue::Bytes(to_hex(decode_bytes(data)?))),
+        AbiType::String => String::from_utf8(decode_bytes(data)?.to_vec())
+            .map(AbiValue::String)
+            .map_err(|_| "invalid UTF-8 string".to_string()),
+        AbiType::Array(element) => {
+            let length = read_usize(data, 0)?;
+            // Every element takes at least one word, which bounds the allocation below.
+            if length > data.len() / WORD {
+                return Err(format!("invalid array length {}", length));
+            }
+            take_elements(elements, length)?;
+            let types = vec![element.as_ref().clone(); length];
+            decode_tuple(&types, &data[WORD..], elements).map(AbiValue::Array)
+        }
+        AbiType::FixedArray(element, length) => {
+            if *length > data.len() / WORD {
+                return Err("data too short".to_string());
+            }
+            take_elements(elements, *length)?;
+            let types = vec![element.as_ref().clone(); *length];
+            decode_tuple(&types, data, elements).map(AbiValue::Array)
+        }
+        AbiType::Tuple(types) => decode_tuple(types, data, elements).map(AbiValue::Tuple),
+    }
+}
+
+fn take_elements(elements: &mut usize, count: usize) -> Result<(), String> {
+    *elements = elements
+        .checked_sub(count)
+        .ok_or_else(|| format!("more than {} array elements", MAX_DECODED_ELEMENTS))?;
+    Ok(())
+}
+
+fn decode_bytes(data: &[u8]) -> Result<&[u8], String> {
+    let length = read_usize(data, 0)?;
+    data.get(WORD..)
+        .and_then(|bytes| bytes.get(..length))
+        .ok_or_else(|| "data too short".to_string())
+}
+
+fn read_word(data: &[u8], offset: usize) -> Result<&[u8], String> {
+    data.get(offset..)
+        .and_then(|data| data.get(..WORD))
+        .ok_or_else(|| "data too short".to_string())
+}
+
+fn read_usize(data: &[u8], offset: usize) -> Result<usize, String> {
+    let word = read_word(data, offset)?;
+    if word[..WORD - 8].iter().any(|b| *b != 0) {
+        return Err("offset or length out of range".to_string());
+    }
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&word[WORD - 8..]);
+    usize::try_from(u64::from_be_bytes(bytes)).map_err(|e| e.to_string())
+}
+
+fn read_offset(data: &[u8], offset: usize) -> Result<usize, String> {
+    let tail = read_usize(data, offset)?;
+    if tail > data.len() {
+        return Err(format!("offset {} out of range", tail));
+    }
+    Ok(tail)
+}
+
+/// Decodes the revert data of a failed call, trying the given custom error signatures
+/// after the standard `Error(string)` and `Panic(uint256)` errors.
+pub fn decode_revert(data: &[u8], error_signatures: &[Signature]) -> RevertReason {
+    if data.len() < 4 {
+        return RevertReason::Unknown(to_hex(data));
+    }
+    let (selector, args) = data.split_at(4);
+    let decoded = if selector == ERROR_SELECTOR {
+        decode(&[AbiType::String], args).map(|mut values| match values.pop() {
+            Some(AbiValue::String(message)) => RevertReason::Error(message),
+            _ => unreachable!("decoded a string"),
+        })
+    } else if selector == PANIC_SELECTOR {
+        decode(&[AbiType::Uint(256)], args).map(|mut values| match values.pop() {
+            Some(AbiValue::Uint(code)) => RevertReason::Panic(code),
+            _ => unreachable!("decoded a uint256"),
+        })
+    } else {
+        error_signatures
+            .iter()
+            .find(|signature| signature.selector() == selector)
+            .ok_or_else(String::new)
+            .and_then(|signature| {
+                decode(&signature.inputs, args).map(|args| RevertReason::CustomError {
+                    signature: signature.canonical(),
+                    args,
+                })
+            })
+    };
+    decoded.unwrap_or_else(|_| RevertReason::Unknown(to_hex(data)))
+}
+
+#[test]
+fn check_parse_signature() {
+    let signature = Signature::parse("transfer(address, uint)").unwrap();
+    assert_eq!(signature.canonical(), "transfer(address,uint256)");
+    assert_eq!(signature.selector(), [0xa9, 0x05, 0x9c, 0xbb]);
+    assert_eq!(
+        Signature::parse("f((uint8,bytes32)[2][],string)")
+            .unwrap()
+            .inputs,
+        vec![
+            AbiType::Array(Box::new(AbiType::FixedArray(
+                Box::new(AbiType::Tuple(vec![
+                    AbiType::Uint(8),
+                    AbiType::FixedBytes(32)
+                ])),
+                2
+            ))),
+            AbiType::String
+        ]
+    );
+    assert_eq!(Signature::parse("totalSupply()").unwrap().inputs, vec![]);
+    for invalid in [
+        "f",
+        "(uint256)",
+        "f(uint7)",
+        "f(bytes33)",
+        "f((uint256)",
+        "f(foo)",
+    ] {
+        assert!(Signature::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn check_encode_decode() {
+    // Example from the Solidity ABI specification.
+    let signature = Signature::parse("f(uint256,uint32[],bytes10,bytes)").unwrap();
+    let args = vec![
+        AbiValue::Uint(Nat::from(0x123u64)),
+        AbiValue::Array(vec![
+            AbiValue::Uint(Nat::from(0x456u64)),
+            AbiValue::Uint(Nat::from(0x789u64)),
+        ]),
+        AbiValue::FixedBytes(to_hex(b"1234567890")),
+        AbiValue::Bytes(to_hex(b"Hello, world!")),
+    ];
+    let calldata = signature.encode_call(&args).unwrap();
+    assert_eq!(
+        to_hex(&calldata),
+        concat!(
+            "0x8be65246",
+            "0000000000000000000000000000000000000000000000000000000000000123",
+            "0000000000000000000000000000000000000000000000000000000000000080",
+            "3132333435363738393000000000000000000000000000000000000000000000",
+            "00000000000000000000000000000000000000000000000000000000000000e0",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "0000000000000000000000000000000000000000000000000000000000000456",
+            "0000000000000000000000000000000000000000000000000000000000000789",
+            "000000000000000000000000000000000000000000000000000000000000000d",
+            "48656c6c6f2c20776f726c642100000000000000000000000000000000000000",
+        )
+    );
+    assert_eq!(decode(&signature.inputs, &calldata[4..]), Ok(args));
+
+    let types = vec![
+        AbiType::Int(8),
+        AbiType::Tuple(vec![AbiType::Bool, AbiType::String]),
+        AbiType::Address,
+    ];
+    let values = vec![
+        AbiValue::Int(Int(BigInt::from(-2))),
+        AbiValue::Tuple(vec![
+            AbiValue::Bool(true),
+            AbiValue::String("ok".to_string()),
+        ]),
+        AbiValue::Address("0x00000000000000000000000000000000000000ff".to_string()),
+    ];
+    let data = encode(&types, &values).unwrap();
+    assert_eq!(&data[..WORD - 1], &[0xff; WORD - 1]);
+    assert_eq!(data[WORD - 1], 0xfe);
+    assert_eq!(decode(&types, &data), Ok(values));
+
+    assert!(encode(&[AbiType::Uint(8)], &[AbiValue::Uint(Nat::from(256u64))]).is_err());
+    assert!(encode(&[AbiType::Int(8)], &[AbiValue::Int(Int(BigInt::from(128)))]).is_err());
+    assert!(encode(&[AbiType::Bool], &[AbiValue::Uint(Nat::from(1u64))]).is_err());
+    assert!(decode(&[AbiType::Bool], &data[..WORD - 1]).is_err());
+    assert!(decode(&[AbiType::Bytes], &[0xff; WORD]).is_err());
+    assert!(encode(
+        &[AbiType::FixedBytes(2)],
+        &[AbiValue::FixedBytes("0x123456".to_string())]
+    )
+    .is_err());
+    assert!(decode(&[AbiType::FixedBytes(2)], &[0xff; WORD]).is_err());
+    let huge = AbiType::FixedArray(Box::new(AbiType::Uint(256)), usize::MAX);
+    assert!(encode(
+        &[huge.clone(), AbiType::Bool],
+        &[AbiValue::Array(vec![]), AbiValue::Bool(true)]
+    )
+    .is_err());
+    assert!(decode(&[huge], &data).is_err());
+
+    // The elements of an outer array all point to the same inner array.
+    let length = 1000;
+    let word = |n: usize| uint_word(&BigUint::from(n)).to_vec();
+    let mut aliased = [word(WORD), word(length)].concat();
+    aliased.extend((0..length).flat_map(|_| word(length * WORD)));
+    aliased.extend(word(length));
+    aliased.extend((0..length).flat_map(word));
+    let nested_array = AbiType::parse("uint256[][]").unwrap();
+    assert!(decode(&[nested_array], &aliased).is_err());
+    let array = AbiType::parse("uint256[]").unwrap();
+    assert!(decode(&[array], &aliased[..(length + 2) * WORD]).is_ok());
+    let nested = format!("uint256{}", "[]".repeat(MAX_TYPE_DEPTH));
+    assert!(AbiType::parse(&nested).is_ok());
+    assert!(AbiType::parse(&format!("{}[]", nested)).is_err());
+    let nested = format!("{}uint256{}", "(".repeat(10_000), ")".repeat(10_000));
+    assert!(AbiType::parse(&nested).is_err());
+    assert!(parse_type_list(&nested).is_err());
+}
+
+#[test]
+fn check_decode_revert() {
+    let error = [
+        ERROR_SELECTOR.to_vec(),
+        encode(
+            &[AbiType::String],
+            &[AbiValue::String("insufficient balance".to_string())],
+        )
+        .unwrap(),
+    ]
+    .concat();
+    assert_eq!(
+        decode_revert(&error, &[]),
+        RevertReason::Error("insufficient balance".to_string())
+    );
+
+    let panic = [
+        PANIC_SELECTOR.to_vec(),
+        encode(&[AbiType::Uint(256)], &[AbiValue::Uint(Nat::from(0x11u64))]).unwrap(),
+    ]
+    .concat();
+    assert_eq!(
+        decode_revert(&panic, &[]),
+        RevertReason::Panic(Nat::from(0x11u64))
+    );
+
+    let signature = Signature::parse("InsufficientBalance(uint256,uint256)").unwrap();
+    let args = vec![
+        AbiValue::Uint(Nat::from(1u64)),
+        AbiValue::Uint(Nat::from(2u64)),
+    ];
+    let custom = signature.encode_call(&args).unwrap();
+    assert_eq!(
+        decode_revert(&custom, std::slice::from_ref(&signature)),
+        RevertReason::CustomError {
+            signature: signature.canonical(),
+            args
+        }
+    );
+    assert_eq!(
+        decode_revert(&custom, &[]),
+        RevertReason::Unknown(to_hex(&custom))
+    );
+    assert_eq!(
+        decode_revert(&[], &[]),
+        RevertReason::Unknown("0x".to_string())
+    );
+}
@@ -0,0 +1,213 @@
+//! Rules for caching JSON-RPC responses whose result can no longer change, or only if their
+//! block is reorganized.
+
+use crate::json_rpc::JsonRpcRequest;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+pub type CacheKey = [u8; 32];
+
+/// Returns the cache key of a request if its response only changes if its block is
+/// reorganized, i.e., the request names its block by hash or number, or refers to objects
+/// addressed by hash.
+///
+/// Params are canonicalized (object members sorted, strings lowercased) so that equivalent
+/// requests share a key; the request id is ignored.
+pub fn cache_key(chain_id: u64, request: &JsonRpcRequest) -> Option<CacheKey> {
+    if !is_cacheable_request(&request.method, &request.params) {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(chain_id.to_be_bytes());
+    hasher.update(request.method.as_bytes());
+    hasher.update([0]);
+    hasher.update(canonicalize(&request.params).to_string().as_bytes());
+    Some(hasher.finalize().into())
+}
+
+/// Whether a result of a cacheable request may be cached: results for objects that do not
+/// exist (yet) and transactions that are still pending are not.
+pub fn is_cacheable_result(method: &str, result: &Value) -> bool {
+    match method {
+        "eth_getTransactionByHash" | "eth_getTransactionByBlockHashAndIndex" => result
+            .get("blockHash")
+            .map_or(false, |block_hash| !block_hash.is_null()),
+        _ => !result.is_null(),
+    }
+}
+
+/// How long the result of a cacheable request stays valid.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Validity {
+    /// The result never changes: it does not depend on a block or the request names its
+    /// block by hash.
+    Permanent,
+    /// The result changes if its block is reorganized, which may still happen unless the
+    /// block is final.
+    UntilReorg,
+}
+
+pub fn validity(method: &str, params: &Value) -> Validity {
+    let block_param_index = match method {
+        "eth_chainId"
+        | "net_version"
+        | "eth_getBlockByHash"
+        | "eth_getBlockTransactionCountByHash"
+        | "eth_getTransactionByBlockHashAndIndex" => return Validity::Permanent,
+        "eth_getBalance" | "eth_getCode" | "eth_getTransactionCount" | "eth_call" => 1,
+        "eth_getStorageAt" | "eth_getProof" => 2,
+        // Including blocks of transactions and receipts looked up by hash.
+        _ => return Validity::UntilReorg,
+    };
+    match params.get(block_param_index) {
+        Some(Value::Object(block)) if block.contains_key("blockHash") => Validity::Permanent,
+        Some(Value::Object(block))
+            if block.get("blockNumber").and_then(Value::as_str) == Some("earliest") =>
+        {
+            Validity::Permanent
+        }
+        Some(Value::String(block)) if block == "earliest" => Validity::Permanent,
+        _ => Validity::UntilReorg,
+    }
+}
+
+fn is_cacheable_request(method: &str, params: &Value) -> bool {
+    let block_param_index = match method {
+        "eth_chainId" | "net_version" => return true,
+        "eth_getBlockByHash"
+        | "eth_getBlockTransactionCountByHash"
+        | "eth_getTransactionByHash"
+        | "eth_getTransactionByBlockHashAndIndex"
+        | "eth_getTransactionReceipt" => return true,
+        "eth_getBalance" | "eth_getCode" | "eth_getTransactionCount" | "eth_call" => 1,
+        "eth_getStorageAt" | "eth_getProof" => 2,
+        _ => return false,
+    };
+    params.get(block_param_index).map_or(false, is_fixed_block)
+}
+
+/// Whether a block parameter names a specific block rather than a tag like `latest`.
+fn is_fixed_block(block: &Value) -> bool {
+    match block {
+        Value::String(block) => block == "earliest" || is_hex(block),
+        // EIP-1898 block parameter.
+        Value::Object(block) => block
+            .get("blockHash")
+            .or_else(|| block.get("blockNumber"))
+            .and_then(Value::as_str)
+            .map_or(false, is_hex),
+        _ => false,
+    }
+}
+
+fn is_hex(s: &str) -> bool {
+    s.strip_prefix("0x").map_or(false, |digits| {
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+fn canonicalize(value: &Value) -> Value {
+    match value {
+        Value::String(s) => Value::String(s.to_ascii_lowercase()),
+        Value::Array(values) => Value::Array(values.iter().map(canonicalize).collect()),
+        // Object members are kept sorted by key by `serde_json::Map`.
+        Value::Object(members) => Value::Object(
+            members
+                .iter()
+                .map(|(k, v)| (k.clone(), canonicalize(v)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+#[test]
+fn check_cache_key() {
+    use crate::json_rpc::parse_payload;
+    let key = |chain_id, payload: &str| cache_key(chain_id, &parse_payload(payload).unwrap()[0]);
+
+    let code = key(
+        1,
+        r#"{"jsonrpc":"2.0","method":"eth_getCode","params":["0xAbC0000000000000000000000000000000000000","0x10"],"id":1}"#,
+    );
+    assert!(code.is_some());
+    assert_eq!(
+        code,
+        key(
+            1,
+            r#"{"jsonrpc":"2.0","method":"eth_getCode","params":["0xabc0000000000000000000000000000000000000","0x10"],"id":7}"#,
+        )
+    );
+    assert_ne!(
+        code,
+        key(
+            5,
+            r#"{"jsonrpc":"2.0","method":"eth_getCode","params":["0xabc0000000000000000000000000000000000000","0x10"],"id":1}"#,
+        )
+    );
+    assert!(key(
+        1,
+        r#"{"jsonrpc":"2.0","method":"eth_getCode","params":["0xabc0000000000000000000000000000000000000","latest"],"id":1}"#,
+    )
+    .is_none());
+    assert!(key(
+        1,
+        r#"{"jsonrpc":"2.0","method":"eth_getBalance","params":["0xabc0000000000000000000000000000000000000",{"blockHash":"0x01"}],"id":1}"#,
+    )
+    .is_some());
+    assert!(key(1, r#"{"jsonrpc":"2.0","method":"eth_chainId","id":1}"#).is_some());
+    assert!(key(1, r#"{"jsonrpc":"2.0","method":"eth_blockNumber","id":1}"#).is_none());
+    assert!(key(
+        1,
+        r#"{"jsonrpc":"2.0","method":"eth_call","params":[{"to":"0x01"}],"id":1}"#
+    )
+    .is_none());
+}
+
+#[test]
+fn check_validity() {
+    use serde_json::json;
+    let address = "0xabc0000000000000000000000000000000000000";
+    assert_eq!(validity("eth_chainId", &json!([])), Validity::Permanent);
+    assert_eq!(
+        validity("eth_getBalance", &json!([address, "0x10"])),
+        Validity::UntilReorg
+    );
+    assert_eq!(
+        validity(
+            "eth_getStorageAt",
+            &json!([address, "0x0", {"blockNumber": "0x20"}])
+        ),
+        Validity::UntilReorg
+    );
+    assert_eq!(
+        validity("eth_call", &json!([{"to": address}, {"blockHash": "0x01"}])),
+        Validity::Permanent
+    );
+    assert_eq!(
+        validity("eth_getCode", &json!([address, "earliest"])),
+        Validity::Permanent
+    );
+    assert_eq!(
+        validity("eth_getTransactionReceipt", &json!(["0x01"])),
+        Validity::UntilReorg
+    );
+}
+
+#[test]
+fn check_is_cacheable_result() {
+    use serde_json::json;
+    assert!(is_cacheable_result("eth_chainId", &json!("0x1")));
+    assert!(!is_cacheable_result(
+        "eth_getTransactionReceipt",
+        &Value::Null
+    ));
+    assert!(!is_cacheable_result(
+        "eth_getTransactionByHash",
+        &json!({"hash": "0x01", "blockHash": null})
+    ));
+    assert!(is_cacheable_result(
+        "eth_getTransactionByHash",
+        &json!({"hash": "0x01", "blockHash": "0x02"})
+    ));
+}
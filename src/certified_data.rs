@@ -0,0 +1,82 @@
+//! Certified map of cached responses and registered providers, served by query calls.
+//!
+//! The certified data of the canister is the root hash of the tree
+//!
+//! ```text
+//! fork(labeled("responses", responses), labeled("providers", providers))
+//! ```
+//!
+//! where `responses` maps cache keys to the SHA-256 of the JSON encoded result and
+//! `providers` maps big-endian provider ids to the SHA-256 of the Candid encoded
+//! `RegisteredProvider`.
+
+use crate::cache::CacheKey;
+use ic_certified_map::{
+    fork, fork_hash, labeled, labeled_hash, AsHashTree, Hash, HashTree, RbTree,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+const LABEL_RESPONSES: &[u8] = b"responses";
+const LABEL_PROVIDERS: &[u8] = b"providers";
+
+#[derive(Default)]
+pub struct CertifiedData {
+    responses: RbTree<Vec<u8>, Hash>,
+    providers: RbTree<Vec<u8>, Hash>,
+}
+
+impl CertifiedData {
+    pub fn insert_response(&mut self, key: &CacheKey, result: &[u8]) {
+        self.responses.insert(key.to_vec(), sha256(result));
+    }
+
+    pub fn remove_response(&mut self, key: &CacheKey) {
+        self.responses.delete(key);
+    }
+
+    pub fn insert_provider(&mut self, provider_id: u64, registered_provider: &[u8]) {
+        self.providers.insert(
+            provider_id.to_be_bytes().to_vec(),
+            sha256(registered_provider),
+        );
+    }
+
+    pub fn remove_provider(&mut self, provider_id: u64) {
+        self.providers.delete(&provider_id.to_be_bytes());
+    }
+
+    pub fn root_hash(&self) -> Hash {
+        fork_hash(
+            &labeled_hash(LABEL_RESPONSES, &self.responses.root_hash()),
+            &labeled_hash(LABEL_PROVIDERS, &self.providers.root_hash()),
+        )
+    }
+
+    /// CBOR encoded witness for the cached response with the given key.
+    pub fn response_witness(&self, key: &CacheKey) -> Vec<u8> {
+        serialize_tree(fork(
+            labeled(LABEL_RESPONSES, self.responses.witness(key)),
+            HashTree::Pruned(labeled_hash(LABEL_PROVIDERS, &self.providers.root_hash())),
+        ))
+    }
+
+    /// CBOR encoded witness for all registered providers.
+    pub fn providers_witness(&self) -> Vec<u8> {
+        serialize_tree(fork(
+            HashTree::Pruned(labeled_hash(LABEL_RESPONSES, &self.responses.root_hash())),
+            labeled(LABEL_PROVIDERS, self.providers.as_hash_tree()),
+        ))
+    }
+}
+
+fn sha256(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+fn serialize_tree(tree: HashTree<'_>) -> Vec<u8> {
+    let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
+    serializer.self_describe().unwrap();
+    tree.serialize(&mut serializer).unwrap();
+    serializer.into_inner()
+}
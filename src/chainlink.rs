@@ -0,0 +1,118 @@
+//! Reading of Chainlink price feeds, i.e., aggregator proxy contracts.
+
+use crate::abi::{self, AbiValue};
+use candid::{CandidType, Deserialize, Int, Nat};
+use num_traits::{Signed, ToPrimitive};
+
+pub const LATEST_ROUND_DATA: &str = "latestRoundData()";
+const LATEST_ROUND_DATA_RETURNS: &str = "(uint80,int256,uint256,uint256,uint80)";
+pub const DECIMALS: &str = "decimals()";
+const DECIMALS_RETURNS: &str = "(uint8)";
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct PriceFeed {
+    pub round_id: Nat,
+    // The price is `answer / 10^decimals`.
+    pub answer: Int,
+    pub decimals: u8,
+    // Seconds since the epoch.
+    pub started_at: u64,
+    pub updated_at: u64,
+    pub answered_in_round: Nat,
+}
+
+impl PriceFeed {
+    /// Decodes the outputs of `latestRoundData` and `decimals`.
+    pub fn decode(round_data: &[u8], decimals: &[u8]) -> Result<Self, String> {
+        let returns = abi::parse_type_list(LATEST_ROUND_DATA_RETURNS).expect("invalid returns");
+        let values = abi::decode(&returns, round_data)?;
+        let (round_id, answer, started_at, updated_at, answered_in_round) = match values.as_slice()
+        {
+            [AbiValue::Uint(round_id), AbiValue::Int(answer), AbiValue::Uint(started_at), AbiValue::Uint(updated_at), AbiValue::Uint(answered_in_round)] => {
+                (round_id, answer, started_at, updated_at, answered_in_round)
+            }
+            _ => unreachable!("decoded the round data"),
+        };
+        let timestamp = |t: &Nat| {
+            t.0.to_u64()
+                .ok_or_else(|| format!("invalid timestamp {}", t))
+        };
+        let returns = abi::parse_type_list(DECIMALS_RETURNS).expect("invalid returns");
+        let decimals = match abi::decode(&returns, decimals)?.pop() {
+            Some(AbiValue::Uint(decimals)) => decimals.0.to_u8().expect("decoded a uint8"),
+            _ => unreachable!("decoded a uint8"),
+        };
+        let feed = PriceFeed {
+            round_id: round_id.clone(),
+            answer: answer.clone(),
+            decimals,
+            started_at: timestamp(started_at)?,
+            updated_at: timestamp(updated_at)?,
+            answered_in_round: answered_in_round.clone(),
+        };
+        if feed.updated_at == 0 {
+            return Err(format!("round {} is not complete", feed.round_id));
+        }
+        if feed.answered_in_round < feed.round_id {
+            return Err(format!(
+                "round {} carries over the answer of round {}",
+                feed.round_id, feed.answered_in_round
+            ));
+        }
+        if !feed.answer.0.is_positive() {
+            return Err(format!("invalid answer {}", feed.answer.0));
+        }
+        Ok(feed)
+    }
+
+    /// The age of the answer in seconds if it is older than `max_age_seconds`.
+    pub fn stale_age(&self, now_seconds: u64, max_age_seconds: u64) -> Option<u64> {
+        let age = now_seconds.saturating_sub(self.updated_at);
+        (age > max_age_seconds).then_some(age)
+    }
+}
+
+#[test]
+fn check_price_feed() {
+    use crate::abi::AbiType;
+    let round_data = abi::encode(
+        &abi::parse_type_list(LATEST_ROUND_DATA_RETURNS).unwrap(),
+        &[
+            AbiValue::Uint(Nat::from(110680464442257320247u128)),
+            AbiValue::Int(Int(183_017_000_000i64.into())),
+            AbiValue::Uint(Nat::from(1_700_000_000u64)),
+            AbiValue::Uint(Nat::from(1_700_000_012u64)),
+            AbiValue::Uint(Nat::from(110680464442257320247u128)),
+        ],
+    )
+    .unwrap();
+    let decimals = abi::encode(&[AbiType::Uint(8)], &[AbiValue::Uint(Nat::from(8u64))]).unwrap();
+    let feed = PriceFeed::decode(&round_data, &decimals).unwrap();
+    assert_eq!(feed.answer, Int(183_017_000_000i64.into()));
+    assert_eq!(feed.decimals, 8);
+    assert_eq!(feed.updated_at, 1_700_000_012);
+    assert_eq!(feed.stale_age(1_700_003_612, 3_600), None);
+    assert_eq!(feed.stale_age(1_700_003_613, 3_600), Some(3_601));
+
+    let valid = abi::decode(
+        &abi::parse_type_list(LATEST_ROUND_DATA_RETURNS).unwrap(),
+        &round_data,
+    )
+    .unwrap();
+    let invalid = |index: usize, value: AbiValue| {
+        let mut values = valid.clone();
+        values[index] = value;
+        abi::encode(
+            &abi::parse_type_list(LATEST_ROUND_DATA_RETURNS).unwrap(),
+            &values,
+        )
+        .unwrap()
+    };
+    let round_data = invalid(3, AbiValue::Uint(Nat::from(0u64)));
+    assert!(PriceFeed::decode(&round_data, &decimals).is_err());
+    let round_data = invalid(4, AbiValue::Uint(Nat::from(110680464442257320246u128)));
+    assert!(PriceFeed::decode(&round_data, &decimals).is_err());
+    let round_data = invalid(1, AbiValue::Int(Int(0.into())));
+    assert!(PriceFeed::decode(&round_data, &decimals).is_err());
+    assert!(PriceFeed::decode(&decimals, &decimals).is_err());
+}
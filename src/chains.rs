@@ -0,0 +1,173 @@
+//! The registry of chains providers may be registered for, with the settings the typed
+//! endpoints use for each chain.
+
+use crate::eth::from_hex;
+use crate::json_rpc::method_matches;
+use crate::multicall;
+use candid::{CandidType, Deserialize};
+
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_SYMBOL_LENGTH: usize = 16;
+const MAX_SUPPORTED_METHODS: usize = 32;
+const MAX_METHOD_LENGTH: usize = 64;
+// The largest response an HTTPS outcall may return.
+const MAX_RESPONSE_BYTES: u64 = 2_000_000;
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct NativeCurrency {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Chain {
+    pub chain_id: u64,
+    pub name: String,
+    // Informs clients, the canister does not use it.
+    pub native_currency: NativeCurrency,
+    // The default poll interval of the background tasks.
+    pub block_time_seconds: u64,
+    // Number of blocks on top of a block before it cannot be reorganized anymore, the default
+    // number of confirmations of the background tasks.
+    pub finality_depth: u64,
+    // Used by the typed endpoints if the caller does not specify max_response_bytes.
+    pub default_max_response_bytes: u64,
+    // If set, only methods matching one of these patterns, as in `MethodPolicy`, are sent to
+    // the providers of the chain.
+    pub supported_methods: Option<Vec<String>>,
+    // Defaults to the address Multicall3 is deployed at on most chains.
+    pub multicall_address: Option<String>,
+}
+
+impl Chain {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() || self.name.len() > MAX_NAME_LENGTH {
+            return Err(format!(
+                "name must have between 1 and {} characters",
+                MAX_NAME_LENGTH
+            ));
+        }
+        let currency = &self.native_currency;
+        if currency.name.is_empty() || currency.name.len() > MAX_NAME_LENGTH {
+            return Err(format!(
+                "native currency name must have between 1 and {} characters",
+                MAX_NAME_LENGTH
+            ));
+        }
+        if currency.symbol.is_empty() || currency.symbol.len() > MAX_SYMBOL_LENGTH {
+            return Err(format!(
+                "native currency symbol must have between 1 and {} characters",
+                MAX_SYMBOL_LENGTH
+            ));
+        }
+        if self.block_time_seconds == 0 {
+            return Err("block_time_seconds must be positive".to_string());
+        }
+        if self.default_max_response_bytes == 0
+            || self.default_max_response_bytes > MAX_RESPONSE_BYTES
+        {
+            return Err(format!(
+                "default_max_response_bytes must be between 1 and {}",
+                MAX_RESPONSE_BYTES
+            ));
+        }
+        if let Some(methods) = &self.supported_methods {
+            if methods.len() > MAX_SUPPORTED_METHODS {
+                return Err(format!(
+                    "at most {} supported methods are allowed",
+                    MAX_SUPPORTED_METHODS
+                ));
+            }
+            if let Some(method) = methods
+                .iter()
+                .find(|method| method.is_empty() || method.len() > MAX_METHOD_LENGTH)
+            {
+                return Err(format!("invalid method pattern {:?}", method));
+            }
+        }
+        if let Some(address) = &self.multicall_address {
+            match from_hex(address) {
+                Ok(bytes) if bytes.len() == 20 => (),
+                _ => return Err(format!("invalid multicall_address {}", address)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn supports(&self, method: &str) -> bool {
+        self.supported_methods.as_ref().map_or(true, |methods| {
+            methods
+                .iter()
+                .any(|pattern| method_matches(pattern, method))
+        })
+    }
+
+    pub fn multicall_address(&self) -> [u8; 20] {
+        self.multicall_address
+            .as_ref()
+            .and_then(|address| from_hex(address).ok())
+            .and_then(|address| address.try_into().ok())
+            .unwrap_or(multicall::DEFAULT_ADDRESS)
+    }
+}
+
+#[cfg(test)]
+pub fn ethereum() -> Chain {
+    Chain {
+        chain_id: 1,
+        name: "Ethereum".to_string(),
+        native_currency: NativeCurrency {
+            name: "Ether".to_string(),
+            symbol: "ETH".to_string(),
+            decimals: 18,
+        },
+        block_time_seconds: 12,
+        finality_depth: 64,
+        default_max_response_bytes: 20_000,
+        supported_methods: Some(vec!["eth_*".to_string(), "net_version".to_string()]),
+        multicall_address: None,
+    }
+}
+
+#[test]
+fn check_chain() {
+    let chain = ethereum();
+    assert!(chain.validate().is_ok());
+    assert!(chain.supports("eth_call"));
+    assert!(chain.supports("net_version"));
+    assert!(!chain.supports("trace_block"));
+    assert_eq!(chain.multicall_address(), multicall::DEFAULT_ADDRESS);
+    let chain = Chain {
+        multicall_address: Some(format!("0x{}", "ab".repeat(20))),
+        supported_methods: None,
+        ..chain
+    };
+    assert!(chain.validate().is_ok());
+    assert!(chain.supports("trace_block"));
+    assert_eq!(chain.multicall_address(), [0xab; 20]);
+    assert!(Chain {
+        multicall_address: Some("0xab".to_string()),
+        ..chain.clone()
+    }
+    .validate()
+    .is_err());
+    assert!(Chain {
+        block_time_seconds: 0,
+        ..chain.clone()
+    }
+    .validate()
+    .is_err());
+    assert!(Chain {
+        default_max_response_bytes: 0,
+        ..chain.clone()
+    }
+    .validate()
+    .is_err());
+    assert!(Chain {
+        name: String::new(),
+        ..chain
+    }
+    .validate()
+    .is_err());
+}
@@ -0,0 +1,479 @@
+//! Watching of addresses for deposits of ether and ERC-20 tokens, recorded once their blocks
+//! are confirmed and optionally delivered to a callback method of the watcher's owner.
+//!
+//! Ether deposits are transactions sending value directly to a watched address, found by
+//! fetching each block with its transactions. As a transfer to an address with code can
+//! revert, they are only recorded once the receipt of the transaction shows it succeeded.
+//! Transfers by contracts (internal transactions) are not detected.
+
+use crate::abi::{self, AbiType, AbiValue};
+use crate::chains::Chain;
+use crate::rpc_types::{Block, BlockTransactions, Log};
+use crate::subscriptions::{truncate_error, LogFilter, MAX_BLOCK_RANGE};
+use candid::{CandidType, Deserialize, Nat, Principal};
+use num_traits::ToPrimitive;
+
+/// The topic of the ERC-20 `Transfer(address,address,uint256)` event.
+pub const TRANSFER_TOPIC: &str =
+    "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+// Watched addresses are matched as topic values of the logs.
+const MAX_ADDRESSES: usize = 8;
+const MAX_CALLBACK_METHOD_LENGTH: usize = 64;
+// Blocks fetched with their transactions in a single poll for ether deposits.
+pub const MAX_NATIVE_BLOCK_RANGE: u64 = 10;
+// Consecutive failed polls or deliveries before a watcher is paused.
+pub const MAX_FAILED_ATTEMPTS: u32 = 8;
+// Time for the owner to resume a paused watcher before it is removed and its balance refunded.
+const PAUSED_RETENTION_SECONDS: u64 = 7 * 24 * 3_600;
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct WatchDeposits {
+    pub chain_id: u64,
+    pub addresses: Vec<String>,
+    // ERC-20 token contracts whose transfers to the addresses are deposits.
+    pub tokens: Vec<String>,
+    // Whether ether sent to the addresses is a deposit.
+    pub native: bool,
+    // Number of blocks on top of a block before its deposits are recorded. Defaults to the
+    // finality depth of the chain.
+    pub confirmations: Option<u64>,
+    // Defaults to the block time of the chain.
+    pub poll_interval_seconds: Option<u64>,
+    // Defaults to the first block not yet confirmed when the watcher is first polled.
+    pub from_block: Option<u64>,
+    // Method of the calling canister taking a `DepositBatch`.
+    pub callback_method: Option<String>,
+    pub max_response_bytes: Option<u64>,
+}
+
+impl WatchDeposits {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.addresses.is_empty() || self.addresses.len() > MAX_ADDRESSES {
+            return Err(format!(
+                "addresses must have between 1 and {} addresses",
+                MAX_ADDRESSES
+            ));
+        }
+        if self.tokens.is_empty() && !self.native {
+            return Err("either tokens or native must be set".to_string());
+        }
+        for address in &self.addresses {
+            match crate::eth::from_hex(address) {
+                Ok(bytes) if bytes.len() == 20 => (),
+                _ => return Err(format!("invalid address {}", address)),
+            }
+        }
+        // Checks the tokens.
+        self.log_filter().validate()?;
+        if let Some(callback_method) = &self.callback_method {
+            if callback_method.is_empty() || callback_method.len() > MAX_CALLBACK_METHOD_LENGTH {
+                return Err(format!(
+                    "callback_method must have between 1 and {} characters",
+                    MAX_CALLBACK_METHOD_LENGTH
+                ));
+            }
+        }
+        if self.poll_interval_seconds == Some(0) {
+            return Err("poll_interval_seconds must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    fn log_filter(&self) -> LogFilter {
+        log_filter(&self.addresses, &self.tokens)
+    }
+}
+
+// Transfers of the tokens to the addresses.
+fn log_filter(addresses: &[String], tokens: &[String]) -> LogFilter {
+    let recipients = addresses
+        .iter()
+        .map(|address| {
+            let digits = address.strip_prefix("0x").unwrap_or(address);
+            format!("0x{:0>64}", digits.to_ascii_lowercase())
+        })
+        .collect();
+    LogFilter {
+        addresses: tokens.to_vec(),
+        topics: vec![
+            Some(vec![TRANSFER_TOPIC.to_string()]),
+            None,
+            Some(recipients),
+        ],
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum WatcherStatus {
+    Active,
+    Paused(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct DepositWatcher {
+    pub watcher_id: u64,
+    pub owner: Principal,
+    pub chain_id: u64,
+    // Lowercase hex.
+    pub addresses: Vec<String>,
+    pub tokens: Vec<String>,
+    pub native: bool,
+    pub confirmations: u64,
+    pub poll_interval_seconds: u64,
+    pub callback_method: Option<String>,
+    pub max_response_bytes: Option<u64>,
+    // The first block not scanned yet.
+    pub next_block: Option<u64>,
+    // Prepaid cycles for the requests and deliveries.
+    pub balance: u128,
+    pub status: WatcherStatus,
+    pub failed_attempts: u32,
+    // A paused watcher is removed at this time unless it is resumed.
+    pub next_poll_at_ns: u64,
+}
+
+impl DepositWatcher {
+    /// A watcher of a chain with the given settings, see `Chain`, which provide the defaults.
+    pub fn new(
+        watcher_id: u64,
+        owner: Principal,
+        request: WatchDeposits,
+        chain: &Chain,
+        balance: u128,
+    ) -> Self {
+        let lowercase = |addresses: Vec<String>| {
+            addresses
+                .into_iter()
+                .map(|address| address.to_ascii_lowercase())
+                .collect()
+        };
+        DepositWatcher {
+            watcher_id,
+            owner,
+            chain_id: request.chain_id,
+            addresses: lowercase(request.addresses),
+            tokens: lowercase(request.tokens),
+            native: request.native,
+            confirmations: request.confirmations.unwrap_or(chain.finality_depth),
+            poll_interval_seconds: request
+                .poll_interval_seconds
+                .unwrap_or(chain.block_time_seconds),
+            callback_method: request.callback_method,
+            max_response_bytes: request.max_response_bytes,
+            next_block: request.from_block,
+            balance,
+            status: WatcherStatus::Active,
+            failed_attempts: 0,
+            next_poll_at_ns: 0,
+        }
+    }
+
+    pub fn log_filter(&self) -> LogFilter {
+        log_filter(&self.addresses, &self.tokens)
+    }
+
+    /// The confirmed blocks to be scanned next, given the latest block number, or `None` if
+    /// there are none yet.
+    pub fn block_range(&self, latest_block: u64) -> Option<(u64, u64)> {
+        let confirmed = latest_block.checked_sub(self.confirmations)?;
+        let from_block = self.next_block?;
+        if from_block > confirmed {
+            return None;
+        }
+        let max_range = if self.native {
+            MAX_NATIVE_BLOCK_RANGE
+        } else {
+            MAX_BLOCK_RANGE
+        };
+        Some((
+            from_block,
+            confirmed.min(from_block.saturating_add(max_range - 1)),
+        ))
+    }
+
+    /// Starts scanning after the confirmed blocks unless the watcher has a start block.
+    pub fn init_cursor(&mut self, latest_block: u64) {
+        if self.next_block.is_none() {
+            self.next_block = Some((latest_block + 1).saturating_sub(self.confirmations));
+        }
+    }
+
+    /// Records a failed poll or delivery, backing off exponentially before the next attempt.
+    pub fn attempt_failed(&mut self, now_ns: u64, error: String) {
+        self.failed_attempts += 1;
+        if self.failed_attempts >= MAX_FAILED_ATTEMPTS {
+            self.pause(now_ns, error);
+            return;
+        }
+        let delay_seconds = self.poll_interval_seconds << self.failed_attempts.min(6);
+        self.next_poll_at_ns = now_ns.saturating_add(delay_seconds.saturating_mul(1_000_000_000));
+    }
+
+    /// Pauses the watcher with the truncated error, scheduling its removal.
+    pub fn pause(&mut self, now_ns: u64, error: String) {
+        self.status = WatcherStatus::Paused(truncate_error(error));
+        self.next_poll_at_ns =
+            now_ns.saturating_add(PAUSED_RETENTION_SECONDS.saturating_mul(1_000_000_000));
+    }
+
+    pub fn scanned(&mut self, now_ns: u64, to_block: u64) {
+        self.next_block = Some(to_block + 1);
+        self.failed_attempts = 0;
+        self.schedule_next_poll(now_ns);
+    }
+
+    pub fn schedule_next_poll(&mut self, now_ns: u64) {
+        self.next_poll_at_ns =
+            now_ns.saturating_add(self.poll_interval_seconds.saturating_mul(1_000_000_000));
+    }
+
+    /// The ether deposits of a block fetched with its transactions, which are only recorded
+    /// once their transactions are known to have succeeded.
+    pub fn native_deposits(&self, block: &Block) -> Result<Vec<Deposit>, String> {
+        let BlockTransactions::Full(transactions) = &block.transactions else {
+            return Err("block without transactions".to_string());
+        };
+        let block_number = to_u64(&block.number)?;
+        transactions
+            .iter()
+            .filter(|tx| tx.value != 0u64)
+            .filter(|tx| matches!(&tx.to, Some(to) if self.addresses.contains(to)))
+            .map(|tx| {
+                let transaction_index = tx
+                    .transaction_index
+                    .as_ref()
+                    .ok_or("transaction without index")?;
+                Ok(Deposit {
+                    watcher_id: self.watcher_id,
+                    token: None,
+                    from: tx.from.clone(),
+                    to: tx.to.clone().expect("filtered by recipient"),
+                    amount: tx.value.clone(),
+                    transaction_hash: tx.hash.clone(),
+                    block_number,
+                    transaction_index: to_u64(transaction_index)?,
+                    log_index: None,
+                })
+            })
+            .collect()
+    }
+
+    /// The token deposit of a log matching `log_filter`, `None` if the log was removed or is
+    /// not a deposit of the watcher.
+    pub fn token_deposit(&self, log: &Log) -> Result<Option<Deposit>, String> {
+        if log.removed || !self.tokens.contains(&log.address) {
+            return Ok(None);
+        }
+        let [topic, from, to] = log.topics.as_slice() else {
+            return Ok(None);
+        };
+        if topic != TRANSFER_TOPIC {
+            return Ok(None);
+        }
+        let address = |topic: &str| format!("0x{}", &topic[topic.len().saturating_sub(40)..]);
+        let to = address(to);
+        if !self.addresses.contains(&to) {
+            return Ok(None);
+        }
+        let data = crate::eth::from_hex(&log.data)?;
+        let amount = match abi::decode(&[AbiType::Uint(256)], &data)?.pop() {
+            Some(AbiValue::Uint(amount)) => amount,
+            _ => unreachable!("decoded a uint256"),
+        };
+        let required = |value: &Option<Nat>, name: &str| {
+            value
+                .as_ref()
+                .ok_or_else(|| format!("log without {}", name))
+                .and_then(to_u64)
+        };
+        Ok(Some(Deposit {
+            watcher_id: self.watcher_id,
+            token: Some(log.address.clone()),
+            from: address(from),
+            to,
+            amount,
+            transaction_hash: log
+                .transaction_hash
+                .clone()
+                .ok_or("log without transaction hash")?,
+            block_number: required(&log.block_number, "block number")?,
+            transaction_index: required(&log.transaction_index, "transaction index")?,
+            log_index: Some(required(&log.log_index, "log index")?),
+        }))
+    }
+}
+
+fn to_u64(n: &Nat) -> Result<u64, String> {
+    n.0.to_u64().ok_or_else(|| format!("{} out of range", n))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Deposit {
+    pub watcher_id: u64,
+    // The token contract, `None` for ether.
+    pub token: Option<String>,
+    pub from: String,
+    pub to: String,
+    pub amount: Nat,
+    pub transaction_hash: String,
+    pub block_number: u64,
+    pub transaction_index: u64,
+    // The index of the `Transfer` log in the block, `None` for ether.
+    pub log_index: Option<u64>,
+}
+
+impl Deposit {
+    /// Watcher id, block number, transaction index and position in the transaction, which
+    /// identify a deposit and order the deposits of a watcher. A token transfer is identified
+    /// by its log index, which is unique within the block.
+    pub fn key(&self) -> (u64, u64, u64, u64) {
+        (
+            self.watcher_id,
+            self.block_number,
+            self.transaction_index,
+            self.log_index.map_or(0, |log_index| log_index + 1),
+        )
+    }
+}
+
+/// Argument of the callback method of a watcher's owner.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct DepositBatch {
+    pub watcher_id: u64,
+    pub chain_id: u64,
+    pub from_block: u64,
+    pub to_block: u64,
+    pub deposits: Vec<Deposit>,
+}
+
+#[cfg(test)]
+fn watcher(native: bool) -> DepositWatcher {
+    DepositWatcher::new(
+        1,
+        Principal::anonymous(),
+        WatchDeposits {
+            chain_id: 1,
+            addresses: vec![format!("0x{}", "AB".repeat(20))],
+            tokens: vec![format!("0x{}", "11".repeat(20))],
+            native,
+            confirmations: Some(12),
+            poll_interval_seconds: Some(10),
+            from_block: None,
+            callback_method: None,
+            max_response_bytes: None,
+        },
+        &crate::chains::ethereum(),
+        0,
+    )
+}
+
+#[test]
+fn check_watch_deposits() {
+    assert_eq!(
+        crate::eth::to_hex(&crate::eth::keccak256(b"Transfer(address,address,uint256)")),
+        TRANSFER_TOPIC
+    );
+    let request = WatchDeposits {
+        chain_id: 1,
+        addresses: vec![format!("0x{}", "ab".repeat(20))],
+        tokens: vec![],
+        native: true,
+        confirmations: None,
+        poll_interval_seconds: None,
+        from_block: None,
+        callback_method: Some("on_deposits".to_string()),
+        max_response_bytes: None,
+    };
+    assert!(request.validate().is_ok());
+    let w = DepositWatcher::new(
+        1,
+        Principal::anonymous(),
+        request.clone(),
+        &crate::chains::ethereum(),
+        0,
+    );
+    assert_eq!((w.confirmations, w.poll_interval_seconds), (64, 12));
+    assert!(WatchDeposits {
+        poll_interval_seconds: Some(0),
+        ..request.clone()
+    }
+    .validate()
+    .is_err());
+    assert!(WatchDeposits {
+        native: false,
+        ..request.clone()
+    }
+    .validate()
+    .is_err());
+    assert!(WatchDeposits {
+        addresses: vec!["0xab".to_string()],
+        ..request.clone()
+    }
+    .validate()
+    .is_err());
+    assert!(WatchDeposits {
+        callback_method: Some(String::new()),
+        ..request
+    }
+    .validate()
+    .is_err());
+
+    let mut w = watcher(true);
+    assert_eq!(w.addresses[0], format!("0x{}", "ab".repeat(20)));
+    assert_eq!(
+        w.log_filter().topics[2],
+        Some(vec![format!("0x{}{}", "00".repeat(12), "ab".repeat(20))])
+    );
+    assert_eq!(w.block_range(100), None);
+    w.init_cursor(100);
+    assert_eq!(w.block_range(120), Some((89, 98)));
+    w.native = false;
+    assert_eq!(w.block_range(120), Some((89, 108)));
+    w.scanned(0, 108);
+    assert_eq!(w.next_block, Some(109));
+    for _ in 0..MAX_FAILED_ATTEMPTS {
+        w.attempt_failed(0, "rejected".to_string());
+    }
+    assert_eq!(w.status, WatcherStatus::Paused("rejected".to_string()));
+    assert_eq!(w.next_poll_at_ns, PAUSED_RETENTION_SECONDS * 1_000_000_000);
+    w.pause(0, "x".repeat(10_000));
+    assert_eq!(
+        w.status,
+        WatcherStatus::Paused("x".repeat(crate::subscriptions::MAX_ERROR_LENGTH))
+    );
+}
+
+#[test]
+fn check_token_deposit() {
+    let w = watcher(false);
+    let amount = abi::encode(&[AbiType::Uint(256)], &[AbiValue::Uint(Nat::from(5u64))]).unwrap();
+    let mut log = Log {
+        address: format!("0x{}", "11".repeat(20)),
+        topics: vec![
+            TRANSFER_TOPIC.to_string(),
+            format!("0x{}{}", "00".repeat(12), "22".repeat(20)),
+            format!("0x{}{}", "00".repeat(12), "ab".repeat(20)),
+        ],
+        data: crate::eth::to_hex(&amount),
+        block_number: Some(Nat::from(100u64)),
+        block_hash: None,
+        transaction_hash: Some(format!("0x{}", "33".repeat(32))),
+        transaction_index: Some(Nat::from(2u64)),
+        log_index: Some(Nat::from(7u64)),
+        removed: false,
+    };
+    let deposit = w.token_deposit(&log).unwrap().unwrap();
+    assert_eq!(deposit.from, format!("0x{}", "22".repeat(20)));
+    assert_eq!(deposit.to, w.addresses[0]);
+    assert_eq!(deposit.amount, Nat::from(5u64));
+    assert_eq!(deposit.key(), (1, 100, 2, 8));
+
+    log.removed = true;
+    assert_eq!(w.token_deposit(&log).unwrap(), None);
+    log.removed = false;
+    log.topics[2] = format!("0x{}{}", "00".repeat(12), "cd".repeat(20));
+    assert_eq!(w.token_deposit(&log).unwrap(), None);
+    log.topics[2] = format!("0x{}{}", "00".repeat(12), "ab".repeat(20));
+    log.log_index = None;
+    assert!(w.token_deposit(&log).is_err());
+}
@@ -0,0 +1,154 @@
+//! Ethereum accounts of callers, derived from the threshold ECDSA key of the canister.
+//!
+//! The management canister signs with a key derived for each caller and returns signatures
+//! without the recovery id Ethereum needs, which is found by recovering the public key.
+
+use crate::eth::keccak256;
+use crate::transaction::{Eip1559Transaction, TransactionSignature};
+use candid::Principal;
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+
+/// Derivation path of the key of a caller.
+pub fn derivation_path(caller: &Principal) -> Vec<Vec<u8>> {
+    vec![caller.as_slice().to_vec()]
+}
+
+/// The Ethereum address of a SEC1 encoded public key.
+pub fn eth_address(public_key: &[u8]) -> Result<[u8; 20], String> {
+    let key = VerifyingKey::from_sec1_bytes(public_key)
+        .map_err(|e| format!("invalid public key: {}", e))?;
+    let point = key.to_encoded_point(false);
+    // Skips the SEC1 tag of the uncompressed point.
+    let hash = keccak256(&point.as_bytes()[1..]);
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&hash[12..]);
+    Ok(address)
+}
+
+/// Encodes a transaction with a signature of its signing hash by the given public key.
+pub fn signed_transaction(
+    tx: &Eip1559Transaction,
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<Vec<u8>, String> {
+    let signature = recoverable_signature(&tx.signing_hash(), signature, public_key)?;
+    Ok(tx.encode(Some(&signature)))
+}
+
+/// Adds the recovery id to a signature of a hash by the given public key.
+pub fn recoverable_signature(
+    hash: &[u8; 32],
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<TransactionSignature, String> {
+    let signature =
+        Signature::from_slice(signature).map_err(|e| format!("invalid signature: {}", e))?;
+    // Ethereum only accepts signatures with a low s (EIP-2).
+    let signature = signature.normalize_s().unwrap_or(signature);
+    let key = VerifyingKey::from_sec1_bytes(public_key)
+        .map_err(|e| format!("invalid public key: {}", e))?;
+    let recovery_id = [0, 1]
+        .into_iter()
+        .filter_map(RecoveryId::from_byte)
+        .find(|id| {
+            VerifyingKey::recover_from_prehash(hash, &signature, *id).map_or(false, |k| k == key)
+        })
+        .ok_or_else(|| "signature does not match the public key".to_string())?;
+    let (r, s) = signature.split_bytes();
+    let mut signature = TransactionSignature {
+        y_parity: recovery_id.is_y_odd(),
+        r: [0; 32],
+        s: [0; 32],
+    };
+    signature.r.copy_from_slice(&r);
+    signature.s.copy_from_slice(&s);
+    Ok(signature)
+}
+
+/// The address that signed a hash, given the `r || s` signature and its y parity.
+pub fn recover_address(
+    hash: &[u8; 32],
+    signature: &[u8; 64],
+    y_parity: bool,
+) -> Result<[u8; 20], String> {
+    let signature =
+        Signature::from_slice(signature).map_err(|e| format!("invalid signature: {}", e))?;
+    // Signatures predating EIP-2 may have a high s, whose negation flips the parity.
+    let (signature, y_parity) = match signature.normalize_s() {
+        Some(normalized) => (normalized, !y_parity),
+        None => (signature, y_parity),
+    };
+    let key =
+        VerifyingKey::recover_from_prehash(hash, &signature, RecoveryId::new(y_parity, false))
+            .map_err(|e| format!("invalid signature: {}", e))?;
+    eth_address(key.to_encoded_point(true).as_bytes())
+}
+
+#[cfg(test)]
+fn sender(raw: &[u8]) -> [u8; 20] {
+    use crate::rlp;
+    let item = rlp::decode(&raw[1..]).unwrap();
+    let fields = item.as_list().unwrap();
+    let unsigned = rlp::encode(&rlp::Rlp::List(fields[..9].to_vec()));
+    let hash = keccak256(&[&[0x02], unsigned.as_slice()].concat());
+    let mut signature = [0u8; 64];
+    for (field, offset) in [(&fields[10], 0), (&fields[11], 32)] {
+        let bytes = field.as_bytes().unwrap();
+        signature[offset + 32 - bytes.len()..offset + 32].copy_from_slice(bytes);
+    }
+    let signature = Signature::from_slice(&signature).unwrap();
+    assert!(signature.normalize_s().is_none(), "high s");
+    let recovery_id = RecoveryId::from_byte(fields[9].as_u64().unwrap() as u8).unwrap();
+    let key = VerifyingKey::recover_from_prehash(&hash, &signature, recovery_id).unwrap();
+    eth_address(key.to_encoded_point(true).as_bytes()).unwrap()
+}
+
+#[test]
+fn check_signed_transaction() {
+    use candid::Nat;
+    use k256::ecdsa::SigningKey;
+    // A local stand-in for the threshold key: the private key of the EIP-155 example.
+    let key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+    let public_key = key
+        .verifying_key()
+        .to_encoded_point(true)
+        .as_bytes()
+        .to_vec();
+    let address = eth_address(&public_key).unwrap();
+    assert_eq!(
+        crate::eth::to_hex(&address),
+        "0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"
+    );
+    let tx = Eip1559Transaction {
+        chain_id: 5,
+        nonce: Nat::from(9u64),
+        max_priority_fee_per_gas: Nat::from(2_000_000_000u64),
+        max_fee_per_gas: Nat::from(40_000_000_000u64),
+        gas: Nat::from(21_000u64),
+        to: Some([0x35; 20]),
+        value: Nat::from(1u64),
+        data: vec![0xab],
+        access_list: vec![],
+    };
+    let (signature, _) = key.sign_prehash_recoverable(&tx.signing_hash()).unwrap();
+    let raw = signed_transaction(&tx, &signature.to_bytes(), &public_key).unwrap();
+    assert_eq!(sender(&raw), address);
+    assert_eq!(
+        crate::transaction::decode_raw_transaction(&raw)
+            .unwrap()
+            .chain_id,
+        Some(5)
+    );
+
+    // Signatures with a high s are normalized.
+    let (r, s) = signature.split_scalars();
+    let high_s = Signature::from_scalars(r.to_bytes(), (-*s).to_bytes()).unwrap();
+    assert_eq!(
+        signed_transaction(&tx, &high_s.to_bytes(), &public_key).unwrap(),
+        raw
+    );
+
+    let other_key = SigningKey::from_slice(&[0x47; 32]).unwrap();
+    let other_public_key = other_key.verifying_key().to_encoded_point(true);
+    assert!(signed_transaction(&tx, &signature.to_bytes(), other_public_key.as_bytes()).is_err());
+}
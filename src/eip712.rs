@@ -0,0 +1,447 @@
+//! EIP-712 hashing of typed structured data.
+//!
+//! Messages are JSON objects as in `eth_signTypedData_v4`. Integers may be JSON numbers or
+//! decimal or `0x` prefixed hex strings, addresses and byte arrays are `0x` prefixed hex.
+
+use crate::abi::{self, AbiType, AbiValue};
+use crate::eth::{from_hex, keccak256, to_hex};
+use crate::transaction::TransactionSignature;
+use candid::{CandidType, Deserialize, Int, Nat};
+use num::{BigInt, Num};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+const DOMAIN_TYPE: &str = "EIP712Domain";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Eip712Domain {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub chain_id: Option<u64>,
+    pub verifying_contract: Option<String>,
+    pub salt: Option<String>,
+}
+
+impl Eip712Domain {
+    // The fields of the `EIP712Domain` type are the ones set, in this order.
+    fn fields(&self) -> (Vec<TypedField>, Value) {
+        let mut fields = vec![];
+        let mut values = serde_json::Map::new();
+        let mut add = |name: &str, field_type: &str, value: Value| {
+            fields.push(TypedField {
+                name: name.to_string(),
+                field_type: field_type.to_string(),
+            });
+            values.insert(name.to_string(), value);
+        };
+        if let Some(name) = &self.name {
+            add("name", "string", Value::from(name.as_str()));
+        }
+        if let Some(version) = &self.version {
+            add("version", "string", Value::from(version.as_str()));
+        }
+        if let Some(chain_id) = self.chain_id {
+            add("chainId", "uint256", Value::from(chain_id));
+        }
+        if let Some(verifying_contract) = &self.verifying_contract {
+            add(
+                "verifyingContract",
+                "address",
+                Value::from(verifying_contract.as_str()),
+            );
+        }
+        if let Some(salt) = &self.salt {
+            add("salt", "bytes32", Value::from(salt.as_str()));
+        }
+        (fields, Value::Object(values))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct TypedField {
+    pub name: String,
+    // A struct type, an atomic or dynamic Solidity type, or an array of these.
+    pub field_type: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<TypedField>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct TypedData {
+    pub domain: Eip712Domain,
+    // An `EIP712Domain` type is ignored, it is derived from the domain.
+    pub types: Vec<StructType>,
+    pub primary_type: String,
+    // JSON object with the fields of the primary type.
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct TypedDataHash {
+    pub domain_separator: String,
+    pub struct_hash: String,
+    // The hash to sign.
+    pub digest: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct TypedDataSignature {
+    pub digest: String,
+    pub r: String,
+    pub s: String,
+    // 27 or 28.
+    pub v: u8,
+    // `r`, `s` and `v` concatenated.
+    pub signature: String,
+}
+
+impl TypedDataSignature {
+    pub fn new(digest: &[u8; 32], signature: &TransactionSignature) -> Self {
+        let v = 27 + signature.y_parity as u8;
+        TypedDataSignature {
+            digest: to_hex(digest),
+            r: to_hex(&signature.r),
+            s: to_hex(&signature.s),
+            v,
+            signature: to_hex(&[&signature.r[..], &signature.s[..], &[v]].concat()),
+        }
+    }
+}
+
+impl TypedData {
+    pub fn hash(&self) -> Result<TypedDataHash, String> {
+        let (domain_separator, struct_hash) = self.hash_structs()?;
+        Ok(TypedDataHash {
+            domain_separator: to_hex(&domain_separator),
+            struct_hash: to_hex(&struct_hash),
+            digest: to_hex(&digest(&domain_separator, &struct_hash)),
+        })
+    }
+
+    /// The hash to sign.
+    pub fn digest(&self) -> Result<[u8; 32], String> {
+        let (domain_separator, struct_hash) = self.hash_structs()?;
+        Ok(digest(&domain_separator, &struct_hash))
+    }
+
+    // The hashes of the domain and the message.
+    fn hash_structs(&self) -> Result<([u8; 32], [u8; 32]), String> {
+        let mut types: BTreeMap<&str, &[TypedField]> = BTreeMap::new();
+        for struct_type in &self.types {
+            if struct_type.name == DOMAIN_TYPE {
+                continue;
+            }
+            if types
+                .insert(&struct_type.name, &struct_type.fields)
+                .is_some()
+            {
+                return Err(format!("duplicate type {}", struct_type.name));
+            }
+        }
+        let (domain_fields, domain) = self.domain.fields();
+        types.insert(DOMAIN_TYPE, &domain_fields);
+        let types = Types(types);
+        let message: Value =
+            serde_json::from_str(&self.message).map_err(|e| format!("invalid message: {}", e))?;
+        let domain_separator = types.hash_struct(DOMAIN_TYPE, &domain)?;
+        let struct_hash = types.hash_struct(&self.primary_type, &message)?;
+        Ok((domain_separator, struct_hash))
+    }
+}
+
+fn digest(domain_separator: &[u8; 32], struct_hash: &[u8; 32]) -> [u8; 32] {
+    keccak256(&[&[0x19, 0x01], &domain_separator[..], &struct_hash[..]].concat())
+}
+
+struct Types<'a>(BTreeMap<&'a str, &'a [TypedField]>);
+
+impl<'a> Types<'a> {
+    fn fields(&self, name: &str) -> Result<&'a [TypedField], String> {
+        self.0
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("unknown type {}", name))
+    }
+
+    /// The type followed by the struct types it references, sorted by name.
+    fn encode_type(&self, name: &str) -> Result<String, String> {
+        let mut referenced = BTreeSet::new();
+        self.collect_references(name, &mut referenced)?;
+        referenced.remove(name);
+        let mut encoded = String::new();
+        for name in std::iter::once(name).chain(referenced) {
+            let fields = self
+                .fields(name)?
+                .iter()
+                .map(|field| format!("{} {}", field.field_type, field.name))
+                .collect::<Vec<_>>();
+            encoded.push_str(&format!("{}({})", name, fields.join(",")));
+        }
+        Ok(encoded)
+    }
+
+    fn collect_references(
+        &self,
+        name: &'a str,
+        referenced: &mut BTreeSet<&'a str>,
+    ) -> Result<(), String> {
+        if !referenced.insert(name) {
+            return Ok(());
+        }
+        for field in self.fields(name)? {
+            let base = element_type(&field.field_type);
+            if let Some((base, _)) = self.0.get_key_value(base) {
+                self.collect_references(base, referenced)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn hash_struct(&self, name: &str, value: &Value) -> Result<[u8; 32], String> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| format!("expected a {} object", name))?;
+        let mut encoded = keccak256(self.encode_type(name)?.as_bytes()).to_vec();
+        for field in self.fields(name)? {
+            let value = object
+                .get(&field.name)
+                .ok_or_else(|| format!("missing field {} of {}", field.name, name))?;
+            let word = self
+                .encode_field(&field.field_type, value)
+                .map_err(|e| format!("{}.{}: {}", name, field.name, e))?;
+            encoded.extend(word);
+        }
+        Ok(keccak256(&encoded))
+    }
+
+    fn encode_field(&self, field_type: &str, value: &Value) -> Result<[u8; 32], String> {
+        if let Some(base) = field_type.strip_suffix(']') {
+            let open = base
+                .rfind('[')
+                .ok_or_else(|| format!("invalid type {}", field_type))?;
+            let values = value
+                .as_array()
+                .ok_or_else(|| format!("expected an array, found {}", value))?;
+            if !base[open + 1..].is_empty() && base[open + 1..].parse() != Ok(values.len()) {
+                return Err(format!(
+                    "expected {} array elements, found {}",
+                    &base[open + 1..],
+                    values.len()
+                ));
+            }
+            let mut encoded = vec![];
+            for value in values {
+                encoded.extend(self.encode_field(&base[..open], value)?);
+            }
+            return Ok(keccak256(&encoded));
+        }
+        if self.0.contains_key(field_type) {
+            return self.hash_struct(field_type, value);
+        }
+        let abi_type = AbiType::parse(field_type)?;
+        let abi_value = match &abi_type {
+            AbiType::Uint(_) => AbiValue::Uint(Nat(number(value)?
+                .to_biguint()
+                .ok_or_else(|| format!("negative uint {}", value))?)),
+            AbiType::Int(_) => AbiValue::Int(Int(number(value)?)),
+            AbiType::Bool => AbiValue::Bool(
+                value
+                    .as_bool()
+                    .ok_or_else(|| format!("expected a bool, found {}", value))?,
+            ),
+            AbiType::Address => AbiValue::Address(string(value)?.to_string()),
+            AbiType::FixedBytes(_) => AbiValue::FixedBytes(string(value)?.to_string()),
+            AbiType::Bytes => return Ok(keccak256(&from_hex(string(value)?)?)),
+            AbiType::String => return Ok(keccak256(string(value)?.as_bytes())),
+            _ => return Err(format!("invalid type {}", field_type)),
+        };
+        let mut word = [0u8; 32];
+        word.copy_from_slice(&abi::encode(&[abi_type], &[abi_value])?);
+        Ok(word)
+    }
+}
+
+// The type of the elements of an array type, or the type itself.
+fn element_type(field_type: &str) -> &str {
+    field_type
+        .find('[')
+        .map_or(field_type, |open| &field_type[..open])
+}
+
+fn string(value: &Value) -> Result<&str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("expected a string, found {}", value))
+}
+
+fn number(value: &Value) -> Result<BigInt, String> {
+    match value {
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) => Ok(BigInt::from(n)),
+            (_, Some(n)) => Ok(BigInt::from(n)),
+            _ => Err(format!("expected an integer, found {}", n)),
+        },
+        Value::String(s) => {
+            let (negative, digits) = match s.strip_prefix('-') {
+                Some(digits) => (true, digits),
+                None => (false, s.as_str()),
+            };
+            let n = match digits.strip_prefix("0x") {
+                Some(hex) => BigInt::from_str_radix(hex, 16),
+                None => BigInt::from_str_radix(digits, 10),
+            }
+            .map_err(|_| format!("invalid integer {}", s))?;
+            Ok(if negative { -n } else { n })
+        }
+        _ => Err(format!("expected an integer, found {}", value)),
+    }
+}
+
+#[cfg(test)]
+fn mail() -> TypedData {
+    let field = |name: &str, field_type: &str| TypedField {
+        name: name.to_string(),
+        field_type: field_type.to_string(),
+    };
+    TypedData {
+        domain: Eip712Domain {
+            name: Some("Ether Mail".to_string()),
+            version: Some("1".to_string()),
+            chain_id: Some(1),
+            verifying_contract: Some("0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC".to_string()),
+            salt: None,
+        },
+        types: vec![
+            StructType {
+                name: "Person".to_string(),
+                fields: vec![field("name", "string"), field("wallet", "address")],
+            },
+            StructType {
+                name: "Mail".to_string(),
+                fields: vec![
+                    field("from", "Person"),
+                    field("to", "Person"),
+                    field("contents", "string"),
+                ],
+            },
+        ],
+        primary_type: "Mail".to_string(),
+        message: serde_json::json!({
+            "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+            "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+            "contents": "Hello, Bob!",
+        })
+        .to_string(),
+    }
+}
+
+#[test]
+fn check_hash_typed_data() {
+    // The example of the EIP.
+    let typed_data = mail();
+    let types = Types(
+        typed_data
+            .types
+            .iter()
+            .map(|t| (t.name.as_str(), t.fields.as_slice()))
+            .collect(),
+    );
+    assert_eq!(
+        types.encode_type("Mail").unwrap(),
+        "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+    );
+    assert_eq!(
+        typed_data.hash().unwrap(),
+        TypedDataHash {
+            domain_separator: "0xf2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+                .to_string(),
+            struct_hash: "0xc52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+                .to_string(),
+            digest: "0xbe609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+                .to_string(),
+        }
+    );
+
+    let mut invalid = mail();
+    invalid.message = serde_json::json!({"contents": "Hello, Bob!"}).to_string();
+    assert!(invalid.hash().is_err());
+    invalid.primary_type = "Letter".to_string();
+    assert!(invalid.hash().is_err());
+}
+
+#[test]
+fn check_encode_field() {
+    let fields = [TypedField {
+        name: "amount".to_string(),
+        field_type: "uint256".to_string(),
+    }];
+    let types = Types([("Transfer", &fields[..])].into_iter().collect());
+    let word = |n: u64| abi::encode(&[AbiType::Uint(256)], &[AbiValue::Uint(Nat::from(n))]);
+    for value in [
+        serde_json::json!(255),
+        serde_json::json!("255"),
+        serde_json::json!("0xff"),
+    ] {
+        assert_eq!(
+            types.encode_field("uint256", &value).unwrap().to_vec(),
+            word(255).unwrap()
+        );
+    }
+    assert!(types
+        .encode_field("uint8", &serde_json::json!(256))
+        .is_err());
+    assert!(types
+        .encode_field("uint256", &serde_json::json!(-1))
+        .is_err());
+    assert_eq!(
+        types
+            .encode_field("int8", &serde_json::json!("-1"))
+            .unwrap(),
+        [0xff; 32]
+    );
+    let array = serde_json::json!([{"amount": 1}, {"amount": 2}]);
+    let hashes = [
+        types.hash_struct("Transfer", &array[0]).unwrap(),
+        types.hash_struct("Transfer", &array[1]).unwrap(),
+    ]
+    .concat();
+    assert_eq!(
+        types.encode_field("Transfer[]", &array).unwrap(),
+        keccak256(&hashes)
+    );
+    assert!(types.encode_field("Transfer[3]", &array).is_err());
+    assert_eq!(
+        types
+            .encode_field("bytes", &serde_json::json!("0x"))
+            .unwrap(),
+        keccak256(&[])
+    );
+}
+
+#[test]
+fn check_sign_typed_data() {
+    use k256::ecdsa::SigningKey;
+    // The key of Cow in the example of the EIP.
+    let key = SigningKey::from_slice(&keccak256(b"cow")).unwrap();
+    let public_key = key.verifying_key().to_encoded_point(true);
+    let digest = mail().digest().unwrap();
+    let (signature, _) = key.sign_prehash_recoverable(&digest).unwrap();
+    let signature =
+        crate::ecdsa::recoverable_signature(&digest, &signature.to_bytes(), public_key.as_bytes())
+            .unwrap();
+    let signature = TypedDataSignature::new(&digest, &signature);
+    assert_eq!(signature.v, 28);
+    assert_eq!(
+        signature.r,
+        "0x4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d"
+    );
+    assert_eq!(
+        signature.s,
+        "0x07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b91562"
+    );
+    assert_eq!(signature.signature.len(), 2 + 65 * 2);
+}
@@ -0,0 +1,76 @@
+//! Ethereum Name Service lookups on mainnet.
+//!
+//! Names are lowercased but not otherwise normalized (ENSIP-15), and only resolvers set in
+//! the registry are used: wildcard (ENSIP-10) and off-chain (EIP-3668) resolution are not
+//! supported.
+
+use crate::abi::AbiValue;
+use crate::eth::{keccak256, to_hex};
+
+pub const CHAIN_ID: u64 = 1;
+/// The address of the ENS registry.
+pub const REGISTRY: [u8; 20] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x2e, 0x07, 0x4e, 0xc6, 0x9a, 0x0d, 0xfb, 0x29, 0x97, 0xba,
+    0x6c, 0x7d, 0x2e, 0x1e,
+];
+pub const RESOLVER: &str = "resolver(bytes32)";
+pub const ADDR: &str = "addr(bytes32)";
+pub const NAME: &str = "name(bytes32)";
+const MAX_NAME_LENGTH: usize = 255;
+
+pub fn namehash(name: &str) -> Result<[u8; 32], String> {
+    let name = name.to_lowercase();
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(format!("names have at most {} bytes", MAX_NAME_LENGTH));
+    }
+    let mut node = [0u8; 32];
+    if name.is_empty() {
+        return Ok(node);
+    }
+    for label in name.rsplit('.') {
+        if label.is_empty() || label.chars().any(char::is_whitespace) {
+            return Err(format!("invalid name {}", name));
+        }
+        node = keccak256(&[node, keccak256(label.as_bytes())].concat());
+    }
+    Ok(node)
+}
+
+/// The name of the reverse record of an address.
+pub fn reverse_name(address: &[u8; 20]) -> String {
+    format!("{}.addr.reverse", &to_hex(address)[2..])
+}
+
+/// The address or name a resolver returned, `None` if the record is not set.
+pub fn record(value: AbiValue) -> Option<String> {
+    match value {
+        AbiValue::Address(address) if address != to_hex(&[0; 20]) => Some(address),
+        AbiValue::String(name) if !name.is_empty() => Some(name),
+        _ => None,
+    }
+}
+
+#[test]
+fn check_namehash() {
+    assert_eq!(namehash("").unwrap(), [0; 32]);
+    assert_eq!(
+        to_hex(&namehash("eth").unwrap()),
+        "0x93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae"
+    );
+    assert_eq!(
+        to_hex(&namehash("Foo.eth").unwrap()),
+        "0xde9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f"
+    );
+    assert!(namehash("foo..eth").is_err());
+    assert!(namehash(".eth").is_err());
+    assert_eq!(
+        reverse_name(&[0xab; 20]),
+        format!("{}.addr.reverse", "ab".repeat(20))
+    );
+    assert_eq!(record(AbiValue::Address(to_hex(&[0; 20]))), None);
+    assert_eq!(record(AbiValue::String(String::new())), None);
+    assert_eq!(
+        record(AbiValue::String("vitalik.eth".to_string())),
+        Some("vitalik.eth".to_string())
+    );
+}
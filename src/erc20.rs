@@ -0,0 +1,138 @@
+//! Calls of ERC-20 token contracts.
+
+use crate::abi::{self, AbiType, AbiValue, Signature};
+use candid::{CandidType, Deserialize, Nat};
+use num_traits::ToPrimitive;
+
+pub const BALANCE_OF: &str = "balanceOf(address)";
+pub const ALLOWANCE: &str = "allowance(address,address)";
+pub const NAME: &str = "name()";
+pub const SYMBOL: &str = "symbol()";
+pub const DECIMALS: &str = "decimals()";
+
+/// The optional metadata of a token, `None` if the token does not implement it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Erc20Metadata {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum Erc20Calldata {
+    Transfer {
+        to: String,
+        amount: Nat,
+    },
+    Approve {
+        spender: String,
+        amount: Nat,
+    },
+    TransferFrom {
+        from: String,
+        to: String,
+        amount: Nat,
+    },
+}
+
+impl Erc20Calldata {
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let (function, args) = match self {
+            Erc20Calldata::Transfer { to, amount } => (
+                "transfer(address,uint256)",
+                vec![
+                    AbiValue::Address(to.clone()),
+                    AbiValue::Uint(amount.clone()),
+                ],
+            ),
+            Erc20Calldata::Approve { spender, amount } => (
+                "approve(address,uint256)",
+                vec![
+                    AbiValue::Address(spender.clone()),
+                    AbiValue::Uint(amount.clone()),
+                ],
+            ),
+            Erc20Calldata::TransferFrom { from, to, amount } => (
+                "transferFrom(address,address,uint256)",
+                vec![
+                    AbiValue::Address(from.clone()),
+                    AbiValue::Address(to.clone()),
+                    AbiValue::Uint(amount.clone()),
+                ],
+            ),
+        };
+        call_data(function, &args)
+    }
+}
+
+pub fn call_data(function: &str, args: &[AbiValue]) -> Result<Vec<u8>, String> {
+    Signature::parse(function)?.encode_call(args)
+}
+
+pub fn decode_amount(output: &[u8]) -> Result<Nat, String> {
+    match abi::decode(&[AbiType::Uint(256)], output)?.pop() {
+        Some(AbiValue::Uint(amount)) => Ok(amount),
+        _ => unreachable!("decoded a uint256"),
+    }
+}
+
+/// Decodes `decimals`, which some tokens declare as `uint256`.
+pub fn decode_decimals(output: &[u8]) -> Result<u8, String> {
+    let decimals = decode_amount(output)?;
+    decimals
+        .0
+        .to_u8()
+        .ok_or_else(|| format!("invalid decimals {}", decimals))
+}
+
+/// Decodes `name` or `symbol`, which some early tokens declare as `bytes32`.
+pub fn decode_text(output: &[u8]) -> Result<String, String> {
+    if output.len() == 32 {
+        let end = output.iter().position(|b| *b == 0).unwrap_or(32);
+        return String::from_utf8(output[..end].to_vec())
+            .map_err(|_| "invalid UTF-8 string".to_string());
+    }
+    match abi::decode(&[AbiType::String], output)?.pop() {
+        Some(AbiValue::String(text)) => Ok(text),
+        _ => unreachable!("decoded a string"),
+    }
+}
+
+#[test]
+fn check_calldata() {
+    let address = format!("0x{}", "ab".repeat(20));
+    let calldata = Erc20Calldata::Transfer {
+        to: address.clone(),
+        amount: Nat::from(1_000u64),
+    }
+    .encode()
+    .unwrap();
+    assert_eq!(calldata[..4], [0xa9, 0x05, 0x9c, 0xbb]);
+    assert_eq!(calldata.len(), 4 + 2 * 32);
+    let calldata = Erc20Calldata::Approve {
+        spender: address,
+        amount: Nat::from(1u64),
+    }
+    .encode()
+    .unwrap();
+    assert_eq!(calldata[..4], [0x09, 0x5e, 0xa7, 0xb3]);
+    assert!(Erc20Calldata::Approve {
+        spender: "0xab".to_string(),
+        amount: Nat::from(1u64),
+    }
+    .encode()
+    .is_err());
+}
+
+#[test]
+fn check_decode_metadata() {
+    let encoded = abi::encode(&[AbiType::String], &[AbiValue::String("USDC".to_string())]).unwrap();
+    assert_eq!(decode_text(&encoded).unwrap(), "USDC");
+    let mut bytes32 = [0u8; 32];
+    bytes32[..3].copy_from_slice(b"MKR");
+    assert_eq!(decode_text(&bytes32).unwrap(), "MKR");
+    let encoded = abi::encode(&[AbiType::Uint(256)], &[AbiValue::Uint(Nat::from(6u64))]).unwrap();
+    assert_eq!(decode_decimals(&encoded).unwrap(), 6);
+    let encoded = abi::encode(&[AbiType::Uint(256)], &[AbiValue::Uint(Nat::from(256u64))]).unwrap();
+    assert!(decode_decimals(&encoded).is_err());
+}
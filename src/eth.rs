@@ -0,0 +1,41 @@
+//! Ethereum primitives shared by the typed endpoints.
+
+use tiny_keccak::{Hasher, Keccak};
+
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    let mut hash = [0u8; 32];
+    hasher.update(data);
+    hasher.finalize(&mut hash);
+    hash
+}
+
+/// Encodes bytes as `0x` prefixed lowercase hex.
+pub fn to_hex(data: &[u8]) -> String {
+    format!("0x{}", hex::encode(data))
+}
+
+/// Decodes `0x` prefixed hex.
+pub fn from_hex(data: &str) -> Result<Vec<u8>, String> {
+    let digits = data
+        .strip_prefix("0x")
+        .ok_or_else(|| format!("missing 0x prefix: {}", data))?;
+    hex::decode(digits).map_err(|e| format!("invalid hex {}: {}", data, e))
+}
+
+#[test]
+fn check_keccak256() {
+    assert_eq!(
+        to_hex(&keccak256(b"")),
+        "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+    );
+}
+
+#[test]
+fn check_hex() {
+    assert_eq!(to_hex(&[0x01, 0xab]), "0x01ab");
+    assert_eq!(from_hex("0x01AB").unwrap(), vec![0x01, 0xab]);
+    assert_eq!(from_hex("0x").unwrap(), Vec::<u8>::new());
+    assert!(from_hex("01ab").is_err());
+    assert!(from_hex("0x1").is_err());
+}
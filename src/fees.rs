@@ -0,0 +1,185 @@
+//! EIP-1559 and EIP-4844 fee estimation from `eth_feeHistory`.
+
+use crate::rpc_types::{parse_quantity, JsonObject};
+use candid::{CandidType, Deserialize, Nat};
+use serde_json::Value;
+
+const MAX_BLOCK_COUNT: u64 = 1024;
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct FeeEstimationConfig {
+    // Number of recent blocks to consider.
+    pub block_count: u64,
+    // Percentile of the priority fees paid within each block, from 0 to 100.
+    pub reward_percentile: u8,
+}
+
+impl Default for FeeEstimationConfig {
+    fn default() -> Self {
+        Self {
+            block_count: 20,
+            reward_percentile: 50,
+        }
+    }
+}
+
+impl FeeEstimationConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.block_count == 0 || self.block_count > MAX_BLOCK_COUNT {
+            return Err(format!(
+                "block_count must be between 1 and {}",
+                MAX_BLOCK_COUNT
+            ));
+        }
+        if self.reward_percentile > 100 {
+            return Err("reward_percentile must be between 0 and 100".to_string());
+        }
+        Ok(())
+    }
+
+    /// Params of the `eth_feeHistory` request.
+    pub fn fee_history_params(&self) -> Value {
+        serde_json::json!([
+            format!("{:#x}", self.block_count),
+            "latest",
+            [self.reward_percentile]
+        ])
+    }
+}
+
+/// Fee parameters for a transaction to be included in one of the next blocks.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct FeeEstimate {
+    // The newest block of the fee history.
+    pub block_number: Nat,
+    // Base fee of the next block.
+    pub base_fee_per_gas: Nat,
+    pub max_priority_fee_per_gas: Nat,
+    pub max_fee_per_gas: Nat,
+    // Not set for chains without blob transactions.
+    pub base_fee_per_blob_gas: Option<Nat>,
+    pub max_fee_per_blob_gas: Option<Nat>,
+}
+
+/// Estimates fees from an `eth_feeHistory` result requested with a single reward
+/// percentile.
+///
+/// The priority fee is the median of the requested percentile over the non-empty blocks.
+/// The maximum fees allow the base fees to double before the transaction is included,
+/// which takes at least six consecutive full blocks.
+pub fn fee_estimate(fee_history: &Value) -> Result<FeeEstimate, String> {
+    let fee_history = JsonObject::new(fee_history)?;
+    let base_fees = fee_history.array("baseFeePerGas")?;
+    let gas_used_ratios = fee_history.array("gasUsedRatio")?;
+    // The base fees include the one of the block following the newest block.
+    let block_count = base_fees.len().saturating_sub(1);
+    if block_count == 0 || gas_used_ratios.len() != block_count {
+        return Err("inconsistent fee history".to_string());
+    }
+    let base_fee_per_gas = parse_quantity(&base_fees[block_count])?;
+    let rewards = match fee_history.get("reward") {
+        Some(reward) => reward
+            .as_array()
+            .ok_or_else(|| "reward is not an array".to_string())?,
+        None => return Err("missing field reward".to_string()),
+    };
+    // One reward per block, each with the fee of the single requested percentile.
+    if rewards.len() != block_count
+        || rewards
+            .iter()
+            .any(|reward| reward.as_array().map_or(true, |reward| reward.len() != 1))
+    {
+        return Err("inconsistent fee history rewards".to_string());
+    }
+    let mut priority_fees = rewards
+        .iter()
+        .zip(gas_used_ratios)
+        .filter(|(_, ratio)| ratio.as_f64().map_or(false, |ratio| ratio > 0.0))
+        .map(|(reward, _)| parse_quantity(&reward[0]))
+        .collect::<Result<Vec<_>, _>>()?;
+    priority_fees.sort();
+    let max_priority_fee_per_gas = priority_fees
+        .get(priority_fees.len() / 2)
+        .cloned()
+        .unwrap_or_else(|| Nat::from(0u64));
+    let base_fee_per_blob_gas = match fee_history.get("baseFeePerBlobGas") {
+        Some(Value::Array(blob_base_fees)) if blob_base_fees.len() == block_count + 1 => {
+            Some(parse_quantity(&blob_base_fees[block_count])?)
+        }
+        Some(Value::Array(blob_base_fees)) if blob_base_fees.is_empty() => None,
+        Some(_) => return Err("invalid baseFeePerBlobGas".to_string()),
+        None => None,
+    };
+    Ok(FeeEstimate {
+        block_number: Nat(fee_history.quantity("oldestBlock")?.0 + block_count - 1u64),
+        max_fee_per_gas: Nat(&base_fee_per_gas.0 * 2u64 + &max_priority_fee_per_gas.0),
+        base_fee_per_gas,
+        max_priority_fee_per_gas,
+        max_fee_per_blob_gas: base_fee_per_blob_gas.as_ref().map(|fee| Nat(&fee.0 * 2u64)),
+        base_fee_per_blob_gas,
+    })
+}
+
+#[test]
+fn check_fee_estimate() {
+    use serde_json::json;
+    let fee_history = json!({
+        "oldestBlock": "0x100",
+        "baseFeePerGas": ["0x10", "0x12", "0x11", "0x14"],
+        "gasUsedRatio": [0.5, 0.0, 0.9],
+        "reward": [["0x3"], ["0x0"], ["0x1"]],
+        "baseFeePerBlobGas": ["0x1", "0x1", "0x1", "0x2"],
+        "blobGasUsedRatio": [0.0, 0.0, 0.5]
+    });
+    assert_eq!(
+        fee_estimate(&fee_history),
+        Ok(FeeEstimate {
+            block_number: Nat::from(0x102u64),
+            base_fee_per_gas: Nat::from(0x14u64),
+            max_priority_fee_per_gas: Nat::from(3u64),
+            max_fee_per_gas: Nat::from(0x2bu64),
+            base_fee_per_blob_gas: Some(Nat::from(2u64)),
+            max_fee_per_blob_gas: Some(Nat::from(4u64)),
+        })
+    );
+
+    let mut pre_cancun = fee_history.clone();
+    pre_cancun
+        .as_object_mut()
+        .unwrap()
+        .remove("baseFeePerBlobGas");
+    pre_cancun["gasUsedRatio"] = json!([0.0, 0.0, 0.0]);
+    let estimate = fee_estimate(&pre_cancun).unwrap();
+    assert_eq!(estimate.max_priority_fee_per_gas, Nat::from(0u64));
+    assert_eq!(estimate.max_fee_per_gas, Nat::from(0x28u64));
+    assert_eq!(estimate.base_fee_per_blob_gas, None);
+
+    let mut inconsistent = fee_history.clone();
+    inconsistent["gasUsedRatio"] = json!([0.5]);
+    assert!(fee_estimate(&inconsistent).is_err());
+    let mut inconsistent = fee_history.clone();
+    inconsistent["reward"] = json!([["0x3"], ["0x1"]]);
+    assert!(fee_estimate(&inconsistent).is_err());
+    let mut inconsistent = fee_history;
+    inconsistent["reward"] = json!([["0x3"], [], ["0x1", "0x2"]]);
+    assert!(fee_estimate(&inconsistent).is_err());
+}
+
+#[test]
+fn check_fee_estimation_config() {
+    assert!(FeeEstimationConfig::default().validate().is_ok());
+    let config = FeeEstimationConfig {
+        block_count: 0,
+        reward_percentile: 50,
+    };
+    assert!(config.validate().is_err());
+    let config = FeeEstimationConfig {
+        block_count: 10,
+        reward_percentile: 101,
+    };
+    assert!(config.validate().is_err());
+    assert_eq!(
+        FeeEstimationConfig::default().fee_history_params(),
+        serde_json::json!(["0x14", "latest", [50]])
+    );
+}
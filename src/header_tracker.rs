@@ -0,0 +1,262 @@
+//! Tracking of the recent block headers of a chain, linked by their parent hashes, and
+//! detection of chain reorganizations.
+
+use crate::rpc_types::Block;
+use candid::{CandidType, Deserialize};
+use num_traits::ToPrimitive;
+use std::collections::BTreeMap;
+
+const MAX_CAPACITY: u64 = 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct BlockHeader {
+    pub number: u64,
+    pub hash: String,
+    pub parent_hash: String,
+    pub timestamp: u64,
+}
+
+impl BlockHeader {
+    pub fn from_block(block: &Block) -> Result<Self, String> {
+        Ok(BlockHeader {
+            number: block
+                .number
+                .0
+                .to_u64()
+                .ok_or_else(|| format!("block number {} out of range", block.number))?,
+            hash: block.hash.clone(),
+            parent_hash: block.parent_hash.clone(),
+            timestamp: block
+                .timestamp
+                .0
+                .to_u64()
+                .ok_or_else(|| format!("timestamp {} out of range", block.timestamp))?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct HeaderTrackerConfig {
+    pub chain_id: u64,
+    pub poll_interval_seconds: u64,
+    // Number of recent headers to keep. Defaults to one more than the finality depth of the
+    // chain, so that reorganizations of blocks that are not final yet are detected.
+    pub capacity: Option<u64>,
+}
+
+impl HeaderTrackerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.poll_interval_seconds == 0 {
+            return Err("poll_interval_seconds must be positive".to_string());
+        }
+        if let Some(capacity) = self.capacity {
+            if capacity == 0 || capacity > MAX_CAPACITY {
+                return Err(format!("capacity must be between 1 and {}", MAX_CAPACITY));
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of headers to keep given the finality depth of the chain, if registered.
+    pub fn capacity(&self, finality_depth: Option<u64>) -> u64 {
+        self.capacity
+            .or_else(|| finality_depth.map(|depth| depth.saturating_add(1)))
+            .map_or(MAX_CAPACITY, |capacity| capacity.min(MAX_CAPACITY))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum NextStep {
+    /// The fetched headers link to the tracked chain, or there is no tracked chain yet.
+    Link,
+    /// The parent of the oldest fetched header is needed to link to the tracked chain.
+    FetchParent(String),
+    /// The fetched headers cannot be linked: start over from them.
+    Reset,
+}
+
+/// Changes to apply to the stored headers of a chain.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HeaderChainUpdate {
+    pub removed: Vec<u64>,
+    pub inserted: Vec<BlockHeader>,
+    /// Number of previously canonical blocks that are no longer part of the chain.
+    pub reorg_depth: u64,
+}
+
+/// The recent headers of the canonical chain by number.
+#[derive(Debug, Default)]
+pub struct HeaderChain(pub BTreeMap<u64, BlockHeader>);
+
+impl HeaderChain {
+    pub fn head(&self) -> Option<&BlockHeader> {
+        self.0.values().next_back()
+    }
+
+    /// Decides how to proceed with the fetched headers, newest first and each the parent of
+    /// the one before.
+    pub fn next_step(&self, fetched: &[BlockHeader], max_fetched: usize) -> NextStep {
+        let oldest = fetched.last().expect("no fetched headers");
+        let Some(lowest) = self.0.keys().next() else {
+            return NextStep::Link;
+        };
+        match oldest.number.checked_sub(1).and_then(|n| self.0.get(&n)) {
+            Some(parent) if parent.hash == oldest.parent_hash => NextStep::Link,
+            // The fetched headers fork off before the oldest tracked header.
+            _ if oldest.number <= *lowest => NextStep::Reset,
+            _ if fetched.len() >= max_fetched => NextStep::Reset,
+            _ => NextStep::FetchParent(oldest.parent_hash.clone()),
+        }
+    }
+
+    /// Replaces the headers from the oldest fetched header on by the fetched headers, or
+    /// all headers if `reset` is set, and drops the oldest headers exceeding the capacity.
+    pub fn apply(
+        &mut self,
+        fetched: Vec<BlockHeader>,
+        reset: bool,
+        capacity: u64,
+    ) -> HeaderChainUpdate {
+        let oldest = fetched.last().expect("no fetched headers").number;
+        let replaced = if reset {
+            std::mem::take(&mut self.0)
+        } else {
+            self.0.split_off(&oldest)
+        };
+        let fetched: BTreeMap<u64, BlockHeader> =
+            fetched.into_iter().map(|h| (h.number, h)).collect();
+        let reorg_depth = if reset {
+            0
+        } else {
+            replaced
+                .values()
+                .filter(|h| fetched.get(&h.number).map(|f| &f.hash) != Some(&h.hash))
+                .count() as u64
+        };
+        let mut removed: Vec<u64> = replaced
+            .keys()
+            .filter(|n| !fetched.contains_key(n))
+            .copied()
+            .collect();
+        let inserted = fetched.values().cloned().collect();
+        self.0.extend(fetched);
+        while self.0.len() as u64 > capacity {
+            if let Some((number, _)) = self.0.pop_first() {
+                removed.push(number);
+            }
+        }
+        HeaderChainUpdate {
+            removed,
+            inserted,
+            reorg_depth,
+        }
+    }
+
+    /// Whether the header is the head or one of its tracked ancestors, as returned for the
+    /// latest block by providers lagging behind.
+    pub fn contains(&self, header: &BlockHeader) -> bool {
+        self.0
+            .get(&header.number)
+            .map_or(false, |h| h.hash.eq_ignore_ascii_case(&header.hash))
+    }
+
+    pub fn contains_hash(&self, hash: &str) -> bool {
+        self.0.values().any(|h| h.hash.eq_ignore_ascii_case(hash))
+    }
+}
+
+#[cfg(test)]
+fn header(number: u64, fork: u8) -> BlockHeader {
+    let hash = |number: u64, fork: u8| format!("0x{:02x}{:062x}", fork, number);
+    BlockHeader {
+        number,
+        hash: hash(number, fork),
+        // Forks branch off from the main chain (fork 0) after block 10.
+        parent_hash: hash(number.wrapping_sub(1), if number <= 11 { 0 } else { fork }),
+        timestamp: number * 12,
+    }
+}
+
+#[test]
+fn check_header_tracker_config() {
+    let config = HeaderTrackerConfig {
+        chain_id: 1,
+        poll_interval_seconds: 12,
+        capacity: None,
+    };
+    assert!(config.validate().is_ok());
+    assert_eq!(config.capacity(Some(64)), 65);
+    assert_eq!(config.capacity(Some(u64::MAX)), MAX_CAPACITY);
+    assert_eq!(config.capacity(None), MAX_CAPACITY);
+    let config = HeaderTrackerConfig {
+        capacity: Some(16),
+        ..config
+    };
+    assert_eq!(config.capacity(Some(64)), 16);
+    assert!(HeaderTrackerConfig {
+        capacity: Some(0),
+        ..config
+    }
+    .validate()
+    .is_err());
+}
+
+#[test]
+fn check_header_chain() {
+    let mut chain = HeaderChain::default();
+    assert_eq!(chain.next_step(&[header(8, 0)], 4), NextStep::Link);
+    chain.apply(vec![header(8, 0)], false, 5);
+    assert_eq!(chain.head(), Some(&header(8, 0)));
+
+    // Catching up on a gap.
+    let mut fetched = vec![header(11, 0)];
+    assert_eq!(
+        chain.next_step(&fetched, 4),
+        NextStep::FetchParent(header(10, 0).hash)
+    );
+    fetched.push(header(10, 0));
+    assert_eq!(
+        chain.next_step(&fetched, 4),
+        NextStep::FetchParent(header(9, 0).hash)
+    );
+    fetched.push(header(9, 0));
+    assert_eq!(chain.next_step(&fetched, 4), NextStep::Link);
+    let update = chain.apply(fetched, false, 5);
+    assert_eq!(update.reorg_depth, 0);
+    assert_eq!(update.removed, Vec::<u64>::new());
+    assert_eq!(update.inserted.len(), 3);
+    chain.apply(vec![header(13, 0), header(12, 0)], false, 5);
+    assert_eq!(numbers(&chain), vec![9, 10, 11, 12, 13]);
+
+    // A provider lagging behind returns an older canonical block as the latest.
+    assert!(chain.contains(&header(13, 0)));
+    assert!(chain.contains(&header(11, 0)));
+    assert!(!chain.contains(&header(11, 1)));
+    assert!(!chain.contains(&header(14, 0)));
+
+    // A reorg replacing blocks 11 to 13 by a shorter fork.
+    assert!(chain.contains_hash(&header(12, 0).hash));
+    let update = chain.apply(vec![header(12, 1), header(11, 1)], false, 5);
+    assert_eq!(update.reorg_depth, 3);
+    assert_eq!(update.removed, vec![13]);
+    assert_eq!(chain.head(), Some(&header(12, 1)));
+    assert!(!chain.contains_hash(&header(12, 0).hash));
+    assert_eq!(numbers(&chain), vec![9, 10, 11, 12]);
+
+    // A fork from before the oldest tracked header.
+    let mut fetched = vec![header(9, 2)];
+    fetched[0].parent_hash = "0xff".to_string();
+    assert_eq!(chain.next_step(&fetched, 4), NextStep::Reset);
+    let update = chain.apply(fetched, true, 5);
+    assert_eq!(update.removed, vec![10, 11, 12]);
+    assert_eq!(numbers(&chain), vec![9]);
+
+    // Too many headers to fetch.
+    let fetched = vec![header(20, 0), header(19, 0), header(18, 0), header(17, 0)];
+    assert_eq!(chain.next_step(&fetched, 4), NextStep::Reset);
+}
+
+#[cfg(test)]
+fn numbers(chain: &HeaderChain) -> Vec<u64> {
+    chain.0.keys().copied().collect()
+}
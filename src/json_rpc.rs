@@ -0,0 +1,250 @@
+//! JSON-RPC envelope handling for payloads and responses exchanged with Ethereum API services.
+
+use candid::CandidType;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Methods rejected unless a provider's `MethodPolicy` says otherwise: they either require
+/// an unlocked account or privileged node access and never succeed on a public service, or
+/// they are expensive to serve.
+const DEFAULT_DENIED_METHODS: &[&str] = &[
+    "eth_sendTransaction",
+    "eth_sign*",
+    "personal_*",
+    "admin_*",
+    "debug_*",
+    "miner_*",
+    "engine_*",
+];
+
+/// A single request of a (possibly batched) JSON-RPC payload.
+#[derive(Debug)]
+pub struct JsonRpcRequest {
+    pub method: String,
+    pub params: Value,
+    pub id: Option<Value>,
+}
+
+/// Serializes a request as issued by the typed endpoints.
+pub fn request_payload(method: &str, params: Value) -> String {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    })
+    .to_string()
+}
+
+/// Parses and validates a JSON-RPC payload, returning its requests.
+pub fn parse_payload(payload: &str) -> Result<Vec<JsonRpcRequest>, String> {
+    match serde_json::from_str::<Value>(payload).map_err(|e| format!("invalid JSON: {}", e))? {
+        Value::Array(batch) => {
+            if batch.is_empty() {
+                return Err("empty batch".to_string());
+            }
+            batch.into_iter().map(parse_request).collect()
+        }
+        request => Ok(vec![parse_request(request)?]),
+    }
+}
+
+fn parse_request(request: Value) -> Result<JsonRpcRequest, String> {
+    let Value::Object(mut request) = request else {
+        return Err("request must be an object".to_string());
+    };
+    if request.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Err("jsonrpc must be \"2.0\"".to_string());
+    }
+    let method = match request.remove("method") {
+        Some(Value::String(method)) if !method.is_empty() => method,
+        _ => return Err("method must be a non-empty string".to_string()),
+    };
+    let params = match request.remove("params") {
+        None => Value::Array(vec![]),
+        Some(params @ (Value::Array(_) | Value::Object(_))) => params,
+        Some(_) => return Err("params must be an array or an object".to_string()),
+    };
+    let id = match request.remove("id") {
+        None => None,
+        Some(id @ (Value::Null | Value::Number(_) | Value::String(_))) => Some(id),
+        Some(_) => return Err("id must be a string, a number or null".to_string()),
+    };
+    Ok(JsonRpcRequest { method, params, id })
+}
+
+/// The JSON-RPC methods a provider may be used for. A pattern matches a method exactly or,
+/// if it ends in `*`, by prefix.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct MethodPolicy {
+    /// If set, only methods matching one of these patterns are allowed.
+    pub allowed_methods: Option<Vec<String>>,
+    /// Methods matching any of these patterns are rejected.
+    pub denied_methods: Vec<String>,
+}
+
+impl Default for MethodPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_methods: None,
+            denied_methods: DEFAULT_DENIED_METHODS
+                .iter()
+                .map(|m| m.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl MethodPolicy {
+    pub fn allows(&self, method: &str) -> bool {
+        self.allowed_methods.as_ref().map_or(true, |allowed| {
+            allowed
+                .iter()
+                .any(|pattern| method_matches(pattern, method))
+        }) && !self
+            .denied_methods
+            .iter()
+            .any(|pattern| method_matches(pattern, method))
+    }
+}
+
+pub fn method_matches(pattern: &str, method: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => method.starts_with(prefix),
+        None => pattern == method,
+    }
+}
+
+/// The `error` member of a JSON-RPC response object.
+#[derive(Debug, Deserialize)]
+pub struct JsonRpcErrorObject {
+    pub code: i64,
+    pub message: String,
+    #[serde(default)]
+    pub data: Option<Value>,
+}
+
+impl JsonRpcErrorObject {
+    /// The `data` member rendered as text: strings (e.g. revert data) are returned as is,
+    /// anything else as JSON.
+    pub fn data_text(&self) -> Option<String> {
+        self.data.as_ref().and_then(|data| match data {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            other => Some(other.to_string()),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonRpcResponseEnvelope {
+    #[serde(default)]
+    error: Option<JsonRpcErrorObject>,
+}
+
+/// Returns the error carried by a JSON-RPC response body, if any.
+///
+/// Batch responses and bodies that are not JSON-RPC responses are left to the caller.
+pub fn response_error(body: &[u8]) -> Option<JsonRpcErrorObject> {
+    serde_json::from_slice::<JsonRpcResponseEnvelope>(body)
+        .ok()?
+        .error
+}
+
+#[test]
+fn check_response_error() {
+    let error = response_error(
+        br#"{"jsonrpc":"2.0","id":1,"error":{"code":3,"message":"execution reverted","data":"0x08c379a0"}}"#,
+    )
+    .unwrap();
+    assert_eq!(error.code, 3);
+    assert_eq!(error.message, "execution reverted");
+    assert_eq!(error.data_text().as_deref(), Some("0x08c379a0"));
+
+    let error =
+        response_error(br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32005,"message":"limit exceeded","data":{"see":"x"}}}"#)
+            .unwrap();
+    assert_eq!(error.data_text().as_deref(), Some(r#"{"see":"x"}"#));
+
+    assert!(response_error(br#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#).is_none());
+    assert_eq!(
+        response_result(br#"{"jsonrpc":"2.0","id":1,"result":null}"#),
+        Some(Value::Null)
+    );
+    assert_eq!(response_result(br#"{"jsonrpc":"2.0","id":1}"#), None);
+    assert!(
+        response_error(br#"[{"jsonrpc":"2.0","id":1,"error":{"code":1,"message":"x"}}]"#).is_none()
+    );
+    assert!(response_error(b"not json").is_none());
+}
+
+/// Returns the `result` member, which may be `null`, of a successful JSON-RPC response body.
+pub fn response_result(body: &[u8]) -> Option<Value> {
+    match serde_json::from_slice::<Value>(body).ok()? {
+        Value::Object(mut response) => response.remove("result"),
+        _ => None,
+    }
+}
+
+/// Serializes a JSON-RPC response carrying `result` for the request with the given `id`.
+pub fn response_body(id: Option<&Value>, result: &Value) -> Vec<u8> {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id.unwrap_or(&Value::Null),
+        "result": result,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+#[test]
+fn check_parse_payload() {
+    let requests =
+        parse_payload(r#"{"jsonrpc":"2.0","method":"eth_gasPrice","params":[],"id":1}"#).unwrap();
+    assert_eq!(requests[0].method, "eth_gasPrice");
+    assert_eq!(requests[0].id, Some(serde_json::json!(1)));
+    let requests = parse_payload(
+        r#"[{"jsonrpc":"2.0","method":"eth_chainId","id":1},{"jsonrpc":"2.0","method":"eth_blockNumber","id":"2"}]"#,
+    )
+    .unwrap();
+    assert_eq!(requests.len(), 2);
+
+    let requests = parse_payload(&request_payload(
+        "eth_getBalance",
+        serde_json::json!(["0x01", "latest"]),
+    ))
+    .unwrap();
+    assert_eq!(requests[0].method, "eth_getBalance");
+    assert_eq!(requests[0].params, serde_json::json!(["0x01", "latest"]));
+
+    assert!(parse_payload("{").is_err());
+    assert!(parse_payload("[]").is_err());
+    assert!(parse_payload(r#"{"jsonrpc":"1.0","method":"eth_chainId","id":1}"#).is_err());
+    assert!(parse_payload(r#"{"jsonrpc":"2.0","method":"","id":1}"#).is_err());
+    assert!(parse_payload(r#"{"jsonrpc":"2.0","method":"eth_chainId","params":"x"}"#).is_err());
+    assert!(parse_payload(r#"{"jsonrpc":"2.0","method":"eth_chainId","id":[1]}"#).is_err());
+}
+
+#[test]
+fn check_method_policy() {
+    let policy = MethodPolicy::default();
+    assert!(policy.allows("eth_getBalance"));
+    assert!(policy.allows("eth_sendRawTransaction"));
+    assert!(!policy.allows("eth_sendTransaction"));
+    assert!(!policy.allows("eth_signTypedData_v4"));
+    assert!(!policy.allows("personal_unlockAccount"));
+    assert!(!policy.allows("debug_traceTransaction"));
+
+    let policy = MethodPolicy {
+        allowed_methods: Some(vec![
+            "eth_*".to_string(),
+            "debug_traceTransaction".to_string(),
+        ]),
+        denied_methods: vec!["eth_getLogs".to_string()],
+    };
+    assert!(policy.allows("eth_call"));
+    assert!(policy.allows("debug_traceTransaction"));
+    assert!(!policy.allows("debug_traceCall"));
+    assert!(!policy.allows("eth_getLogs"));
+    assert!(!policy.allows("net_version"));
+}
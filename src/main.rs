@@ -1,6 +1,9 @@
 use candid::{candid_method, CandidType, Decode, Deserialize, Encode, Principal};
-use ic_canister_log::declare_log_buffer;
+use ic_canister_log::{declare_log_buffer, log};
 use ic_canister_serve::{serve_logs, serve_metrics};
+use ic_cdk::api::management_canister::ecdsa::{
+    EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
+};
 use ic_cdk::api::management_canister::http_request::{
     http_request as make_http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
     HttpResponse, TransformArgs, TransformContext,
@@ -11,24 +14,116 @@ use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemor
 #[cfg(target_arch = "wasm32")]
 use ic_stable_structures::DefaultMemoryImpl;
 use ic_stable_structures::{BoundedStorable, Cell, StableBTreeMap, Storable};
+use num_traits::ToPrimitive;
 #[macro_use]
 extern crate num_derive;
 use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::hash_set::HashSet;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 #[cfg(not(target_arch = "wasm32"))]
 use std::fs::File;
+use std::time::Duration;
+
+mod abi;
+mod cache;
+mod certified_data;
+mod chainlink;
+mod chains;
+mod deposits;
+mod ecdsa;
+mod eip712;
+mod ens;
+mod erc20;
+mod eth;
+mod fees;
+mod header_tracker;
+mod json_rpc;
+mod multicall;
+mod nonces;
+mod proof;
+mod rlp;
+mod rpc_types;
+mod subscriptions;
+mod transaction;
+mod trie;
+mod tx_tracker;
+
+use abi::{AbiValue, RevertReason};
+use cache::CacheKey;
+use certified_data::CertifiedData;
+use chainlink::PriceFeed;
+use chains::Chain;
+use deposits::{Deposit, DepositBatch, DepositWatcher, WatchDeposits, WatcherStatus};
+use eip712::{TypedData, TypedDataHash, TypedDataSignature};
+use erc20::{Erc20Calldata, Erc20Metadata};
+use fees::{FeeEstimate, FeeEstimationConfig};
+use header_tracker::{BlockHeader, HeaderChain, HeaderTrackerConfig, NextStep};
+use json_rpc::{JsonRpcRequest, MethodPolicy};
+use multicall::{CallResult, MulticallCall};
+use nonces::NonceState;
+use proof::VerifiedAccount;
+use rpc_types::{Block, BlockTag, TransactionReceipt, TransactionRequest, TransactionStatus};
+use subscriptions::{LogBatch, SubscribeLogs, Subscription, SubscriptionStatus};
+use transaction::{Eip1559Transaction, SendRawTransactionStatus};
+use tx_tracker::{Observation, TrackTransaction, TrackedTransaction, TransactionUpdate};
 
 const INGRESS_OVERHEAD_BYTES: u128 = 100;
 const INGRESS_MESSAGE_RECEIVED_COST: u128 = 1_200_000u128;
 const INGRESS_MESSAGE_BYTE_RECEIVED_COST: u128 = 2_000u128;
 const HTTP_OUTCALL_REQUEST_COST: u128 = 400_000_000u128;
 const HTTP_OUTCALL_BYTE_RECEIEVED_COST: u128 = 100_000u128;
+const RESPONSE_CACHE_HIT_COST: u128 = 10_000_000u128;
+const RESPONSE_CACHE_BYTE_COST: u128 = 2_000u128;
+const CANISTER_CALL_COST: u128 = 260_000u128;
+const CANISTER_CALL_BYTE_COST: u128 = 1_000u128;
+// Attached to `sign_with_ecdsa` calls.
+const ECDSA_SIGNATURE_COST: u128 = 10_000_000_000u128;
 
 const MINIMUM_WITHDRAWAL_CYCLES: u128 = 1_000_000_000u128;
+// Required to create a subscription, so that it can pay for more than a few requests.
+const MINIMUM_PREPAID_BALANCE_CYCLES: u128 = 10_000_000_000u128;
+
+// Threshold ECDSA key of the subnet unless set with `set_ecdsa_key_name`.
+const DEFAULT_ECDSA_KEY_NAME: &str = "key_1";
+const MAX_ECDSA_KEY_NAME_LENGTH: usize = 64;
+const MAX_ECDSA_PUBLIC_KEYS: usize = 10_000;
+
+// Maximum number of providers a raw transaction is broadcast to.
+const MAX_SEND_RAW_TRANSACTION_PROVIDERS: usize = 3;
+const SEND_RAW_TRANSACTION_MAX_RESPONSE_BYTES: u64 = 2048;
+// Used by the typed endpoints for chains migrated to the chain registry or not registered.
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 20_000;
+const FEE_ESTIMATE_CACHE_TTL_NS: u64 = 5_000_000_000;
+// Cached responses that change if their block is reorganized expire after this time.
+const REORGABLE_RESPONSE_CACHE_TTL_NS: u64 = 30_000_000_000;
+const ENS_RECORD_CACHE_TTL_NS: u64 = 300_000_000_000;
+const MAX_ENS_RECORDS: usize = 10_000;
+const DEFAULT_GAS_MULTIPLIER_PERCENT: u32 = 120;
+// Blocks fetched for their header still include the transaction hashes.
+const BLOCK_HEADER_MAX_RESPONSE_BYTES: u64 = 64_000;
+// The largest response an HTTPS outcall may return.
+const BLOCK_RECEIPTS_MAX_RESPONSE_BYTES: u64 = 2_000_000;
+// Catching up on more blocks than this restarts tracking from the latest block.
+const MAX_HEADER_FETCHES_PER_POLL: usize = 16;
+const BACKGROUND_TICK_SECONDS: u64 = 5;
+const SUBSCRIPTION_MAX_RESPONSE_BYTES: u64 = 200_000;
+const MAX_DEPOSITS_PER_PAGE: u64 = 100;
+const MAX_DEPOSIT_WATCHERS_PER_OWNER: usize = 16;
+const MAX_SUBSCRIPTIONS_PER_OWNER: usize = 16;
+const MAX_TRACKED_TRANSACTIONS_PER_OWNER: usize = 64;
 
 const STRING_STORABLE_MAX_SIZE: u32 = 100;
+const METHOD_POLICY_MAX_SIZE: u32 = 4096;
+const RESPONSE_CACHE_ENTRY_MAX_SIZE: u32 = 16384;
+const SUBSCRIPTION_MAX_SIZE: u32 = 8192;
+const TRACKED_TRANSACTION_MAX_SIZE: u32 = 4096;
+const NONCE_STATE_MAX_SIZE: u32 = 1024;
+const ERC20_METADATA_MAX_SIZE: u32 = 512;
+const DEPOSIT_WATCHER_MAX_SIZE: u32 = 4096;
+const DEPOSIT_MAX_SIZE: u32 = 512;
+const CHAIN_MAX_SIZE: u32 = 4096;
+const DEFAULT_RESPONSE_CACHE_MAX_ENTRIES: u64 = 1000;
 const WASM_PAGE_SIZE: u64 = 65536;
 
 const INITIAL_SERVICE_HOSTS_ALLOWLIST: &[&str] = &[
@@ -74,6 +169,8 @@ const FREE_RPC_ALLOWLIST: &[&str] = &[];
 const AUTHORIZED_ADMIN: &[&str] = &[];
 
 type AllowlistSet = HashSet<&'static &'static str>;
+// Time, number of agreeing providers and record by node and resolver function.
+type EnsRecords = HashMap<([u8; 32], &'static str), (u64, u32, Option<String>)>;
 
 #[allow(unused)] // Some compiler quirk causes this to be reported as unused.
 #[cfg(not(target_arch = "wasm32"))]
@@ -91,9 +188,26 @@ struct Metrics {
     json_rpc_request_cycles_charged: u128,
     json_rpc_request_cycles_refunded: u128,
     json_rpc_request_err_no_permission: u64,
+    json_rpc_request_err_invalid_payload: u64,
+    json_rpc_request_err_method_not_allowed: u64,
     json_rpc_request_err_service_url_host_not_allowed: u64,
     json_rpc_request_err_http_request_error: u64,
+    json_rpc_request_err_http_status: u64,
+    json_rpc_request_err_json_rpc_error: u64,
     json_rpc_host_requests: HashMap<String, u64>,
+    json_rpc_cache_hits: u64,
+    json_rpc_cache_misses: u64,
+    json_rpc_cache_evictions: u64,
+    header_tracker_reorgs: u64,
+    header_tracker_resets: u64,
+    header_tracker_errors: u64,
+    header_tracker_cycles_spent: u128,
+    log_subscription_deliveries: u64,
+    log_subscription_errors: u64,
+    tracked_transaction_errors: u64,
+    tracked_transaction_replacements: u64,
+    deposits_recorded: u64,
+    deposit_watcher_errors: u64,
 }
 
 #[derive(Clone, Debug, PartialEq, CandidType, FromPrimitive, Deserialize)]
@@ -104,9 +218,43 @@ enum Auth {
     FreeRpc = 4,
 }
 
+// Selects how the `transform` function canonicalizes a response so that all replicas agree.
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+enum ResponseTransform {
+    Raw,
+    SendRawTransaction,
+}
+
 #[derive(Clone, Debug, Default, CandidType, Deserialize)]
 struct Metadata {
     next_provider_id: u64,
+    // `dfx_test_key` on a local replica.
+    ecdsa_key_name: Option<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct ResponseCacheState {
+    max_entries: u64,
+    next_sequence: u64,
+}
+
+impl Default for ResponseCacheState {
+    fn default() -> Self {
+        Self {
+            max_entries: DEFAULT_RESPONSE_CACHE_MAX_ENTRIES,
+            next_sequence: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct ResponseCacheEntry {
+    // JSON encoded `result` of the response.
+    result: Vec<u8>,
+    // Sequence number of the last use, the key of the entry in RESPONSE_CACHE_LRU.
+    last_used: u64,
+    // Set if the block of the result is not final yet.
+    expires_at_ns: Option<u64>,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -115,6 +263,21 @@ struct StringStorable(String);
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
 struct PrincipalStorable(Principal);
 
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct CacheKeyStorable(CacheKey);
+
+// Chain id and block number.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct HeaderKey(u64, u64);
+
+// Chain id and address.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct AddressKey(u64, [u8; 20]);
+
+// See `Deposit::key`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone)]
+struct DepositKey(u64, u64, u64, u64);
+
 impl Storable for StringStorable {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
         // String already implements `Storable`.
@@ -146,6 +309,89 @@ impl BoundedStorable for PrincipalStorable {
     const IS_FIXED_SIZE: bool = false;
 }
 
+impl Storable for CacheKeyStorable {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::from(&self.0[..])
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Self(bytes.as_ref().try_into().expect("invalid cache key"))
+    }
+}
+
+impl BoundedStorable for CacheKeyStorable {
+    const MAX_SIZE: u32 = 32;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+impl Storable for HeaderKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned([self.0.to_be_bytes(), self.1.to_be_bytes()].concat())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let (chain_id, number) = bytes.split_at(8);
+        Self(
+            u64::from_be_bytes(chain_id.try_into().expect("invalid header key")),
+            u64::from_be_bytes(number.try_into().expect("invalid header key")),
+        )
+    }
+}
+
+impl BoundedStorable for HeaderKey {
+    const MAX_SIZE: u32 = 16;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+impl Storable for AddressKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned([&self.0.to_be_bytes()[..], &self.1[..]].concat())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let (chain_id, address) = bytes.split_at(8);
+        Self(
+            u64::from_be_bytes(chain_id.try_into().expect("invalid address key")),
+            address.try_into().expect("invalid address key"),
+        )
+    }
+}
+
+impl BoundedStorable for AddressKey {
+    const MAX_SIZE: u32 = 28;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+impl Storable for DepositKey {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(
+            [
+                self.0.to_be_bytes(),
+                self.1.to_be_bytes(),
+                self.2.to_be_bytes(),
+                self.3.to_be_bytes(),
+            ]
+            .concat(),
+        )
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        let field = |i: usize| {
+            u64::from_be_bytes(
+                bytes[i * 8..(i + 1) * 8]
+                    .try_into()
+                    .expect("invalid deposit key"),
+            )
+        };
+        Self(field(0), field(1), field(2), field(3))
+    }
+}
+
+impl BoundedStorable for DepositKey {
+    const MAX_SIZE: u32 = 32;
+    const IS_FIXED_SIZE: bool = true;
+}
+
 #[derive(Debug, CandidType)]
 struct RegisteredProvider {
     provider_id: u64,
@@ -156,6 +402,48 @@ struct RegisteredProvider {
     cycles_per_message_byte: u64,
 }
 
+#[derive(Debug, CandidType)]
+struct CertifiedProviders {
+    providers: Vec<RegisteredProvider>,
+    certificate: Option<Vec<u8>>,
+    witness: Vec<u8>,
+}
+
+#[derive(Debug, CandidType)]
+struct CertifiedResponse {
+    cache_key: Vec<u8>,
+    result: Vec<u8>,
+    certificate: Option<Vec<u8>>,
+    witness: Vec<u8>,
+}
+
+#[derive(Debug, CandidType)]
+struct ProviderSendRawTransactionStatus {
+    provider_id: u64,
+    status: Result<SendRawTransactionStatus, EthRpcError>,
+}
+
+#[derive(Debug, CandidType)]
+struct SendRawTransactionResult {
+    transaction_hash: String,
+    status: SendRawTransactionStatus,
+    provider_statuses: Vec<ProviderSendRawTransactionStatus>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+struct ContractCall {
+    to: String,
+    from: Option<String>,
+    value: Option<candid::Nat>,
+    // Solidity function signature, e.g. `balanceOf(address)`.
+    function: String,
+    args: Vec<AbiValue>,
+    // Parenthesized return types, e.g. `(uint256)`.
+    returns: String,
+    // Signatures of the custom errors the call may revert with.
+    errors: Vec<String>,
+}
+
 #[derive(Debug, CandidType, Deserialize)]
 struct RegisterProvider {
     chain_id: u64,
@@ -186,6 +474,178 @@ impl Storable for Metadata {
     }
 }
 
+impl Storable for MethodPolicy {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for MethodPolicy {
+    const MAX_SIZE: u32 = METHOD_POLICY_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for ResponseCacheState {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl Storable for FeeEstimationConfig {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl Storable for HeaderTrackerConfig {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for HeaderTrackerConfig {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for BlockHeader {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for BlockHeader {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for Subscription {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Subscription {
+    const MAX_SIZE: u32 = SUBSCRIPTION_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for TrackedTransaction {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for TrackedTransaction {
+    const MAX_SIZE: u32 = TRACKED_TRANSACTION_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for NonceState {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for NonceState {
+    const MAX_SIZE: u32 = NONCE_STATE_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for Erc20Metadata {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Erc20Metadata {
+    const MAX_SIZE: u32 = ERC20_METADATA_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for DepositWatcher {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DepositWatcher {
+    const MAX_SIZE: u32 = DEPOSIT_WATCHER_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for Deposit {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Deposit {
+    const MAX_SIZE: u32 = DEPOSIT_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for Chain {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Chain {
+    const MAX_SIZE: u32 = CHAIN_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for ResponseCacheEntry {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ResponseCacheEntry {
+    const MAX_SIZE: u32 = RESPONSE_CACHE_ENTRY_MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
 impl Storable for Provider {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
         Cow::Owned(Encode!(self).unwrap())
@@ -205,6 +665,27 @@ thread_local! {
     static METRICS: RefCell<Metrics> = RefCell::new(Metrics::default());
     static SERVICE_HOSTS_ALLOWLIST: RefCell<AllowlistSet> = RefCell::new(AllowlistSet::new());
     static AUTH_STABLE: RefCell<HashSet<Principal>> = RefCell::new(HashSet::<Principal>::new());
+    // Rebuilt from RESPONSE_CACHE and PROVIDERS on upgrade.
+    static CERTIFIED_DATA: RefCell<CertifiedData> = RefCell::new(CertifiedData::default());
+    // Expiry time and key of the expiring RESPONSE_CACHE entries, rebuilt on upgrade.
+    static RESPONSE_CACHE_EXPIRY: RefCell<BTreeSet<(u64, CacheKey)>> = RefCell::new(BTreeSet::new());
+    // Fee estimates by chain id with the time they were computed.
+    static FEE_ESTIMATES: RefCell<HashMap<u64, (u64, FeeEstimate)>> = RefCell::new(HashMap::new());
+    static ENS_RECORDS: RefCell<EnsRecords> = RefCell::new(HashMap::new());
+    static ECDSA_PUBLIC_KEYS: RefCell<HashMap<Principal, Vec<u8>>> = RefCell::new(HashMap::new());
+    // Restarted from HEADER_TRACKERS on upgrade.
+    static HEADER_TRACKER_TIMERS: RefCell<HashMap<u64, ic_cdk_timers::TimerId>> = RefCell::new(HashMap::new());
+    static HEADER_TRACKER_POLLS: RefCell<HashSet<u64>> = RefCell::new(HashSet::new());
+    static SUBSCRIPTION_POLLS: RefCell<HashSet<u64>> = RefCell::new(HashSet::new());
+    static TRANSACTION_POLLS: RefCell<HashSet<u64>> = RefCell::new(HashSet::new());
+    static DEPOSIT_WATCHER_POLLS: RefCell<HashSet<u64>> = RefCell::new(HashSet::new());
+    // Next poll time and id of the active subscriptions, rebuilt from SUBSCRIPTIONS on upgrade.
+    static SUBSCRIPTION_SCHEDULE: RefCell<BTreeSet<(u64, u64)>> = RefCell::new(BTreeSet::new());
+    // Next poll or removal time and id of the tracked transactions, rebuilt from
+    // TRACKED_TRANSACTIONS on upgrade.
+    static TRACKED_TRANSACTION_SCHEDULE: RefCell<BTreeSet<(u64, u64)>> = RefCell::new(BTreeSet::new());
+    // Next poll time and id of the deposit watchers, rebuilt from DEPOSIT_WATCHERS on upgrade.
+    static DEPOSIT_WATCHER_SCHEDULE: RefCell<BTreeSet<(u64, u64)>> = RefCell::new(BTreeSet::new());
 
     // Stable static data: this is preserved when the canister is upgraded.
     #[cfg(not(target_arch = "wasm32"))]
@@ -220,6 +701,44 @@ thread_local! {
         StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))));
     static PROVIDERS: RefCell<StableBTreeMap<u64, Provider, Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))));
+    static METHOD_POLICIES: RefCell<StableBTreeMap<u64, MethodPolicy, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))));
+    static RESPONSE_CACHE_STATE: RefCell<Cell<ResponseCacheState, Memory>> = RefCell::new(Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))),
+            <ResponseCacheState>::default()).unwrap());
+    static RESPONSE_CACHE: RefCell<StableBTreeMap<CacheKeyStorable, ResponseCacheEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))));
+    static RESPONSE_CACHE_LRU: RefCell<StableBTreeMap<u64, CacheKeyStorable, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6)))));
+    static FEE_ESTIMATION_CONFIG: RefCell<Cell<FeeEstimationConfig, Memory>> = RefCell::new(Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))),
+            <FeeEstimationConfig>::default()).unwrap());
+    static HEADER_TRACKERS: RefCell<StableBTreeMap<u64, HeaderTrackerConfig, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))));
+    static BLOCK_HEADERS: RefCell<StableBTreeMap<HeaderKey, BlockHeader, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))));
+    static FINALIZED_HEADERS: RefCell<StableBTreeMap<u64, BlockHeader, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))));
+    static SUBSCRIPTIONS: RefCell<StableBTreeMap<u64, Subscription, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))));
+    static NEXT_SUBSCRIPTION_ID: RefCell<Cell<u64, Memory>> = RefCell::new(Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))), 0).unwrap());
+    static TRACKED_TRANSACTIONS: RefCell<StableBTreeMap<u64, TrackedTransaction, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))));
+    static NEXT_TRACKING_ID: RefCell<Cell<u64, Memory>> = RefCell::new(Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))), 0).unwrap());
+    static NONCES: RefCell<StableBTreeMap<AddressKey, NonceState, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))));
+    static ERC20_METADATA: RefCell<StableBTreeMap<AddressKey, Erc20Metadata, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))));
+    static DEPOSIT_WATCHERS: RefCell<StableBTreeMap<u64, DepositWatcher, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))));
+    static NEXT_WATCHER_ID: RefCell<Cell<u64, Memory>> = RefCell::new(Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))), 0).unwrap());
+    static DEPOSITS: RefCell<StableBTreeMap<DepositKey, Deposit, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))));
+    static CHAINS: RefCell<StableBTreeMap<u64, Chain, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))));
 }
 
 #[allow(dead_code)]
@@ -231,7 +750,37 @@ enum EthRpcError {
     ServiceUrlHostMissing,
     ServiceUrlHostNotAllowed,
     ProviderNotFound,
-    HttpRequestError { code: u32, message: String },
+    ChainNotFound,
+    InvalidPayload(String),
+    MethodNotAllowed(String),
+    InvalidTransaction(String),
+    InvalidResponse(String),
+    InvalidArgument(String),
+    ExecutionReverted(RevertReason),
+    InvalidProof(String),
+    SubscriptionNotFound,
+    RefundFailed(String),
+    SigningError(String),
+    TrackedTransactionNotFound,
+    DepositWatcherNotFound,
+    InconsistentResults(String),
+    StalePrice {
+        updated_at: u64,
+        age_seconds: u64,
+    },
+    HttpRequestError {
+        code: u32,
+        message: String,
+    },
+    HttpStatus {
+        status: u16,
+        body: String,
+    },
+    JsonRpcError {
+        code: i64,
+        message: String,
+        data: Option<String>,
+    },
 }
 
 #[macro_export]
@@ -254,70 +803,2734 @@ macro_rules! inc_metric_entry {
     }};
 }
 
-#[macro_export]
-macro_rules! add_metric {
-    ($metric:ident, $value:expr) => {{
-        METRICS.with(|m| m.borrow_mut().$metric += $value);
-    }};
+#[macro_export]
+macro_rules! add_metric {
+    ($metric:ident, $value:expr) => {{
+        METRICS.with(|m| m.borrow_mut().$metric += $value);
+    }};
+}
+
+#[macro_export]
+macro_rules! get_metric {
+    ($metric:ident) => {{
+        METRICS.with(|m| m.borrow().$metric)
+    }};
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn json_rpc_request(
+    json_rpc_payload: String,
+    service_url: String,
+    max_response_bytes: u64,
+) -> Result<Vec<u8>, EthRpcError> {
+    json_rpc_request_internal(
+        json_rpc_payload,
+        service_url,
+        max_response_bytes,
+        None,
+        ResponseTransform::Raw,
+    )
+    .await
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn json_rpc_provider_request(
+    json_rpc_payload: String,
+    provider_id: u64,
+    max_response_bytes: u64,
+) -> Result<Vec<u8>, EthRpcError> {
+    let provider = PROVIDERS.with(|p| {
+        p.borrow()
+            .get(&provider_id)
+            .ok_or(EthRpcError::ProviderNotFound)
+    });
+    provider_request(
+        provider?,
+        json_rpc_payload,
+        max_response_bytes,
+        ResponseTransform::Raw,
+    )
+    .await
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn json_rpc_cached_request(
+    json_rpc_payload: String,
+    provider_id: u64,
+    max_response_bytes: u64,
+) -> Result<Vec<u8>, EthRpcError> {
+    let provider = PROVIDERS.with(|p| {
+        p.borrow()
+            .get(&provider_id)
+            .ok_or(EthRpcError::ProviderNotFound)
+    });
+    let provider = provider?;
+    let requests = check_json_rpc_request(&json_rpc_payload, Some(&provider))?;
+    let cacheable = match requests.as_slice() {
+        [request] => cache::cache_key(provider.chain_id, request).map(|key| {
+            (
+                key,
+                request.method.clone(),
+                cache::validity(&request.method, &request.params),
+                request.id.clone(),
+            )
+        }),
+        _ => None,
+    };
+    let Some((key, method, validity, id)) = cacheable else {
+        return checked_provider_request(provider, json_rpc_payload, max_response_bytes).await;
+    };
+    if let Some(result) = response_cache_get(&key) {
+        inc_metric!(json_rpc_cache_hits);
+        if !authorized(Auth::FreeRpc) {
+            charge_cycles(json_rpc_cache_hit_cycles_cost(
+                &json_rpc_payload,
+                result.len(),
+            ))?;
+        }
+        let result: serde_json::Value =
+            serde_json::from_slice(&result).expect("invalid cached result");
+        return Ok(json_rpc::response_body(id.as_ref(), &result));
+    }
+    inc_metric!(json_rpc_cache_misses);
+    let body = checked_provider_request(provider, json_rpc_payload, max_response_bytes).await?;
+    if let Some(result) = json_rpc::response_result(&body) {
+        if cache::is_cacheable_result(&method, &result) {
+            let expires_at_ns = match validity {
+                cache::Validity::Permanent => None,
+                cache::Validity::UntilReorg => {
+                    Some(ic_cdk::api::time() + REORGABLE_RESPONSE_CACHE_TTL_NS)
+                }
+            };
+            response_cache_insert(key, result.to_string().into_bytes(), expires_at_ns);
+        }
+    }
+    Ok(body)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn eth_send_raw_transaction(
+    chain_id: u64,
+    raw_signed_transaction: String,
+) -> Result<SendRawTransactionResult, EthRpcError> {
+    let raw = eth::from_hex(&raw_signed_transaction).map_err(EthRpcError::InvalidTransaction)?;
+    let transaction =
+        transaction::decode_raw_transaction(&raw).map_err(EthRpcError::InvalidTransaction)?;
+    if transaction.chain_id != Some(chain_id) {
+        return Err(EthRpcError::InvalidTransaction(format!(
+            "transaction chain id {:?} does not match {}",
+            transaction.chain_id, chain_id
+        )));
+    }
+    let providers = chain_providers(chain_id);
+    if providers.is_empty() {
+        return Err(EthRpcError::ProviderNotFound);
+    }
+    let json_rpc_payload = json_rpc::request_payload(
+        "eth_sendRawTransaction",
+        serde_json::json!([eth::to_hex(&raw)]),
+    );
+    let mut provider_statuses = vec![];
+    for provider in providers
+        .into_iter()
+        .take(MAX_SEND_RAW_TRANSACTION_PROVIDERS)
+    {
+        let provider_id = provider.provider_id;
+        let status = provider_request(
+            provider,
+            json_rpc_payload.clone(),
+            SEND_RAW_TRANSACTION_MAX_RESPONSE_BYTES,
+            ResponseTransform::SendRawTransaction,
+        )
+        .await
+        .map(|body| {
+            Decode!(&body, SendRawTransactionStatus).unwrap_or(SendRawTransactionStatus::Rejected)
+        });
+        provider_statuses.push(ProviderSendRawTransactionStatus {
+            provider_id,
+            status,
+        });
+    }
+    let status = transaction::combine_send_raw_transaction_statuses(
+        provider_statuses
+            .iter()
+            .filter_map(|s| s.status.as_ref().ok()),
+    );
+    match status {
+        Some(status) => Ok(SendRawTransactionResult {
+            transaction_hash: eth::to_hex(&transaction.hash),
+            status,
+            provider_statuses,
+        }),
+        // All providers failed: report the error of the first one.
+        None => Err(provider_statuses.remove(0).status.unwrap_err()),
+    }
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn eth_get_transaction_receipt(
+    chain_id: u64,
+    transaction_hash: String,
+    max_response_bytes: Option<u64>,
+) -> Result<Option<TransactionReceipt>, EthRpcError> {
+    let result = typed_request(
+        chain_id,
+        "eth_getTransactionReceipt",
+        serde_json::json!([transaction_hash]),
+        max_response_bytes,
+    )
+    .await?;
+    if result.is_null() {
+        return Ok(None);
+    }
+    let mut receipt =
+        rpc_types::transaction_receipt(&result).map_err(EthRpcError::InvalidResponse)?;
+    // Blob versioned hashes are part of the transaction, not of the receipt.
+    if receipt.transaction_type == 3u64 {
+        let transaction = typed_request(
+            chain_id,
+            "eth_getTransactionByHash",
+            serde_json::json!([transaction_hash]),
+            max_response_bytes,
+        )
+        .await?;
+        receipt.blob_versioned_hashes =
+            rpc_types::blob_versioned_hashes(&transaction).map_err(EthRpcError::InvalidResponse)?;
+    }
+    Ok(Some(receipt))
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn eth_get_block(
+    chain_id: u64,
+    block: BlockTag,
+    full_transactions: bool,
+    max_response_bytes: Option<u64>,
+) -> Result<Option<Block>, EthRpcError> {
+    get_block(chain_id, &block, full_transactions, max_response_bytes).await
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn eth_get_proof_verified(
+    chain_id: u64,
+    address: String,
+    storage_keys: Vec<String>,
+    block: BlockTag,
+    max_response_bytes: Option<u64>,
+) -> Result<VerifiedAccount, EthRpcError> {
+    let header = verified_block_header(chain_id, &block).await?;
+    let proof = typed_request(
+        chain_id,
+        "eth_getProof",
+        serde_json::json!([
+            address,
+            storage_keys,
+            BlockTag::Hash(header.hash.clone()).to_param()
+        ]),
+        max_response_bytes,
+    )
+    .await?;
+    proof::verify_account_proof(&header, &address, &storage_keys, &proof)
+        .map_err(EthRpcError::InvalidProof)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn eth_get_transaction_receipt_verified(
+    chain_id: u64,
+    transaction_hash: String,
+    max_response_bytes: Option<u64>,
+) -> Result<Option<TransactionReceipt>, EthRpcError> {
+    let Some(receipt) = eth_get_transaction_receipt(chain_id, transaction_hash.clone(), None).await? else {
+        return Ok(None);
+    };
+    // Receipts do not commit to transaction hashes, so the receipt is selected by the index
+    // of the transaction in the transactions trie of the block.
+    let block = BlockTag::Hash(receipt.block_hash);
+    let max_response_bytes = Some(max_response_bytes.unwrap_or(BLOCK_RECEIPTS_MAX_RESPONSE_BYTES));
+    let (method, block_param) = block.get_block_request();
+    let result = typed_request(
+        chain_id,
+        method,
+        serde_json::json!([block_param, true]),
+        max_response_bytes,
+    )
+    .await?;
+    let header = if result.is_null() {
+        None
+    } else {
+        Some(rpc_types::block(&result).map_err(EthRpcError::InvalidResponse)?)
+    };
+    let header = verify_block_header(&block, header)?;
+    let transactions = result["transactions"].as_array().ok_or_else(|| {
+        EthRpcError::InvalidResponse("expected an array of transactions".to_string())
+    })?;
+    let transactions = proof::verify_transactions_root(&header, transactions)
+        .map_err(EthRpcError::InvalidProof)?;
+    let result = typed_request(
+        chain_id,
+        "eth_getBlockReceipts",
+        serde_json::json!([BlockTag::Hash(header.hash.clone()).to_param()]),
+        max_response_bytes,
+    )
+    .await?;
+    let receipts = result
+        .as_array()
+        .ok_or_else(|| EthRpcError::InvalidResponse("expected an array of receipts".to_string()))?
+        .iter()
+        .map(rpc_types::transaction_receipt)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(EthRpcError::InvalidResponse)?;
+    proof::verified_receipt(&header, &transactions, &receipts, &transaction_hash)
+        .map(Some)
+        .map_err(EthRpcError::InvalidProof)
+}
+
+// Fetches a block and checks that its header matches its hash and, if the block is
+// selected by hash, the requested hash.
+async fn verified_block_header(chain_id: u64, block: &BlockTag) -> Result<Block, EthRpcError> {
+    let header = get_block(
+        chain_id,
+        block,
+        false,
+        Some(BLOCK_HEADER_MAX_RESPONSE_BYTES),
+    )
+    .await?;
+    verify_block_header(block, header)
+}
+
+fn verify_block_header(block: &BlockTag, header: Option<Block>) -> Result<Block, EthRpcError> {
+    let header = header.ok_or_else(|| EthRpcError::InvalidArgument("unknown block".to_string()))?;
+    if let BlockTag::Hash(hash) = block {
+        if !hash.eq_ignore_ascii_case(&header.hash) {
+            return Err(EthRpcError::InvalidProof(format!(
+                "requested block {}, got block {}",
+                hash, header.hash
+            )));
+        }
+    }
+    proof::verify_block_hash(&header).map_err(EthRpcError::InvalidProof)?;
+    Ok(header)
+}
+
+async fn get_block(
+    chain_id: u64,
+    block: &BlockTag,
+    full_transactions: bool,
+    max_response_bytes: Option<u64>,
+) -> Result<Option<Block>, EthRpcError> {
+    let (method, block_param) = block.get_block_request();
+    let result = typed_request(
+        chain_id,
+        method,
+        serde_json::json!([block_param, full_transactions]),
+        max_response_bytes,
+    )
+    .await?;
+    if result.is_null() {
+        return Ok(None);
+    }
+    rpc_types::block(&result)
+        .map(Some)
+        .map_err(EthRpcError::InvalidResponse)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn eth_call(
+    chain_id: u64,
+    call: ContractCall,
+    block: Option<BlockTag>,
+    max_response_bytes: Option<u64>,
+) -> Result<Vec<AbiValue>, EthRpcError> {
+    let signature = abi::Signature::parse(&call.function).map_err(EthRpcError::InvalidArgument)?;
+    let returns = abi::parse_type_list(&call.returns).map_err(EthRpcError::InvalidArgument)?;
+    let error_signatures = parse_error_signatures(&call.errors)?;
+    let data = signature
+        .encode_call(&call.args)
+        .map_err(EthRpcError::InvalidArgument)?;
+    let mut transaction = serde_json::json!({ "to": call.to, "data": eth::to_hex(&data) });
+    if let Some(from) = call.from {
+        transaction["from"] = from.into();
+    }
+    if let Some(value) = call.value {
+        transaction["value"] = format!("{:#x}", value.0).into();
+    }
+    let output = call_contract(
+        chain_id,
+        transaction,
+        block,
+        max_response_bytes,
+        &error_signatures,
+        1,
+    )
+    .await?;
+    abi::decode(&returns, &output).map_err(EthRpcError::InvalidResponse)
+}
+
+// Returns the output of an `eth_call` of the transaction, which the given number of
+// providers must agree on.
+async fn call_contract(
+    chain_id: u64,
+    transaction: serde_json::Value,
+    block: Option<BlockTag>,
+    max_response_bytes: Option<u64>,
+    error_signatures: &[abi::Signature],
+    provider_count: u32,
+) -> Result<Vec<u8>, EthRpcError> {
+    let block = consensus_block(chain_id, block, provider_count).await?;
+    let result = consensus_request(
+        chain_id,
+        "eth_call",
+        serde_json::json!([transaction, block.unwrap_or(BlockTag::Latest).to_param()]),
+        max_response_bytes,
+        provider_count,
+    )
+    .await
+    .map_err(|e| execution_reverted(e, error_signatures))?;
+    rpc_types::parse_data(&result)
+        .and_then(|output| eth::from_hex(&output))
+        .map_err(EthRpcError::InvalidResponse)
+}
+
+// Resolves a block given by a tag to its number with the cheapest provider if several
+// providers must agree, so that they all answer for the same block.
+async fn consensus_block(
+    chain_id: u64,
+    block: Option<BlockTag>,
+    provider_count: u32,
+) -> Result<Option<BlockTag>, EthRpcError> {
+    if provider_count <= 1 {
+        return Ok(block);
+    }
+    let number = match block.unwrap_or(BlockTag::Latest) {
+        BlockTag::Latest => {
+            let number =
+                typed_request(chain_id, "eth_blockNumber", serde_json::json!([]), None).await?;
+            rpc_types::parse_quantity(&number).map_err(EthRpcError::InvalidResponse)?
+        }
+        tag @ (BlockTag::Safe | BlockTag::Finalized) => {
+            get_block(chain_id, &tag, false, Some(BLOCK_HEADER_MAX_RESPONSE_BYTES))
+                .await?
+                .ok_or_else(|| {
+                    EthRpcError::InvalidResponse(format!("no {:?} block", tag).to_lowercase())
+                })?
+                .number
+        }
+        block => return Ok(Some(block)),
+    };
+    Ok(Some(BlockTag::Number(number)))
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn multicall(
+    chain_id: u64,
+    calls: Vec<MulticallCall>,
+    block: Option<BlockTag>,
+    max_response_bytes: Option<u64>,
+) -> Result<Vec<CallResult>, EthRpcError> {
+    let (data, prepared) = multicall::encode(&calls).map_err(EthRpcError::InvalidArgument)?;
+    let transaction = serde_json::json!({
+        "to": get_multicall_address(chain_id),
+        "data": eth::to_hex(&data),
+    });
+    let output = call_contract(chain_id, transaction, block, max_response_bytes, &[], 1).await?;
+    multicall::decode(&output, &prepared).map_err(EthRpcError::InvalidResponse)
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_multicall_address(chain_id: u64) -> String {
+    let address = get_chain(chain_id).map_or(multicall::DEFAULT_ADDRESS, |chain| {
+        chain.multicall_address()
+    });
+    eth::to_hex(&address)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn erc20_balance_of(
+    chain_id: u64,
+    token: String,
+    owner: String,
+    block: Option<BlockTag>,
+) -> Result<candid::Nat, EthRpcError> {
+    let output = erc20_call(
+        chain_id,
+        &token,
+        erc20::BALANCE_OF,
+        &[AbiValue::Address(owner)],
+        block,
+    )
+    .await?;
+    erc20::decode_amount(&output).map_err(EthRpcError::InvalidResponse)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn erc20_allowance(
+    chain_id: u64,
+    token: String,
+    owner: String,
+    spender: String,
+    block: Option<BlockTag>,
+) -> Result<candid::Nat, EthRpcError> {
+    let output = erc20_call(
+        chain_id,
+        &token,
+        erc20::ALLOWANCE,
+        &[AbiValue::Address(owner), AbiValue::Address(spender)],
+        block,
+    )
+    .await?;
+    erc20::decode_amount(&output).map_err(EthRpcError::InvalidResponse)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn erc20_metadata(chain_id: u64, token: String) -> Result<Erc20Metadata, EthRpcError> {
+    let key = AddressKey(chain_id, parse_address(&token)?);
+    if let Some(metadata) = ERC20_METADATA.with(|m| m.borrow().get(&key)) {
+        if !authorized(Auth::Rpc) {
+            inc_metric!(json_rpc_request_err_no_permission);
+            return Err(EthRpcError::NoPermission);
+        }
+        if !authorized(Auth::FreeRpc) {
+            charge_cycles(RESPONSE_CACHE_HIT_COST)?;
+        }
+        return Ok(metadata);
+    }
+    let name = optional_output(erc20_call(chain_id, &token, erc20::NAME, &[], None).await)?;
+    let symbol = optional_output(erc20_call(chain_id, &token, erc20::SYMBOL, &[], None).await)?;
+    let decimals = optional_output(erc20_call(chain_id, &token, erc20::DECIMALS, &[], None).await)?;
+    let metadata = Erc20Metadata {
+        name: name
+            .map(|o| erc20::decode_text(&o))
+            .transpose()
+            .map_err(EthRpcError::InvalidResponse)?,
+        symbol: symbol
+            .map(|o| erc20::decode_text(&o))
+            .transpose()
+            .map_err(EthRpcError::InvalidResponse)?,
+        decimals: decimals
+            .map(|o| erc20::decode_decimals(&o))
+            .transpose()
+            .map_err(EthRpcError::InvalidResponse)?,
+    };
+    // Nothing is cached for addresses without a token contract, which may be deployed later.
+    if metadata != Erc20Metadata::default()
+        && Encode!(&metadata).unwrap().len() <= ERC20_METADATA_MAX_SIZE as usize
+    {
+        ERC20_METADATA.with(|m| m.borrow_mut().insert(key, metadata.clone()));
+    }
+    Ok(metadata)
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn erc20_calldata(call: Erc20Calldata) -> Result<String, EthRpcError> {
+    call.encode()
+        .map(|data| eth::to_hex(&data))
+        .map_err(EthRpcError::InvalidArgument)
+}
+
+async fn erc20_call(
+    chain_id: u64,
+    token: &str,
+    function: &str,
+    args: &[AbiValue],
+    block: Option<BlockTag>,
+) -> Result<Vec<u8>, EthRpcError> {
+    let data = erc20::call_data(function, args).map_err(EthRpcError::InvalidArgument)?;
+    let transaction = serde_json::json!({ "to": token, "data": eth::to_hex(&data) });
+    call_contract(chain_id, transaction, block, None, &[], 1).await
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn resolve_ens(name: String, providers: Option<u32>) -> Result<Option<String>, EthRpcError> {
+    let node = ens::namehash(&name).map_err(EthRpcError::InvalidArgument)?;
+    ens_record(node, ens::ADDR, "(address)", providers.unwrap_or(1)).await
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn reverse_resolve(
+    address: String,
+    providers: Option<u32>,
+) -> Result<Option<String>, EthRpcError> {
+    let provider_count = providers.unwrap_or(1);
+    let reverse_name = ens::reverse_name(&parse_address(&address)?);
+    let node = ens::namehash(&reverse_name).expect("invalid reverse name");
+    let Some(name) = ens_record(node, ens::NAME, "(string)", provider_count).await? else {
+        return Ok(None);
+    };
+    // The reverse record is only valid if the name resolves to the address.
+    let Ok(node) = ens::namehash(&name) else {
+        return Ok(None);
+    };
+    let resolved = ens_record(node, ens::ADDR, "(address)", provider_count).await?;
+    Ok(resolved
+        .filter(|resolved| resolved.eq_ignore_ascii_case(&address))
+        .map(|_| name))
+}
+
+// Looks up a record of a node with the resolver set in the ENS registry.
+async fn ens_record(
+    node: [u8; 32],
+    function: &'static str,
+    returns: &str,
+    provider_count: u32,
+) -> Result<Option<String>, EthRpcError> {
+    let now = ic_cdk::api::time();
+    let cached = ENS_RECORDS.with(|r| {
+        r.borrow()
+            .get(&(node, function))
+            .filter(|(time, providers, _)| {
+                now.saturating_sub(*time) < ENS_RECORD_CACHE_TTL_NS && *providers >= provider_count
+            })
+            .map(|(_, _, record)| record.clone())
+    });
+    if let Some(record) = cached {
+        if !authorized(Auth::Rpc) {
+            inc_metric!(json_rpc_request_err_no_permission);
+            return Err(EthRpcError::NoPermission);
+        }
+        if !authorized(Auth::FreeRpc) {
+            charge_cycles(RESPONSE_CACHE_HIT_COST)?;
+        }
+        return Ok(record);
+    }
+    // The registry and the resolver are called at the same block.
+    let block = consensus_block(ens::CHAIN_ID, None, provider_count).await?;
+    let registry = eth::to_hex(&ens::REGISTRY);
+    let resolver = ens_call(
+        registry,
+        ens::RESOLVER,
+        node,
+        "(address)",
+        block.clone(),
+        provider_count,
+    )
+    .await?
+    .and_then(ens::record);
+    let record = match resolver {
+        Some(resolver) => ens_call(resolver, function, node, returns, block, provider_count)
+            .await?
+            .and_then(ens::record),
+        None => None,
+    };
+    ENS_RECORDS.with(|r| {
+        let mut r = r.borrow_mut();
+        if r.len() >= MAX_ENS_RECORDS {
+            r.clear();
+        }
+        r.insert((node, function), (now, provider_count, record.clone()));
+    });
+    Ok(record)
+}
+
+// Calls a function of an ENS contract taking a node, `None` if it is not implemented.
+async fn ens_call(
+    to: String,
+    function: &str,
+    node: [u8; 32],
+    returns: &str,
+    block: Option<BlockTag>,
+    provider_count: u32,
+) -> Result<Option<AbiValue>, EthRpcError> {
+    let data = abi::Signature::parse(function)
+        .and_then(|signature| signature.encode_call(&[AbiValue::FixedBytes(eth::to_hex(&node))]))
+        .expect("invalid ENS function");
+    let transaction = serde_json::json!({ "to": to, "data": eth::to_hex(&data) });
+    let output = optional_output(
+        call_contract(ens::CHAIN_ID, transaction, block, None, &[], provider_count).await,
+    )?;
+    let Some(output) = output else {
+        return Ok(None);
+    };
+    let returns = abi::parse_type_list(returns).expect("invalid ENS returns");
+    abi::decode(&returns, &output)
+        .map(|mut values| values.pop())
+        .map_err(EthRpcError::InvalidResponse)
+}
+
+// The output of a call of an optional function, `None` if the contract does not implement it.
+fn optional_output(result: Result<Vec<u8>, EthRpcError>) -> Result<Option<Vec<u8>>, EthRpcError> {
+    match result {
+        Ok(output) if output.is_empty() => Ok(None),
+        Ok(output) => Ok(Some(output)),
+        Err(EthRpcError::ExecutionReverted(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn parse_address(address: &str) -> Result<[u8; 20], EthRpcError> {
+    eth::from_hex(address)
+        .ok()
+        .and_then(|address| address.try_into().ok())
+        .ok_or_else(|| EthRpcError::InvalidArgument(format!("invalid address {}", address)))
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn get_price_feed(
+    chain_id: u64,
+    feed_address: String,
+    max_age_seconds: Option<u64>,
+    block: Option<BlockTag>,
+    providers: Option<u32>,
+) -> Result<PriceFeed, EthRpcError> {
+    parse_address(&feed_address)?;
+    let provider_count = providers.unwrap_or(1);
+    // Both functions are called at the same block.
+    let block = consensus_block(chain_id, block, provider_count).await?;
+    let mut outputs = vec![];
+    for function in [chainlink::LATEST_ROUND_DATA, chainlink::DECIMALS] {
+        let data = abi::Signature::parse(function)
+            .and_then(|signature| signature.encode_call(&[]))
+            .expect("invalid price feed function");
+        let transaction = serde_json::json!({ "to": feed_address, "data": eth::to_hex(&data) });
+        outputs.push(
+            call_contract(
+                chain_id,
+                transaction,
+                block.clone(),
+                None,
+                &[],
+                provider_count,
+            )
+            .await?,
+        );
+    }
+    let feed = PriceFeed::decode(&outputs[0], &outputs[1]).map_err(EthRpcError::InvalidResponse)?;
+    if let Some(max_age_seconds) = max_age_seconds {
+        let now_seconds = ic_cdk::api::time() / 1_000_000_000;
+        if let Some(age_seconds) = feed.stale_age(now_seconds, max_age_seconds) {
+            return Err(EthRpcError::StalePrice {
+                updated_at: feed.updated_at,
+                age_seconds,
+            });
+        }
+    }
+    Ok(feed)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn estimate_gas(
+    chain_id: u64,
+    tx_request: TransactionRequest,
+    gas_multiplier_percent: Option<u32>,
+    errors: Vec<String>,
+) -> Result<candid::Nat, EthRpcError> {
+    let gas_multiplier_percent = gas_multiplier_percent.unwrap_or(DEFAULT_GAS_MULTIPLIER_PERCENT);
+    if gas_multiplier_percent < 100 {
+        return Err(EthRpcError::InvalidArgument(
+            "gas_multiplier_percent must be at least 100".to_string(),
+        ));
+    }
+    let error_signatures = parse_error_signatures(&errors)?;
+    let transaction = tx_request.to_json().map_err(EthRpcError::InvalidArgument)?;
+    let result = typed_request(
+        chain_id,
+        "eth_estimateGas",
+        serde_json::json!([transaction]),
+        None,
+    )
+    .await
+    .map_err(|e| execution_reverted(e, &error_signatures))?;
+    let gas = rpc_types::parse_quantity(&result).map_err(EthRpcError::InvalidResponse)?;
+    // Round up so that the margin is never lost to integer division.
+    Ok(candid::Nat(
+        (gas.0 * gas_multiplier_percent + 99u32) / 100u32,
+    ))
+}
+
+fn parse_error_signatures(errors: &[String]) -> Result<Vec<abi::Signature>, EthRpcError> {
+    errors
+        .iter()
+        .map(|error| abi::Signature::parse(error).map_err(EthRpcError::InvalidArgument))
+        .collect()
+}
+
+// Decodes the revert data of a JSON-RPC error reporting a reverted execution.
+fn execution_reverted(error: EthRpcError, error_signatures: &[abi::Signature]) -> EthRpcError {
+    match error {
+        EthRpcError::JsonRpcError {
+            code,
+            message,
+            data,
+        } if code == 3 || message.to_lowercase().contains("revert") => {
+            let data = data
+                .and_then(|data| eth::from_hex(&data).ok())
+                .unwrap_or_default();
+            EthRpcError::ExecutionReverted(abi::decode_revert(&data, error_signatures))
+        }
+        error => error,
+    }
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn estimate_fees(chain_id: u64) -> Result<FeeEstimate, EthRpcError> {
+    let now = ic_cdk::api::time();
+    let cached = FEE_ESTIMATES.with(|f| {
+        f.borrow()
+            .get(&chain_id)
+            .filter(|(time, _)| now.saturating_sub(*time) < FEE_ESTIMATE_CACHE_TTL_NS)
+            .map(|(_, estimate)| estimate.clone())
+    });
+    if let Some(estimate) = cached {
+        if !authorized(Auth::Rpc) {
+            inc_metric!(json_rpc_request_err_no_permission);
+            return Err(EthRpcError::NoPermission);
+        }
+        if !authorized(Auth::FreeRpc) {
+            charge_cycles(RESPONSE_CACHE_HIT_COST)?;
+        }
+        return Ok(estimate);
+    }
+    let config = FEE_ESTIMATION_CONFIG.with(|c| c.borrow().get().clone());
+    let fee_history = typed_request(
+        chain_id,
+        "eth_feeHistory",
+        config.fee_history_params(),
+        None,
+    )
+    .await?;
+    let estimate = fees::fee_estimate(&fee_history).map_err(EthRpcError::InvalidResponse)?;
+    FEE_ESTIMATES.with(|f| {
+        f.borrow_mut()
+            .insert(chain_id, (ic_cdk::api::time(), estimate.clone()))
+    });
+    Ok(estimate)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn get_eth_address() -> Result<String, EthRpcError> {
+    let public_key = ecdsa_public_key(ic_cdk::caller()).await?;
+    let address = ecdsa::eth_address(&public_key).map_err(EthRpcError::SigningError)?;
+    Ok(eth::to_hex(&address))
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn sign_and_send_transaction(
+    chain_id: u64,
+    unsigned_tx: TransactionRequest,
+) -> Result<SendRawTransactionResult, EthRpcError> {
+    if !authorized(Auth::Rpc) {
+        inc_metric!(json_rpc_request_err_no_permission);
+        return Err(EthRpcError::NoPermission);
+    }
+    if unsigned_tx.gas_price.is_some() {
+        return Err(EthRpcError::InvalidArgument(
+            "gas_price is not supported, set max_fee_per_gas instead".to_string(),
+        ));
+    }
+    let to = match &unsigned_tx.to {
+        Some(to) => Some(
+            eth::from_hex(to)
+                .ok()
+                .and_then(|to| <[u8; 20]>::try_from(to).ok())
+                .ok_or_else(|| EthRpcError::InvalidArgument(format!("invalid to {}", to)))?,
+        ),
+        None => None,
+    };
+    let data = match &unsigned_tx.input {
+        Some(input) => eth::from_hex(input).map_err(EthRpcError::InvalidArgument)?,
+        None => vec![],
+    };
+    let caller = ic_cdk::caller();
+    let public_key = ecdsa_public_key(caller).await?;
+    let address = ecdsa::eth_address(&public_key).map_err(EthRpcError::SigningError)?;
+    if let Some(from) = &unsigned_tx.from {
+        if !from.eq_ignore_ascii_case(&eth::to_hex(&address)) {
+            return Err(EthRpcError::InvalidArgument(format!(
+                "from {} is not the address of the caller {}",
+                from,
+                eth::to_hex(&address)
+            )));
+        }
+    }
+    let nonce = match &unsigned_tx.nonce {
+        Some(nonce) => nonce.clone(),
+        None => {
+            let nonce = reserve_nonce_for(chain_id, address).await?;
+            let key = AddressKey(chain_id, address);
+            let raw = match sign_transaction(
+                chain_id,
+                caller,
+                &public_key,
+                unsigned_tx,
+                candid::Nat::from(nonce),
+                to,
+                data,
+            )
+            .await
+            {
+                Ok(raw) => raw,
+                Err(e) => {
+                    // The transaction was not sent.
+                    update_nonce_state(&key, |s| release_unsent_nonce(s, nonce));
+                    return Err(e);
+                }
+            };
+            let result = eth_send_raw_transaction(chain_id, eth::to_hex(&raw)).await;
+            settle_nonce(&key, nonce, &result);
+            return result;
+        }
+    };
+    let raw = sign_transaction(chain_id, caller, &public_key, unsigned_tx, nonce, to, data).await?;
+    eth_send_raw_transaction(chain_id, eth::to_hex(&raw)).await
+}
+
+// Fills in the fees and gas of a transaction of the caller and signs it.
+async fn sign_transaction(
+    chain_id: u64,
+    caller: Principal,
+    public_key: &[u8],
+    unsigned_tx: TransactionRequest,
+    nonce: candid::Nat,
+    to: Option<[u8; 20]>,
+    data: Vec<u8>,
+) -> Result<Vec<u8>, EthRpcError> {
+    let (max_fee_per_gas, max_priority_fee_per_gas) = match (
+        &unsigned_tx.max_fee_per_gas,
+        &unsigned_tx.max_priority_fee_per_gas,
+    ) {
+        (Some(max_fee), Some(max_priority_fee)) => (max_fee.clone(), max_priority_fee.clone()),
+        (max_fee, max_priority_fee) => {
+            let estimate = estimate_fees(chain_id).await?;
+            (
+                max_fee.clone().unwrap_or(estimate.max_fee_per_gas),
+                max_priority_fee
+                    .clone()
+                    .unwrap_or(estimate.max_priority_fee_per_gas),
+            )
+        }
+    };
+    let gas = match &unsigned_tx.gas {
+        Some(gas) => gas.clone(),
+        None => {
+            let address = ecdsa::eth_address(public_key).map_err(EthRpcError::SigningError)?;
+            let tx_request = TransactionRequest {
+                from: Some(eth::to_hex(&address)),
+                nonce: Some(nonce.clone()),
+                ..unsigned_tx.clone()
+            };
+            estimate_gas(chain_id, tx_request, None, vec![]).await?
+        }
+    };
+    let tx = Eip1559Transaction {
+        chain_id,
+        nonce,
+        max_priority_fee_per_gas,
+        max_fee_per_gas,
+        gas,
+        to,
+        value: unsigned_tx.value.unwrap_or_else(|| candid::Nat::from(0u64)),
+        data,
+        access_list: vec![],
+    };
+    let signature = sign_with_ecdsa(caller, tx.signing_hash()).await?;
+    ecdsa::signed_transaction(&tx, &signature, public_key).map_err(EthRpcError::SigningError)
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn hash_typed_data(typed_data: TypedData) -> Result<TypedDataHash, EthRpcError> {
+    typed_data.hash().map_err(EthRpcError::InvalidArgument)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn sign_typed_data(typed_data: TypedData) -> Result<TypedDataSignature, EthRpcError> {
+    if !authorized(Auth::Rpc) {
+        inc_metric!(json_rpc_request_err_no_permission);
+        return Err(EthRpcError::NoPermission);
+    }
+    let digest = typed_data.digest().map_err(EthRpcError::InvalidArgument)?;
+    let caller = ic_cdk::caller();
+    let public_key = ecdsa_public_key(caller).await?;
+    let signature = sign_with_ecdsa(caller, digest).await?;
+    let signature = ecdsa::recoverable_signature(&digest, &signature, &public_key)
+        .map_err(EthRpcError::SigningError)?;
+    Ok(TypedDataSignature::new(&digest, &signature))
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn reserve_nonce(chain_id: u64) -> Result<u64, EthRpcError> {
+    if !authorized(Auth::Rpc) {
+        inc_metric!(json_rpc_request_err_no_permission);
+        return Err(EthRpcError::NoPermission);
+    }
+    let public_key = ecdsa_public_key(ic_cdk::caller()).await?;
+    let address = ecdsa::eth_address(&public_key).map_err(EthRpcError::SigningError)?;
+    reserve_nonce_for(chain_id, address).await
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn release_nonce(chain_id: u64, nonce: u64) -> Result<(), EthRpcError> {
+    if !authorized(Auth::Rpc) {
+        inc_metric!(json_rpc_request_err_no_permission);
+        return Err(EthRpcError::NoPermission);
+    }
+    let public_key = ecdsa_public_key(ic_cdk::caller()).await?;
+    let address = ecdsa::eth_address(&public_key).map_err(EthRpcError::SigningError)?;
+    update_nonce_state(&AddressKey(chain_id, address), |s| s.release(nonce))
+        .map_err(EthRpcError::InvalidArgument)
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_nonce_state(chain_id: u64, address: String) -> Option<NonceState> {
+    let address = eth::from_hex(&address).ok()?.try_into().ok()?;
+    NONCES.with(|n| n.borrow().get(&AddressKey(chain_id, address)))
+}
+
+// Reserves the next nonce of an address, first reconciling with the chain if due.
+async fn reserve_nonce_for(chain_id: u64, address: [u8; 20]) -> Result<u64, EthRpcError> {
+    let key = AddressKey(chain_id, address);
+    let state = NONCES.with(|n| n.borrow().get(&key)).unwrap_or_default();
+    if state.needs_reconcile(ic_cdk::api::time()) {
+        let count = typed_request(
+            chain_id,
+            "eth_getTransactionCount",
+            serde_json::json!([eth::to_hex(&address), "pending"]),
+            None,
+        )
+        .await?;
+        let count = rpc_types::parse_quantity(&count)
+            .map_err(EthRpcError::InvalidResponse)?
+            .0
+            .to_u64()
+            .ok_or_else(|| EthRpcError::InvalidResponse("nonce out of range".to_string()))?;
+        // Reservations made while waiting for the response are taken into account.
+        update_nonce_state(&key, |s| s.reconcile(count, ic_cdk::api::time()));
+    }
+    Ok(update_nonce_state(&key, |s| s.reserve(ic_cdk::api::time())))
+}
+
+// Settles the reservation of the nonce of a broadcast transaction.
+fn settle_nonce(
+    key: &AddressKey,
+    nonce: u64,
+    result: &Result<SendRawTransactionResult, EthRpcError>,
+) {
+    update_nonce_state(key, |s| match result {
+        Ok(result)
+            if matches!(
+                result.status,
+                SendRawTransactionStatus::Ok | SendRawTransactionStatus::AlreadyKnown
+            ) => {}
+        // Only released if every provider rejected the transaction.
+        Ok(result)
+            if result.status != SendRawTransactionStatus::NonceTooLow
+                && result.provider_statuses.iter().all(|p| p.status.is_ok()) =>
+        {
+            release_unsent_nonce(s, nonce)
+        }
+        // The nonce is used, or a provider that did not answer may have accepted the
+        // transaction: the nonce stays reserved and the next reservation reconciles with the
+        // chain.
+        _ => s.invalidate(),
+    });
+}
+
+// Releases a nonce whose transaction was not accepted.
+fn release_unsent_nonce(state: &mut NonceState, nonce: u64) {
+    if let Err(e) = state.release(nonce) {
+        log!(ERROR, "failed to release nonce: {}", e);
+    }
+}
+
+fn update_nonce_state<T>(key: &AddressKey, f: impl FnOnce(&mut NonceState) -> T) -> T {
+    NONCES.with(|n| {
+        let mut n = n.borrow_mut();
+        let mut state = n.get(key).unwrap_or_default();
+        let result = f(&mut state);
+        n.insert(key.clone(), state);
+        result
+    })
+}
+
+#[ic_cdk::update(guard = "is_authorized")]
+#[candid_method]
+fn set_ecdsa_key_name(name: String) {
+    if name.is_empty() || name.len() > MAX_ECDSA_KEY_NAME_LENGTH {
+        ic_cdk::trap(&format!(
+            "name must have between 1 and {} characters",
+            MAX_ECDSA_KEY_NAME_LENGTH
+        ));
+    }
+    METADATA.with(|m| {
+        let mut metadata = m.borrow().get().clone();
+        metadata.ecdsa_key_name = Some(name);
+        m.borrow_mut().set(metadata).unwrap();
+    });
+    // The keys of all callers change with the key of the canister.
+    ECDSA_PUBLIC_KEYS.with(|k| k.borrow_mut().clear());
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_ecdsa_key_name() -> String {
+    METADATA
+        .with(|m| m.borrow().get().ecdsa_key_name.clone())
+        .unwrap_or_else(|| DEFAULT_ECDSA_KEY_NAME.to_string())
+}
+
+fn ecdsa_key_id() -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: get_ecdsa_key_name(),
+    }
+}
+
+// The public key derived for a caller from the threshold ECDSA key.
+async fn ecdsa_public_key(caller: Principal) -> Result<Vec<u8>, EthRpcError> {
+    if let Some(public_key) = ECDSA_PUBLIC_KEYS.with(|k| k.borrow().get(&caller).cloned()) {
+        return Ok(public_key);
+    }
+    let (response,) =
+        ic_cdk::api::management_canister::ecdsa::ecdsa_public_key(EcdsaPublicKeyArgument {
+            canister_id: None,
+            derivation_path: ecdsa::derivation_path(&caller),
+            key_id: ecdsa_key_id(),
+        })
+        .await
+        .map_err(|(code, message)| {
+            EthRpcError::SigningError(format!("ecdsa_public_key failed ({:?}): {}", code, message))
+        })?;
+    ECDSA_PUBLIC_KEYS.with(|k| {
+        let mut k = k.borrow_mut();
+        if k.len() >= MAX_ECDSA_PUBLIC_KEYS {
+            k.clear();
+        }
+        k.insert(caller, response.public_key.clone())
+    });
+    Ok(response.public_key)
+}
+
+async fn sign_with_ecdsa(caller: Principal, hash: [u8; 32]) -> Result<Vec<u8>, EthRpcError> {
+    if !authorized(Auth::FreeRpc) {
+        charge_cycles(ECDSA_SIGNATURE_COST)?;
+    }
+    let (response,) =
+        ic_cdk::api::management_canister::ecdsa::sign_with_ecdsa(SignWithEcdsaArgument {
+            message_hash: hash.to_vec(),
+            derivation_path: ecdsa::derivation_path(&caller),
+            key_id: ecdsa_key_id(),
+        })
+        .await
+        .map_err(|(code, message)| {
+            EthRpcError::SigningError(format!("sign_with_ecdsa failed ({:?}): {}", code, message))
+        })?;
+    Ok(response.signature)
+}
+
+#[ic_cdk::update(guard = "is_authorized")]
+#[candid_method]
+fn track_chain_headers(config: HeaderTrackerConfig) {
+    if let Err(e) = config.validate() {
+        ic_cdk::trap(&e);
+    }
+    HEADER_TRACKERS.with(|h| h.borrow_mut().insert(config.chain_id, config.clone()));
+    start_header_tracker(&config);
+}
+
+#[ic_cdk::update(guard = "is_authorized")]
+#[candid_method]
+fn untrack_chain_headers(chain_id: u64) {
+    HEADER_TRACKERS.with(|h| h.borrow_mut().remove(&chain_id));
+    FINALIZED_HEADERS.with(|f| f.borrow_mut().remove(&chain_id));
+    BLOCK_HEADERS.with(|b| {
+        let numbers: Vec<u64> = chain_headers(chain_id).0.keys().copied().collect();
+        for number in numbers {
+            b.borrow_mut().remove(&HeaderKey(chain_id, number));
+        }
+    });
+    if let Some(timer_id) = HEADER_TRACKER_TIMERS.with(|t| t.borrow_mut().remove(&chain_id)) {
+        ic_cdk_timers::clear_timer(timer_id);
+    }
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_header_trackers() -> Vec<HeaderTrackerConfig> {
+    HEADER_TRACKERS.with(|h| h.borrow().iter().map(|(_, config)| config).collect())
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_head(chain_id: u64) -> Option<BlockHeader> {
+    chain_headers(chain_id).head().cloned()
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_finalized(chain_id: u64) -> Option<BlockHeader> {
+    FINALIZED_HEADERS.with(|f| f.borrow().get(&chain_id))
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn is_canonical(chain_id: u64, block_hash: String) -> bool {
+    chain_headers(chain_id).contains_hash(&block_hash)
+        || get_finalized(chain_id).map_or(false, |f| f.hash.eq_ignore_ascii_case(&block_hash))
+}
+
+// The tracked headers of a chain.
+fn chain_headers(chain_id: u64) -> HeaderChain {
+    BLOCK_HEADERS.with(|b| {
+        HeaderChain(
+            b.borrow()
+                .range(HeaderKey(chain_id, 0)..=HeaderKey(chain_id, u64::MAX))
+                .map(|(key, header)| (key.1, header))
+                .collect(),
+        )
+    })
+}
+
+fn start_header_tracker(config: &HeaderTrackerConfig) {
+    let chain_id = config.chain_id;
+    let timer_id = ic_cdk_timers::set_timer_interval(
+        Duration::from_secs(config.poll_interval_seconds),
+        move || ic_cdk::spawn(poll_headers(chain_id)),
+    );
+    if let Some(timer_id) =
+        HEADER_TRACKER_TIMERS.with(|t| t.borrow_mut().insert(chain_id, timer_id))
+    {
+        ic_cdk_timers::clear_timer(timer_id);
+    }
+}
+
+async fn poll_headers(chain_id: u64) {
+    // Skip the poll if the previous one is still waiting for responses.
+    if !HEADER_TRACKER_POLLS.with(|p| p.borrow_mut().insert(chain_id)) {
+        return;
+    }
+    if let Err(e) = update_headers(chain_id).await {
+        inc_metric!(header_tracker_errors);
+        log!(
+            ERROR,
+            "failed to update headers of chain {}: {:?}",
+            chain_id,
+            e
+        );
+    }
+    HEADER_TRACKER_POLLS.with(|p| p.borrow_mut().remove(&chain_id));
+}
+
+async fn update_headers(chain_id: u64) -> Result<(), EthRpcError> {
+    let latest = fetch_header(chain_id, &BlockTag::Latest).await?;
+    let mut chain = chain_headers(chain_id);
+    // Nothing changed if the latest block is already tracked. Replacing the headers from an
+    // older latest block on would report a reorg where a provider is merely lagging behind.
+    if !chain.contains(&latest) {
+        let mut fetched = vec![latest];
+        let reset = loop {
+            match chain.next_step(&fetched, MAX_HEADER_FETCHES_PER_POLL) {
+                NextStep::Link => break false,
+                NextStep::Reset => break true,
+                NextStep::FetchParent(hash) => {
+                    fetched.push(fetch_header(chain_id, &BlockTag::Hash(hash)).await?)
+                }
+            }
+        };
+        // The chain may have been untracked while waiting for responses.
+        let Some(config) = HEADER_TRACKERS.with(|h| h.borrow().get(&chain_id)) else {
+            return Ok(());
+        };
+        let finality_depth = get_chain(chain_id).map(|registered| registered.finality_depth);
+        let update = chain.apply(fetched, reset, config.capacity(finality_depth));
+        if reset {
+            inc_metric!(header_tracker_resets);
+            log!(INFO, "restarted tracking the headers of chain {}", chain_id);
+        }
+        if update.reorg_depth > 0 {
+            inc_metric!(header_tracker_reorgs);
+            log!(
+                INFO,
+                "reorg of depth {} on chain {}, new head {:?}",
+                update.reorg_depth,
+                chain_id,
+                chain.head()
+            );
+        }
+        BLOCK_HEADERS.with(|b| {
+            let mut b = b.borrow_mut();
+            for number in update.removed {
+                b.remove(&HeaderKey(chain_id, number));
+            }
+            for header in update.inserted {
+                b.insert(HeaderKey(chain_id, header.number), header);
+            }
+        });
+    }
+    // Not all chains support the `finalized` tag.
+    match fetch_header(chain_id, &BlockTag::Finalized).await {
+        Ok(finalized) if HEADER_TRACKERS.with(|h| h.borrow().contains_key(&chain_id)) => {
+            FINALIZED_HEADERS.with(|f| f.borrow_mut().insert(chain_id, finalized));
+        }
+        Ok(_) => (),
+        Err(e) => log!(INFO, "no finalized header for chain {}: {:?}", chain_id, e),
+    }
+    Ok(())
+}
+
+// The canister pays for the requests of the header tracker from its own balance.
+async fn fetch_header(chain_id: u64, block: &BlockTag) -> Result<BlockHeader, EthRpcError> {
+    let (method, block_param) = block.get_block_request();
+    let result = prepaid_request(
+        chain_id,
+        method,
+        serde_json::json!([block_param, false]),
+        BLOCK_HEADER_MAX_RESPONSE_BYTES,
+        |cost| {
+            add_metric!(header_tracker_cycles_spent, cost);
+            Ok(())
+        },
+    )
+    .await
+    .map_err(EthRpcError::InvalidResponse)?;
+    let header = if result.is_null() {
+        None
+    } else {
+        Some(rpc_types::block(&result).map_err(EthRpcError::InvalidResponse)?)
+    };
+    let header = verify_block_header(block, header)?;
+    BlockHeader::from_block(&header).map_err(EthRpcError::InvalidResponse)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+fn subscribe_logs(request: SubscribeLogs) -> Result<u64, EthRpcError> {
+    let caller = ic_cdk::caller();
+    // Subscriptions are limited per owner, which the anonymous principal cannot be.
+    if !authorized(Auth::Rpc) || caller == Principal::anonymous() {
+        inc_metric!(json_rpc_request_err_no_permission);
+        return Err(EthRpcError::NoPermission);
+    }
+    request.validate().map_err(EthRpcError::InvalidArgument)?;
+    if chain_providers(request.chain_id).is_empty() {
+        return Err(EthRpcError::ProviderNotFound);
+    }
+    let subscription_count = SUBSCRIPTIONS.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|(_, subscription)| subscription.owner == caller)
+            .count()
+    });
+    if subscription_count >= MAX_SUBSCRIPTIONS_PER_OWNER {
+        return Err(EthRpcError::InvalidArgument(format!(
+            "an owner may have at most {} subscriptions",
+            MAX_SUBSCRIPTIONS_PER_OWNER
+        )));
+    }
+    let balance = accept_prepaid_balance()?;
+    let subscription_id = NEXT_SUBSCRIPTION_ID.with(|n| {
+        let subscription_id = *n.borrow().get();
+        n.borrow_mut().set(subscription_id + 1).unwrap();
+        subscription_id
+    });
+    let subscription = Subscription {
+        subscription_id,
+        owner: caller,
+        chain_id: request.chain_id,
+        filter: request.filter,
+        callback_method: request.callback_method,
+        confirmations: request.confirmations,
+        poll_interval_seconds: request.poll_interval_seconds,
+        max_response_bytes: request.max_response_bytes,
+        next_block: request.from_block,
+        balance,
+        status: SubscriptionStatus::Active,
+        failed_attempts: 0,
+        next_poll_at_ns: 0,
+    };
+    store_subscription(subscription);
+    Ok(subscription_id)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+fn top_up_subscription(subscription_id: u64) -> Result<u128, EthRpcError> {
+    let mut subscription =
+        get_subscription(subscription_id).ok_or(EthRpcError::SubscriptionNotFound)?;
+    subscription.balance +=
+        ic_cdk::api::call::msg_cycles_accept128(ic_cdk::api::call::msg_cycles_available128());
+    let balance = subscription.balance;
+    store_subscription(subscription);
+    Ok(balance)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+fn resume_subscription(subscription_id: u64) -> Result<(), EthRpcError> {
+    owned_subscription(subscription_id)?;
+    update_subscription(subscription_id, |s| {
+        s.status = SubscriptionStatus::Active;
+        s.failed_attempts = 0;
+        s.next_poll_at_ns = 0;
+    });
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn unsubscribe_logs(subscription_id: u64) -> Result<(), EthRpcError> {
+    let subscription = owned_subscription(subscription_id)?;
+    // Removed while waiting for the refund, so that it is not polled anymore.
+    remove_subscription(subscription_id);
+    if let Err(e) = refund_balance(subscription.owner, subscription.balance).await {
+        // Kept with its balance, so that the owner can try again.
+        let subscription = Subscription {
+            status: SubscriptionStatus::paused(e.clone()),
+            ..subscription
+        };
+        store_subscription(subscription);
+        return Err(EthRpcError::RefundFailed(e));
+    }
+    Ok(())
+}
+
+// Accepts the attached cycles as the prepaid balance of a background task, which must be
+// at least MINIMUM_PREPAID_BALANCE_CYCLES.
+fn accept_prepaid_balance() -> Result<u128, EthRpcError> {
+    let cycles_available = ic_cdk::api::call::msg_cycles_available128();
+    if cycles_available < MINIMUM_PREPAID_BALANCE_CYCLES {
+        return Err(EthRpcError::TooFewCycles(format!(
+            "requires at least {} cycles, got {} cycles",
+            MINIMUM_PREPAID_BALANCE_CYCLES, cycles_available
+        )));
+    }
+    Ok(ic_cdk::api::call::msg_cycles_accept128(cycles_available))
+}
+
+// Refunds the remaining prepaid balance of a subscription, tracked transaction or deposit
+// watcher, unless it is too small to be worth it.
+async fn refund_balance(owner: Principal, balance: u128) -> Result<(), String> {
+    if balance < MINIMUM_WITHDRAWAL_CYCLES {
+        return Ok(());
+    }
+    ic_cdk::api::call::call_with_payment128::<_, ()>(
+        Principal::management_canister(),
+        "deposit_cycles",
+        (DepositCyclesArgs { canister_id: owner },),
+        balance,
+    )
+    .await
+    .map_err(|(code, message)| {
+        log!(
+            ERROR,
+            "failed to refund {} cycles to {} ({:?}): {}",
+            balance,
+            owner,
+            code,
+            message
+        );
+        format!("refund rejected ({:?}): {}", code, message)
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_subscription(subscription_id: u64) -> Option<Subscription> {
+    SUBSCRIPTIONS.with(|s| s.borrow().get(&subscription_id))
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_subscriptions() -> Vec<Subscription> {
+    let caller = ic_cdk::caller();
+    SUBSCRIPTIONS.with(|s| {
+        s.borrow()
+            .iter()
+            .map(|(_, subscription)| subscription)
+            .filter(|subscription| subscription.owner == caller)
+            .collect()
+    })
+}
+
+// A subscription the caller may manage.
+fn owned_subscription(subscription_id: u64) -> Result<Subscription, EthRpcError> {
+    let subscription =
+        get_subscription(subscription_id).ok_or(EthRpcError::SubscriptionNotFound)?;
+    if subscription.owner != ic_cdk::caller() && !authorized(Auth::Admin) {
+        return Err(EthRpcError::NoPermission);
+    }
+    Ok(subscription)
+}
+
+// Updates a subscription unless it was removed in the meantime.
+fn update_subscription(subscription_id: u64, f: impl FnOnce(&mut Subscription)) {
+    if let Some(mut subscription) = get_subscription(subscription_id) {
+        f(&mut subscription);
+        store_subscription(subscription);
+    }
+}
+
+// Inserts or updates a subscription, keeping SUBSCRIPTION_SCHEDULE in sync.
+fn store_subscription(subscription: Subscription) {
+    let entry = (subscription.next_poll_at_ns, subscription.subscription_id);
+    let active = subscription.status == SubscriptionStatus::Active;
+    let previous = SUBSCRIPTIONS.with(|s| {
+        s.borrow_mut()
+            .insert(subscription.subscription_id, subscription)
+    });
+    SUBSCRIPTION_SCHEDULE.with(|s| {
+        let mut s = s.borrow_mut();
+        if let Some(previous) = previous {
+            s.remove(&(previous.next_poll_at_ns, previous.subscription_id));
+        }
+        if active {
+            s.insert(entry);
+        }
+    });
+}
+
+fn remove_subscription(subscription_id: u64) -> Option<Subscription> {
+    let subscription = SUBSCRIPTIONS.with(|s| s.borrow_mut().remove(&subscription_id))?;
+    SUBSCRIPTION_SCHEDULE.with(|s| {
+        s.borrow_mut()
+            .remove(&(subscription.next_poll_at_ns, subscription_id))
+    });
+    Some(subscription)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+fn track_transaction(request: TrackTransaction) -> Result<u64, EthRpcError> {
+    let caller = ic_cdk::caller();
+    // Tracked transactions are limited per owner, which the anonymous principal cannot be.
+    if !authorized(Auth::Rpc) || caller == Principal::anonymous() {
+        inc_metric!(json_rpc_request_err_no_permission);
+        return Err(EthRpcError::NoPermission);
+    }
+    request.validate().map_err(EthRpcError::InvalidArgument)?;
+    let chain = registered_chain(request.chain_id)?;
+    let tracked_count = TRACKED_TRANSACTIONS.with(|t| {
+        t.borrow()
+            .iter()
+            .filter(|(_, tracked_transaction)| tracked_transaction.owner == caller)
+            .count()
+    });
+    if tracked_count >= MAX_TRACKED_TRANSACTIONS_PER_OWNER {
+        return Err(EthRpcError::InvalidArgument(format!(
+            "an owner may have at most {} tracked transactions",
+            MAX_TRACKED_TRANSACTIONS_PER_OWNER
+        )));
+    }
+    let balance = accept_prepaid_balance()?;
+    let tracking_id = NEXT_TRACKING_ID.with(|n| {
+        let tracking_id = *n.borrow().get();
+        n.borrow_mut().set(tracking_id + 1).unwrap();
+        tracking_id
+    });
+    let tracked_transaction = TrackedTransaction {
+        tracking_id,
+        owner: caller,
+        chain_id: request.chain_id,
+        transaction_hash: request.transaction_hash.to_ascii_lowercase(),
+        replaced_hashes: vec![],
+        mined_transaction_hash: None,
+        confirmations: request.confirmations.unwrap_or(chain.finality_depth),
+        poll_interval_seconds: request
+            .poll_interval_seconds
+            .unwrap_or(chain.block_time_seconds),
+        callback_method: request.callback_method,
+        max_response_bytes: request.max_response_bytes,
+        replacement_policy: request.replacement_policy,
+        status: tx_tracker::TrackedStatus::Pending,
+        from: None,
+        nonce: None,
+        missed_polls: 0,
+        reorgs: 0,
+        balance,
+        next_poll_at_ns: 0,
+        broadcast_at_ns: ic_cdk::api::time(),
+    };
+    store_tracked_transaction(tracked_transaction);
+    Ok(tracking_id)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+fn top_up_tracked_transaction(tracking_id: u64) -> Result<u128, EthRpcError> {
+    let mut balance = 0;
+    update_tracked_transaction(tracking_id, |t| {
+        t.balance +=
+            ic_cdk::api::call::msg_cycles_accept128(ic_cdk::api::call::msg_cycles_available128());
+        balance = t.balance;
+    })
+    .ok_or(EthRpcError::TrackedTransactionNotFound)?;
+    Ok(balance)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn replace_transaction(
+    tracking_id: u64,
+    fee_bump_percent: Option<u64>,
+) -> Result<SendRawTransactionResult, EthRpcError> {
+    if !authorized(Auth::Rpc) {
+        inc_metric!(json_rpc_request_err_no_permission);
+        return Err(EthRpcError::NoPermission);
+    }
+    let tracked_transaction =
+        get_tracked_transaction(tracking_id).ok_or(EthRpcError::TrackedTransactionNotFound)?;
+    if tracked_transaction.owner != ic_cdk::caller() {
+        return Err(EthRpcError::NoPermission);
+    }
+    tracked_transaction
+        .check_replaceable()
+        .map_err(EthRpcError::InvalidArgument)?;
+    let fee_bump_percent = fee_bump_percent.unwrap_or(tx_tracker::MIN_FEE_BUMP_PERCENT);
+    tx_tracker::check_fee_bump_percent(fee_bump_percent).map_err(EthRpcError::InvalidArgument)?;
+    let chain_id = tracked_transaction.chain_id;
+    let transaction = typed_request(
+        chain_id,
+        "eth_getTransactionByHash",
+        serde_json::json!([tracked_transaction.transaction_hash]),
+        tracked_transaction.max_response_bytes,
+    )
+    .await?;
+    if transaction.is_null() {
+        return Err(EthRpcError::InvalidArgument(
+            "the transaction is not known".to_string(),
+        ));
+    }
+    let transaction = rpc_types::transaction(&transaction).map_err(EthRpcError::InvalidResponse)?;
+    let estimate = estimate_fees(chain_id).await?;
+    let replacement =
+        tx_tracker::replacement_transaction(&transaction, &estimate, fee_bump_percent, None)
+            .map_err(EthRpcError::InvalidArgument)?;
+    let raw = sign_replacement(tracked_transaction.owner, &transaction, &replacement).await?;
+    let result = eth_send_raw_transaction(chain_id, eth::to_hex(&raw)).await?;
+    if matches!(
+        result.status,
+        SendRawTransactionStatus::Ok | SendRawTransactionStatus::AlreadyKnown
+    ) {
+        inc_metric!(tracked_transaction_replacements);
+        update_tracked_transaction(tracking_id, |t| {
+            t.replaced(result.transaction_hash.clone(), ic_cdk::api::time())
+        });
+    }
+    Ok(result)
+}
+
+// Signs a replacement of a pending transaction sent from the address of the owner.
+async fn sign_replacement(
+    owner: Principal,
+    transaction: &rpc_types::Transaction,
+    replacement: &Eip1559Transaction,
+) -> Result<Vec<u8>, EthRpcError> {
+    if transaction.block_hash.is_some() {
+        return Err(EthRpcError::InvalidArgument(
+            "the transaction is included in a block".to_string(),
+        ));
+    }
+    let public_key = ecdsa_public_key(owner).await?;
+    let address = ecdsa::eth_address(&public_key).map_err(EthRpcError::SigningError)?;
+    if !transaction
+        .from
+        .eq_ignore_ascii_case(&eth::to_hex(&address))
+    {
+        return Err(EthRpcError::InvalidArgument(format!(
+            "the transaction is not sent from the address of the caller {}",
+            eth::to_hex(&address)
+        )));
+    }
+    let signature = sign_with_ecdsa(owner, replacement.signing_hash()).await?;
+    ecdsa::signed_transaction(replacement, &signature, &public_key)
+        .map_err(EthRpcError::SigningError)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn untrack_transaction(tracking_id: u64) -> Result<(), EthRpcError> {
+    let tracked_transaction =
+        get_tracked_transaction(tracking_id).ok_or(EthRpcError::TrackedTransactionNotFound)?;
+    if tracked_transaction.owner != ic_cdk::caller() && !authorized(Auth::Admin) {
+        return Err(EthRpcError::NoPermission);
+    }
+    // Removed while waiting for the refund, so that it is not polled anymore.
+    remove_tracked_transaction(tracking_id);
+    if let Err(e) = refund_balance(tracked_transaction.owner, tracked_transaction.balance).await {
+        // Kept with its balance, so that the owner can try again.
+        store_tracked_transaction(tracked_transaction);
+        return Err(EthRpcError::RefundFailed(e));
+    }
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_tracked_transaction(tracking_id: u64) -> Option<TrackedTransaction> {
+    TRACKED_TRANSACTIONS.with(|t| t.borrow().get(&tracking_id))
+}
+
+// Updates a tracked transaction unless it was removed in the meantime.
+fn update_tracked_transaction<T>(
+    tracking_id: u64,
+    f: impl FnOnce(&mut TrackedTransaction) -> T,
+) -> Option<T> {
+    let mut tracked_transaction = get_tracked_transaction(tracking_id)?;
+    let result = f(&mut tracked_transaction);
+    store_tracked_transaction(tracked_transaction);
+    Some(result)
+}
+
+// Inserts or updates a tracked transaction, keeping TRACKED_TRANSACTION_SCHEDULE in sync.
+fn store_tracked_transaction(tracked_transaction: TrackedTransaction) {
+    let entry = (
+        tracked_transaction.next_poll_at_ns,
+        tracked_transaction.tracking_id,
+    );
+    let previous = TRACKED_TRANSACTIONS.with(|t| {
+        t.borrow_mut()
+            .insert(tracked_transaction.tracking_id, tracked_transaction)
+    });
+    TRACKED_TRANSACTION_SCHEDULE.with(|s| {
+        let mut s = s.borrow_mut();
+        if let Some(previous) = previous {
+            s.remove(&(previous.next_poll_at_ns, previous.tracking_id));
+        }
+        s.insert(entry);
+    });
+}
+
+fn remove_tracked_transaction(tracking_id: u64) -> Option<TrackedTransaction> {
+    let tracked_transaction = TRACKED_TRANSACTIONS.with(|t| t.borrow_mut().remove(&tracking_id))?;
+    TRACKED_TRANSACTION_SCHEDULE.with(|s| {
+        s.borrow_mut()
+            .remove(&(tracked_transaction.next_poll_at_ns, tracking_id))
+    });
+    Some(tracked_transaction)
+}
+
+async fn poll_transaction(tracking_id: u64) {
+    // Skip the poll if the previous one is still in progress.
+    if !TRANSACTION_POLLS.with(|p| p.borrow_mut().insert(tracking_id)) {
+        return;
+    }
+    if let Err(e) = update_transaction_status(tracking_id).await {
+        inc_metric!(tracked_transaction_errors);
+        log!(
+            ERROR,
+            "failed to update the status of tracked transaction {}: {}",
+            tracking_id,
+            e
+        );
+        update_tracked_transaction(tracking_id, |t| t.schedule_next_poll(ic_cdk::api::time()));
+    }
+    TRANSACTION_POLLS.with(|p| p.borrow_mut().remove(&tracking_id));
+}
+
+async fn update_transaction_status(tracking_id: u64) -> Result<(), String> {
+    let Some(tracked_transaction) = get_tracked_transaction(tracking_id) else {
+        return Ok(());
+    };
+    let debit = move |cost| debit_tracked_transaction(tracking_id, cost);
+    let chain_id = tracked_transaction.chain_id;
+    let hash = serde_json::json!([tracked_transaction.polled_hash()]);
+    let max_response_bytes = tracked_transaction
+        .max_response_bytes
+        .unwrap_or_else(|| default_max_response_bytes(chain_id));
+    let receipt = prepaid_request(
+        chain_id,
+        "eth_getTransactionReceipt",
+        hash.clone(),
+        max_response_bytes,
+        debit,
+    )
+    .await?;
+    let observation = if !receipt.is_null() {
+        // The sender and nonce are needed to tell whether the nonce was used by another
+        // transaction if the transaction is reorged out.
+        if tracked_transaction.nonce.is_none() {
+            let transaction = prepaid_request(
+                chain_id,
+                "eth_getTransactionByHash",
+                hash,
+                max_response_bytes,
+                debit,
+            )
+            .await?;
+            if !transaction.is_null() {
+                let transaction = rpc_types::transaction(&transaction)?;
+                update_tracked_transaction(tracking_id, |t| {
+                    t.seen(transaction.from, transaction.nonce)
+                });
+            }
+        }
+        receipt_observation(chain_id, &receipt, debit).await?
+    } else {
+        let transaction = prepaid_request(
+            chain_id,
+            "eth_getTransactionByHash",
+            hash,
+            max_response_bytes,
+            debit,
+        )
+        .await?;
+        if !transaction.is_null() {
+            let transaction = rpc_types::transaction(&transaction)?;
+            if transaction.block_hash.is_some() {
+                // Included after the receipt was requested: the next poll gets the receipt.
+                update_tracked_transaction(tracking_id, |t| {
+                    t.seen(transaction.from, transaction.nonce);
+                    t.schedule_next_poll(ic_cdk::api::time())
+                });
+                return Ok(());
+            }
+            if tracked_transaction.is_stuck(ic_cdk::api::time()) {
+                replace_stuck_transaction(&tracked_transaction, &transaction, debit).await;
+            }
+            Observation::Pending {
+                from: transaction.from,
+                nonce: transaction.nonce,
+            }
+        } else {
+            let nonce_used = match (&tracked_transaction.from, &tracked_transaction.nonce) {
+                (Some(from), Some(nonce)) => {
+                    let count = prepaid_request(
+                        chain_id,
+                        "eth_getTransactionCount",
+                        serde_json::json!([from, "latest"]),
+                        default_max_response_bytes(chain_id),
+                        debit,
+                    )
+                    .await?;
+                    rpc_types::parse_quantity(&count)? > *nonce
+                }
+                _ => false,
+            };
+            if nonce_used {
+                // The nonce may have been used by the transaction itself since its receipt
+                // was requested, or by one of its replacements.
+                mined_transaction(&tracked_transaction, debit)
+                    .await?
+                    .unwrap_or(Observation::Missing { nonce_used })
+            } else {
+                Observation::Missing { nonce_used }
+            }
+        }
+    };
+    let now = ic_cdk::api::time();
+    let update = update_tracked_transaction(tracking_id, |t| {
+        let notify = t.observe(observation);
+        if t.is_final() {
+            // Not removed before the balance is refunded.
+            t.next_poll_at_ns = u64::MAX;
+        } else {
+            t.schedule_next_poll(now);
+        }
+        let update = TransactionUpdate {
+            tracking_id,
+            chain_id,
+            transaction_hash: t.transaction_hash.clone(),
+            mined_transaction_hash: t.mined_transaction_hash.clone(),
+            status: t.status.clone(),
+        };
+        (notify, update, t.callback_method.clone(), t.is_final())
+    });
+    let Some((notify, update, callback_method, is_final)) = update else {
+        return Ok(());
+    };
+    if let (true, Some(callback_method)) = (notify, callback_method) {
+        // Notifications are one-way messages, so that an unresponsive owner cannot keep the
+        // canister from being upgraded. They are not retried, the status can be queried
+        // instead.
+        let payload_bytes = Encode!(&update).unwrap().len() as u128;
+        if let Err(e) = debit(CANISTER_CALL_COST + CANISTER_CALL_BYTE_COST * payload_bytes) {
+            log!(
+                ERROR,
+                "failed to notify tracked transaction {}: {}",
+                tracking_id,
+                e
+            );
+        } else if let Err(code) =
+            ic_cdk::api::call::notify(tracked_transaction.owner, &callback_method, (update,))
+        {
+            log!(
+                ERROR,
+                "failed to notify tracked transaction {} ({:?})",
+                tracking_id,
+                code
+            );
+        }
+    }
+    if is_final {
+        // Taken while waiting for the refund, so that it is not refunded twice if the
+        // transaction is untracked in the meantime.
+        let Some(balance) =
+            update_tracked_transaction(tracking_id, |t| std::mem::take(&mut t.balance))
+        else {
+            return Ok(());
+        };
+        let now = ic_cdk::api::time();
+        match refund_balance(tracked_transaction.owner, balance).await {
+            Ok(()) => update_tracked_transaction(tracking_id, |t| t.schedule_removal(now)),
+            // Kept with its balance until the owner untracks it.
+            Err(_) => update_tracked_transaction(tracking_id, |t| t.balance += balance),
+        };
+    }
+    Ok(())
+}
+
+async fn receipt_observation(
+    chain_id: u64,
+    receipt: &serde_json::Value,
+    debit: impl Fn(u128) -> Result<(), String>,
+) -> Result<Observation, String> {
+    let receipt = rpc_types::transaction_receipt(receipt)?;
+    Ok(Observation::Receipt {
+        transaction_hash: receipt.transaction_hash.to_ascii_lowercase(),
+        block_number: receipt
+            .block_number
+            .0
+            .to_u64()
+            .ok_or("block number out of range")?,
+        block_hash: receipt.block_hash,
+        status: receipt.status,
+        latest_block: latest_block_number(chain_id, debit).await?,
+    })
+}
+
+// Looks for the receipt of any of the broadcast transactions.
+async fn mined_transaction(
+    tracked_transaction: &TrackedTransaction,
+    debit: impl Fn(u128) -> Result<(), String> + Copy,
+) -> Result<Option<Observation>, String> {
+    let hashes = tracked_transaction
+        .replaced_hashes
+        .iter()
+        .chain(std::iter::once(&tracked_transaction.transaction_hash));
+    for hash in hashes {
+        let receipt = prepaid_request(
+            tracked_transaction.chain_id,
+            "eth_getTransactionReceipt",
+            serde_json::json!([hash]),
+            tracked_transaction
+                .max_response_bytes
+                .unwrap_or_else(|| default_max_response_bytes(tracked_transaction.chain_id)),
+            debit,
+        )
+        .await?;
+        if !receipt.is_null() {
+            return receipt_observation(tracked_transaction.chain_id, &receipt, debit)
+                .await
+                .map(Some);
+        }
+    }
+    Ok(None)
+}
+
+// Applies the replacement policy of a stuck transaction, paid from its balance. Failures
+// are retried once the replacement is stuck again.
+async fn replace_stuck_transaction(
+    tracked_transaction: &TrackedTransaction,
+    transaction: &rpc_types::Transaction,
+    debit: impl Fn(u128) -> Result<(), String> + Copy,
+) {
+    let tracking_id = tracked_transaction.tracking_id;
+    let result = async {
+        let policy = tracked_transaction
+            .replacement_policy
+            .as_ref()
+            .ok_or("no replacement policy")?;
+        let config = FEE_ESTIMATION_CONFIG.with(|c| c.borrow().get().clone());
+        let fee_history = prepaid_request(
+            tracked_transaction.chain_id,
+            "eth_feeHistory",
+            config.fee_history_params(),
+            default_max_response_bytes(tracked_transaction.chain_id),
+            debit,
+        )
+        .await?;
+        let estimate = fees::fee_estimate(&fee_history)?;
+        let replacement = tx_tracker::replacement_transaction(
+            transaction,
+            &estimate,
+            policy.fee_bump_percent,
+            policy.max_fee_per_gas.as_ref(),
+        )?;
+        debit(ECDSA_SIGNATURE_COST)?;
+        let raw = sign_replacement(tracked_transaction.owner, transaction, &replacement)
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        let transaction_hash = prepaid_request(
+            tracked_transaction.chain_id,
+            "eth_sendRawTransaction",
+            serde_json::json!([eth::to_hex(&raw)]),
+            SEND_RAW_TRANSACTION_MAX_RESPONSE_BYTES,
+            debit,
+        )
+        .await?;
+        transaction_hash
+            .as_str()
+            .map(|hash| hash.to_ascii_lowercase())
+            .ok_or_else(|| format!("invalid transaction hash {}", transaction_hash))
+    }
+    .await;
+    let now = ic_cdk::api::time();
+    match result {
+        Ok(transaction_hash) => {
+            inc_metric!(tracked_transaction_replacements);
+            update_tracked_transaction(tracking_id, |t| t.replaced(transaction_hash, now));
+        }
+        Err(e) => {
+            inc_metric!(tracked_transaction_errors);
+            log!(
+                ERROR,
+                "failed to replace tracked transaction {}: {}",
+                tracking_id,
+                e
+            );
+            update_tracked_transaction(tracking_id, |t| t.broadcast_at_ns = now);
+        }
+    }
+}
+
+// Pays from the balance of a tracked transaction.
+fn debit_tracked_transaction(tracking_id: u64, cost: u128) -> Result<(), String> {
+    update_tracked_transaction(tracking_id, |t| {
+        if t.balance < cost {
+            return Err(format!(
+                "requires {} cycles, balance is {} cycles",
+                cost, t.balance
+            ));
+        }
+        t.balance -= cost;
+        Ok(())
+    })
+    .unwrap_or_else(|| Err("tracked transaction not found".to_string()))
+}
+
+// Polls the subscriptions, tracked transactions and deposit watchers that are due.
+fn start_background_timer() {
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(BACKGROUND_TICK_SECONDS), || {
+        let now = ic_cdk::api::time();
+        let subscriptions: Vec<u64> = SUBSCRIPTION_SCHEDULE.with(|s| {
+            s.borrow()
+                .range(..=(now, u64::MAX))
+                .map(|(_, subscription_id)| *subscription_id)
+                .collect()
+        });
+        for subscription_id in subscriptions {
+            ic_cdk::spawn(poll_subscription(subscription_id));
+        }
+        let transactions: Vec<u64> = TRACKED_TRANSACTION_SCHEDULE.with(|s| {
+            s.borrow()
+                .range(..=(now, u64::MAX))
+                .map(|(_, tracking_id)| *tracking_id)
+                .collect()
+        });
+        for tracking_id in transactions {
+            match get_tracked_transaction(tracking_id).map(|t| t.is_final()) {
+                Some(false) => ic_cdk::spawn(poll_transaction(tracking_id)),
+                Some(true) => {
+                    remove_tracked_transaction(tracking_id);
+                }
+                None => (),
+            }
+        }
+        let watchers: Vec<u64> = DEPOSIT_WATCHER_SCHEDULE.with(|s| {
+            s.borrow()
+                .range(..=(now, u64::MAX))
+                .map(|(_, watcher_id)| *watcher_id)
+                .collect()
+        });
+        for watcher_id in watchers {
+            match get_deposit_watcher(watcher_id).map(|w| w.status) {
+                Some(WatcherStatus::Active) => ic_cdk::spawn(poll_deposit_watcher(watcher_id)),
+                Some(WatcherStatus::Paused(_)) => {
+                    ic_cdk::spawn(remove_paused_deposit_watcher(watcher_id))
+                }
+                None => (),
+            }
+        }
+    });
+}
+
+async fn poll_subscription(subscription_id: u64) {
+    // Skip the poll if the previous one is still in progress.
+    if !SUBSCRIPTION_POLLS.with(|p| p.borrow_mut().insert(subscription_id)) {
+        return;
+    }
+    if let Err(e) = deliver_logs(subscription_id).await {
+        inc_metric!(log_subscription_errors);
+        log!(
+            ERROR,
+            "failed to deliver logs of subscription {}: {}",
+            subscription_id,
+            e
+        );
+        update_subscription(subscription_id, |s| {
+            if s.status == SubscriptionStatus::Active {
+                s.attempt_failed(ic_cdk::api::time(), e);
+            }
+        });
+    }
+    SUBSCRIPTION_POLLS.with(|p| p.borrow_mut().remove(&subscription_id));
+}
+
+// Fetches the logs of the next confirmed blocks and delivers them to the subscriber.
+async fn deliver_logs(subscription_id: u64) -> Result<(), String> {
+    let Some(mut subscription) = get_subscription(subscription_id) else {
+        return Ok(());
+    };
+    let debit = move |cost| debit_subscription(subscription_id, cost);
+    let latest_block = latest_block_number(subscription.chain_id, debit).await?;
+    let now = ic_cdk::api::time();
+    if subscription.next_block.is_none() {
+        subscription.init_cursor(latest_block);
+        update_subscription(subscription_id, |s| s.init_cursor(latest_block));
+    }
+    let Some((from_block, to_block)) = subscription.block_range(latest_block) else {
+        update_subscription(subscription_id, |s| s.schedule_next_poll(now));
+        return Ok(());
+    };
+    let logs = prepaid_request(
+        subscription.chain_id,
+        "eth_getLogs",
+        subscription.filter.params(from_block, to_block),
+        subscription
+            .max_response_bytes
+            .unwrap_or(SUBSCRIPTION_MAX_RESPONSE_BYTES),
+        debit,
+    )
+    .await?;
+    let logs = logs
+        .as_array()
+        .ok_or("logs are not an array")?
+        .iter()
+        .map(rpc_types::log)
+        .collect::<Result<Vec<_>, _>>()?;
+    if !logs.is_empty() {
+        let batch = LogBatch {
+            subscription_id,
+            chain_id: subscription.chain_id,
+            from_block,
+            to_block,
+            logs,
+        };
+        let payload_bytes = Encode!(&batch).unwrap().len() as u128;
+        let cost = CANISTER_CALL_COST + CANISTER_CALL_BYTE_COST * payload_bytes;
+        debit_subscription(subscription_id, cost)?;
+        // The cursor only advances once the subscriber accepted the batch, so that a rejected
+        // batch is sent again with the backoff of a failed attempt.
+        ic_cdk::call::<_, ()>(subscription.owner, &subscription.callback_method, (batch,))
+            .await
+            .map_err(|(code, message)| format!("callback rejected ({:?}): {}", code, message))?;
+        inc_metric!(log_subscription_deliveries);
+    }
+    update_subscription(subscription_id, |s| {
+        s.delivered(ic_cdk::api::time(), to_block)
+    });
+    Ok(())
+}
+
+// The latest block number from the header tracker, if the chain is tracked, or else from
+// the cheapest provider.
+async fn latest_block_number(
+    chain_id: u64,
+    debit: impl Fn(u128) -> Result<(), String>,
+) -> Result<u64, String> {
+    if let Some(head) = chain_headers(chain_id).head() {
+        return Ok(head.number);
+    }
+    let block_number = prepaid_request(
+        chain_id,
+        "eth_blockNumber",
+        serde_json::json!([]),
+        default_max_response_bytes(chain_id),
+        debit,
+    )
+    .await?;
+    rpc_types::parse_quantity(&block_number)?
+        .0
+        .to_u64()
+        .ok_or_else(|| "block number out of range".to_string())
+}
+
+// Sends a background request to the cheapest provider of a chain, which is paid for with
+// `debit` instead of the cycles attached to the call.
+async fn prepaid_request(
+    chain_id: u64,
+    method: &str,
+    params: serde_json::Value,
+    max_response_bytes: u64,
+    debit: impl Fn(u128) -> Result<(), String>,
+) -> Result<serde_json::Value, String> {
+    inc_metric!(json_rpc_requests);
+    let mut provider = chain_providers(chain_id)
+        .into_iter()
+        .next()
+        .ok_or("no provider for the chain")?;
+    let payload = json_rpc::request_payload(method, params);
+    check_json_rpc_payload(&payload, Some(&provider)).map_err(|e| format!("{:?}", e))?;
+    let service_url = provider.service_url.clone() + &provider.api_key;
+    let host = service_host(&service_url).map_err(|e| format!("{:?}", e))?;
+    let provider_cost = json_rpc_provider_cycles_cost(
+        &payload,
+        provider.cycles_per_call,
+        provider.cycles_per_message_byte,
+    );
+    debit(json_rpc_cycles_cost(&payload, &service_url, max_response_bytes) + provider_cost)?;
+    provider.cycles_owed += provider_cost;
+    PROVIDERS.with(|p| p.borrow_mut().insert(provider.provider_id, provider));
+    let body = send_json_rpc_request(
+        payload,
+        service_url,
+        host,
+        max_response_bytes,
+        ResponseTransform::Raw,
+    )
+    .await
+    .map_err(|e| format!("{:?}", e))?;
+    json_rpc::response_result(&body)
+        .ok_or_else(|| format!("invalid response of {} bytes", body.len()))
+}
+
+// Pays from the balance of a subscription, pausing it if the balance is too low.
+fn debit_subscription(subscription_id: u64, cost: u128) -> Result<(), String> {
+    let mut result = Err("subscription not found".to_string());
+    update_subscription(subscription_id, |s| {
+        if s.balance < cost {
+            let error = format!("requires {} cycles, balance is {} cycles", cost, s.balance);
+            s.status = SubscriptionStatus::paused(error.clone());
+            result = Err(error);
+        } else {
+            s.balance -= cost;
+            result = Ok(());
+        }
+    });
+    result
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+fn watch_deposits(request: WatchDeposits) -> Result<u64, EthRpcError> {
+    let caller = ic_cdk::caller();
+    // Watchers are limited per owner, which the anonymous principal cannot be.
+    if !authorized(Auth::Rpc) || caller == Principal::anonymous() {
+        inc_metric!(json_rpc_request_err_no_permission);
+        return Err(EthRpcError::NoPermission);
+    }
+    request.validate().map_err(EthRpcError::InvalidArgument)?;
+    let chain = registered_chain(request.chain_id)?;
+    let watcher_count = DEPOSIT_WATCHERS.with(|w| {
+        w.borrow()
+            .iter()
+            .filter(|(_, watcher)| watcher.owner == caller)
+            .count()
+    });
+    if watcher_count >= MAX_DEPOSIT_WATCHERS_PER_OWNER {
+        return Err(EthRpcError::InvalidArgument(format!(
+            "an owner may have at most {} deposit watchers",
+            MAX_DEPOSIT_WATCHERS_PER_OWNER
+        )));
+    }
+    let balance = accept_prepaid_balance()?;
+    let watcher_id = NEXT_WATCHER_ID.with(|n| {
+        let watcher_id = *n.borrow().get();
+        n.borrow_mut().set(watcher_id + 1).unwrap();
+        watcher_id
+    });
+    store_deposit_watcher(DepositWatcher::new(
+        watcher_id, caller, request, &chain, balance,
+    ));
+    Ok(watcher_id)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+fn top_up_deposit_watcher(watcher_id: u64) -> Result<u128, EthRpcError> {
+    let mut watcher = get_deposit_watcher(watcher_id).ok_or(EthRpcError::DepositWatcherNotFound)?;
+    watcher.balance +=
+        ic_cdk::api::call::msg_cycles_accept128(ic_cdk::api::call::msg_cycles_available128());
+    let balance = watcher.balance;
+    store_deposit_watcher(watcher);
+    Ok(balance)
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+fn resume_deposit_watcher(watcher_id: u64) -> Result<(), EthRpcError> {
+    owned_deposit_watcher(watcher_id)?;
+    update_deposit_watcher(watcher_id, |w| {
+        w.status = WatcherStatus::Active;
+        w.failed_attempts = 0;
+        w.next_poll_at_ns = 0;
+    });
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+#[candid_method]
+async fn unwatch_deposits(watcher_id: u64) -> Result<(), EthRpcError> {
+    owned_deposit_watcher(watcher_id)?;
+    close_deposit_watcher(watcher_id)
+        .await
+        .map_err(EthRpcError::RefundFailed)?;
+    remove_deposits(watcher_id);
+    Ok(())
+}
+
+// Removes a deposit watcher that was not resumed in time, keeping its recorded deposits.
+async fn remove_paused_deposit_watcher(watcher_id: u64) {
+    if close_deposit_watcher(watcher_id).await.is_ok() {
+        log!(INFO, "removed paused deposit watcher {}", watcher_id);
+    }
+}
+
+// Refunds the balance of a deposit watcher and removes it. If the refund fails, the watcher
+// is kept paused with its balance, so that the owner can try again.
+async fn close_deposit_watcher(watcher_id: u64) -> Result<(), String> {
+    // Removed while waiting for the refund, so that it is not polled anymore.
+    let Some(mut watcher) = remove_deposit_watcher(watcher_id) else {
+        return Ok(());
+    };
+    if let Err(e) = refund_balance(watcher.owner, watcher.balance).await {
+        watcher.pause(ic_cdk::api::time(), e.clone());
+        store_deposit_watcher(watcher);
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn remove_deposits(watcher_id: u64) {
+    DEPOSITS.with(|d| {
+        let mut d = d.borrow_mut();
+        let keys: Vec<DepositKey> = d
+            .range(deposit_key_range(watcher_id))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            d.remove(&key);
+        }
+    });
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_deposit_watcher(watcher_id: u64) -> Option<DepositWatcher> {
+    DEPOSIT_WATCHERS.with(|w| w.borrow().get(&watcher_id))
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_deposit_watchers() -> Vec<DepositWatcher> {
+    let caller = ic_cdk::caller();
+    DEPOSIT_WATCHERS.with(|w| {
+        w.borrow()
+            .iter()
+            .map(|(_, watcher)| watcher)
+            .filter(|watcher| watcher.owner == caller)
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_deposits(watcher_id: u64, offset: u64, limit: u64) -> Result<Vec<Deposit>, EthRpcError> {
+    DEPOSITS.with(|d| {
+        let d = d.borrow();
+        // The deposits of a watcher removed after being paused are kept.
+        if get_deposit_watcher(watcher_id).is_none()
+            && d.range(deposit_key_range(watcher_id)).next().is_none()
+        {
+            return Err(EthRpcError::DepositWatcherNotFound);
+        }
+        Ok(d.range(deposit_key_range(watcher_id))
+            .skip(offset as usize)
+            .take(limit.min(MAX_DEPOSITS_PER_PAGE) as usize)
+            .map(|(_, deposit)| deposit)
+            .collect())
+    })
+}
+
+fn deposit_key_range(watcher_id: u64) -> std::ops::RangeInclusive<DepositKey> {
+    DepositKey(watcher_id, 0, 0, 0)..=DepositKey(watcher_id, u64::MAX, u64::MAX, u64::MAX)
+}
+
+// A deposit watcher the caller may manage.
+fn owned_deposit_watcher(watcher_id: u64) -> Result<DepositWatcher, EthRpcError> {
+    let watcher = get_deposit_watcher(watcher_id).ok_or(EthRpcError::DepositWatcherNotFound)?;
+    if watcher.owner != ic_cdk::caller() && !authorized(Auth::Admin) {
+        return Err(EthRpcError::NoPermission);
+    }
+    Ok(watcher)
+}
+
+// Updates a deposit watcher unless it was removed in the meantime.
+fn update_deposit_watcher(watcher_id: u64, f: impl FnOnce(&mut DepositWatcher)) {
+    if let Some(mut watcher) = get_deposit_watcher(watcher_id) {
+        f(&mut watcher);
+        store_deposit_watcher(watcher);
+    }
+}
+
+// Inserts or updates a deposit watcher, keeping DEPOSIT_WATCHER_SCHEDULE in sync.
+fn store_deposit_watcher(watcher: DepositWatcher) {
+    let entry = (watcher.next_poll_at_ns, watcher.watcher_id);
+    let previous = DEPOSIT_WATCHERS.with(|w| w.borrow_mut().insert(watcher.watcher_id, watcher));
+    DEPOSIT_WATCHER_SCHEDULE.with(|s| {
+        let mut s = s.borrow_mut();
+        if let Some(previous) = previous {
+            s.remove(&(previous.next_poll_at_ns, previous.watcher_id));
+        }
+        s.insert(entry);
+    });
+}
+
+fn remove_deposit_watcher(watcher_id: u64) -> Option<DepositWatcher> {
+    let watcher = DEPOSIT_WATCHERS.with(|w| w.borrow_mut().remove(&watcher_id))?;
+    DEPOSIT_WATCHER_SCHEDULE.with(|s| {
+        s.borrow_mut()
+            .remove(&(watcher.next_poll_at_ns, watcher_id))
+    });
+    Some(watcher)
+}
+
+async fn poll_deposit_watcher(watcher_id: u64) {
+    // Skip the poll if the previous one is still in progress.
+    if !DEPOSIT_WATCHER_POLLS.with(|p| p.borrow_mut().insert(watcher_id)) {
+        return;
+    }
+    if let Err(e) = scan_deposits(watcher_id).await {
+        inc_metric!(deposit_watcher_errors);
+        log!(
+            ERROR,
+            "failed to scan deposits of watcher {}: {}",
+            watcher_id,
+            e
+        );
+        update_deposit_watcher(watcher_id, |w| {
+            if w.status == WatcherStatus::Active {
+                w.attempt_failed(ic_cdk::api::time(), e);
+            }
+        });
+    }
+    DEPOSIT_WATCHER_POLLS.with(|p| p.borrow_mut().remove(&watcher_id));
+}
+
+// Records the deposits in the next confirmed blocks and delivers them to the callback, if
+// any. If the callback rejects the batch, the blocks are scanned again and their deposits
+// delivered again, but not recorded twice.
+async fn scan_deposits(watcher_id: u64) -> Result<(), String> {
+    let Some(mut watcher) = get_deposit_watcher(watcher_id) else {
+        return Ok(());
+    };
+    let debit = move |cost| debit_deposit_watcher(watcher_id, cost);
+    let latest_block = latest_block_number(watcher.chain_id, debit).await?;
+    let now = ic_cdk::api::time();
+    if watcher.next_block.is_none() {
+        watcher.init_cursor(latest_block);
+        update_deposit_watcher(watcher_id, |w| w.init_cursor(latest_block));
+    }
+    let Some((from_block, to_block)) = watcher.block_range(latest_block) else {
+        update_deposit_watcher(watcher_id, |w| w.schedule_next_poll(now));
+        return Ok(());
+    };
+    let mut deposits = vec![];
+    if !watcher.tokens.is_empty() {
+        let logs = prepaid_request(
+            watcher.chain_id,
+            "eth_getLogs",
+            watcher.log_filter().params(from_block, to_block),
+            watcher
+                .max_response_bytes
+                .unwrap_or(SUBSCRIPTION_MAX_RESPONSE_BYTES),
+            debit,
+        )
+        .await?;
+        for log in logs.as_array().ok_or("logs are not an array")? {
+            deposits.extend(watcher.token_deposit(&rpc_types::log(log)?)?);
+        }
+    }
+    if watcher.native {
+        for block_number in from_block..=to_block {
+            let block = prepaid_request(
+                watcher.chain_id,
+                "eth_getBlockByNumber",
+                serde_json::json!([format!("{:#x}", block_number), true]),
+                watcher
+                    .max_response_bytes
+                    .unwrap_or(BLOCK_RECEIPTS_MAX_RESPONSE_BYTES),
+                debit,
+            )
+            .await?;
+            for deposit in watcher.native_deposits(&rpc_types::block(&block)?)? {
+                let receipt = prepaid_request(
+                    watcher.chain_id,
+                    "eth_getTransactionReceipt",
+                    serde_json::json!([deposit.transaction_hash]),
+                    watcher
+                        .max_response_bytes
+                        .unwrap_or_else(|| default_max_response_bytes(watcher.chain_id)),
+                    debit,
+                )
+                .await?;
+                let receipt = rpc_types::transaction_receipt(&receipt)?;
+                if receipt.status == Some(TransactionStatus::Success) {
+                    deposits.push(deposit);
+                }
+            }
+        }
+    }
+    deposits.sort_by_key(Deposit::key);
+    DEPOSITS.with(|d| {
+        let mut d = d.borrow_mut();
+        for deposit in &deposits {
+            let (watcher_id, block_number, transaction_index, position) = deposit.key();
+            let key = DepositKey(watcher_id, block_number, transaction_index, position);
+            if !d.contains_key(&key) {
+                d.insert(key, deposit.clone());
+                inc_metric!(deposits_recorded);
+            }
+        }
+    });
+    if let Some(callback_method) = &watcher.callback_method {
+        if !deposits.is_empty() {
+            let batch = DepositBatch {
+                watcher_id,
+                chain_id: watcher.chain_id,
+                from_block,
+                to_block,
+                deposits,
+            };
+            let payload_bytes = Encode!(&batch).unwrap().len() as u128;
+            let cost = CANISTER_CALL_COST + CANISTER_CALL_BYTE_COST * payload_bytes;
+            debit_deposit_watcher(watcher_id, cost)?;
+            ic_cdk::call::<_, ()>(watcher.owner, callback_method, (batch,))
+                .await
+                .map_err(|(code, message)| {
+                    format!("callback rejected ({:?}): {}", code, message)
+                })?;
+        }
+    }
+    update_deposit_watcher(watcher_id, |w| w.scanned(ic_cdk::api::time(), to_block));
+    Ok(())
+}
+
+// Pays from the balance of a deposit watcher, pausing it if the balance is too low.
+fn debit_deposit_watcher(watcher_id: u64, cost: u128) -> Result<(), String> {
+    let mut result = Err("deposit watcher not found".to_string());
+    update_deposit_watcher(watcher_id, |w| {
+        if w.balance < cost {
+            let error = format!("requires {} cycles, balance is {} cycles", cost, w.balance);
+            w.pause(ic_cdk::api::time(), error.clone());
+            result = Err(error);
+        } else {
+            w.balance -= cost;
+            result = Ok(());
+        }
+    });
+    result
+}
+
+#[ic_cdk::update(guard = "is_authorized")]
+#[candid_method]
+fn set_fee_estimation_config(config: FeeEstimationConfig) {
+    if let Err(e) = config.validate() {
+        ic_cdk::trap(&e);
+    }
+    FEE_ESTIMATION_CONFIG.with(|c| c.borrow_mut().set(config).unwrap());
+    FEE_ESTIMATES.with(|f| f.borrow_mut().clear());
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_fee_estimation_config() -> FeeEstimationConfig {
+    FEE_ESTIMATION_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+// Sends a request to the cheapest provider registered for the chain and returns the
+// `result` of the response.
+async fn typed_request(
+    chain_id: u64,
+    method: &str,
+    params: serde_json::Value,
+    max_response_bytes: Option<u64>,
+) -> Result<serde_json::Value, EthRpcError> {
+    consensus_request(chain_id, method, params, max_response_bytes, 1).await
+}
+
+// Sends a request to the given number of cheapest providers registered for the chain and
+// returns the `result` of the responses, which must all be equal.
+async fn consensus_request(
+    chain_id: u64,
+    method: &str,
+    params: serde_json::Value,
+    max_response_bytes: Option<u64>,
+    provider_count: u32,
+) -> Result<serde_json::Value, EthRpcError> {
+    let providers = chain_providers(chain_id);
+    if providers.is_empty() {
+        return Err(EthRpcError::ProviderNotFound);
+    }
+    if provider_count == 0 || provider_count as usize > providers.len() {
+        return Err(EthRpcError::InvalidArgument(format!(
+            "providers must be between 1 and {}, the number of providers of chain {}",
+            providers.len(),
+            chain_id
+        )));
+    }
+    let payload = json_rpc::request_payload(method, params);
+    let mut first: Option<(u64, serde_json::Value)> = None;
+    for provider in providers.into_iter().take(provider_count as usize) {
+        let provider_id = provider.provider_id;
+        let body = provider_request(
+            provider,
+            payload.clone(),
+            max_response_bytes.unwrap_or_else(|| default_max_response_bytes(chain_id)),
+            ResponseTransform::Raw,
+        )
+        .await?;
+        let result = json_rpc::response_result(&body)
+            .ok_or_else(|| EthRpcError::InvalidResponse("missing result".to_string()))?;
+        match &first {
+            None => first = Some((provider_id, result)),
+            Some((first_id, first_result)) if *first_result != result => {
+                return Err(EthRpcError::InconsistentResults(format!(
+                    "providers {} and {} returned different results",
+                    first_id, provider_id
+                )));
+            }
+            Some(_) => (),
+        }
+    }
+    Ok(first.expect("no provider").1)
+}
+
+// Registered providers for a chain, cheapest first.
+fn chain_providers(chain_id: u64) -> Vec<Provider> {
+    let mut providers = PROVIDERS.with(|p| {
+        p.borrow()
+            .iter()
+            .map(|(_, provider)| provider)
+            .filter(|provider| provider.chain_id == chain_id)
+            .collect::<Vec<Provider>>()
+    });
+    providers.sort_by_key(|p| (p.cycles_per_call, p.cycles_per_message_byte, p.provider_id));
+    providers
 }
 
-#[macro_export]
-macro_rules! get_metric {
-    ($metric:ident) => {{
-        METRICS.with(|m| m.borrow().$metric)
-    }};
+async fn provider_request(
+    provider: Provider,
+    json_rpc_payload: String,
+    max_response_bytes: u64,
+    response_transform: ResponseTransform,
+) -> Result<Vec<u8>, EthRpcError> {
+    let service_url = provider.service_url.clone() + &provider.api_key;
+    json_rpc_request_internal(
+        json_rpc_payload,
+        service_url,
+        max_response_bytes,
+        Some(provider),
+        response_transform,
+    )
+    .await
 }
 
-#[ic_cdk_macros::update]
-#[candid_method]
-async fn json_rpc_request(
+fn check_json_rpc_request(
+    json_rpc_payload: &str,
+    provider: Option<&Provider>,
+) -> Result<Vec<JsonRpcRequest>, EthRpcError> {
+    if !authorized(Auth::Rpc) {
+        inc_metric!(json_rpc_request_err_no_permission);
+        return Err(EthRpcError::NoPermission);
+    }
+    check_json_rpc_payload(json_rpc_payload, provider)
+}
+
+fn check_json_rpc_payload(
+    json_rpc_payload: &str,
+    provider: Option<&Provider>,
+) -> Result<Vec<JsonRpcRequest>, EthRpcError> {
+    let requests = json_rpc::parse_payload(json_rpc_payload).map_err(|e| {
+        inc_metric!(json_rpc_request_err_invalid_payload);
+        EthRpcError::InvalidPayload(e)
+    })?;
+    let method_policy = provider
+        .and_then(|provider| METHOD_POLICIES.with(|m| m.borrow().get(&provider.provider_id)))
+        .unwrap_or_default();
+    if let Some(request) = requests.iter().find(|r| !method_policy.allows(&r.method)) {
+        inc_metric!(json_rpc_request_err_method_not_allowed);
+        return Err(EthRpcError::MethodNotAllowed(request.method.clone()));
+    }
+    let chain = provider.and_then(|provider| get_chain(provider.chain_id));
+    if let Some(chain) = chain {
+        if let Some(request) = requests.iter().find(|r| !chain.supports(&r.method)) {
+            inc_metric!(json_rpc_request_err_method_not_allowed);
+            return Err(EthRpcError::MethodNotAllowed(request.method.clone()));
+        }
+    }
+    Ok(requests)
+}
+
+async fn json_rpc_request_internal(
     json_rpc_payload: String,
     service_url: String,
     max_response_bytes: u64,
+    provider: Option<Provider>,
+    response_transform: ResponseTransform,
 ) -> Result<Vec<u8>, EthRpcError> {
-    json_rpc_request_internal(json_rpc_payload, service_url, max_response_bytes, None).await
+    inc_metric!(json_rpc_requests);
+    check_json_rpc_request(&json_rpc_payload, provider.as_ref())?;
+    send_checked_json_rpc_request(
+        json_rpc_payload,
+        service_url,
+        max_response_bytes,
+        provider,
+        response_transform,
+    )
+    .await
 }
 
-#[ic_cdk_macros::update]
-#[candid_method]
-async fn json_rpc_provider_request(
+// Sends a request to a provider whose payload was checked with check_json_rpc_request.
+async fn checked_provider_request(
+    provider: Provider,
     json_rpc_payload: String,
-    provider_id: u64,
     max_response_bytes: u64,
 ) -> Result<Vec<u8>, EthRpcError> {
-    let provider = PROVIDERS.with(|p| {
-        p.borrow()
-            .get(&provider_id)
-            .ok_or(EthRpcError::ProviderNotFound)
-    });
-    let provider = provider?;
+    inc_metric!(json_rpc_requests);
     let service_url = provider.service_url.clone() + &provider.api_key;
-    json_rpc_request_internal(
+    send_checked_json_rpc_request(
         json_rpc_payload,
         service_url,
         max_response_bytes,
         Some(provider),
+        ResponseTransform::Raw,
     )
     .await
 }
 
-async fn json_rpc_request_internal(
+async fn send_checked_json_rpc_request(
     json_rpc_payload: String,
     service_url: String,
     max_response_bytes: u64,
     provider: Option<Provider>,
+    response_transform: ResponseTransform,
 ) -> Result<Vec<u8>, EthRpcError> {
-    inc_metric!(json_rpc_requests);
-    if !authorized(Auth::Rpc) {
-        inc_metric!(json_rpc_request_err_no_permission);
-        return Err(EthRpcError::NoPermission);
-    }
-    let cycles_available = ic_cdk::api::call::msg_cycles_available128();
-    let parsed_url = url::Url::parse(&service_url).or(Err(EthRpcError::ServiceUrlParseError))?;
-    let host = parsed_url
-        .host_str()
-        .ok_or(EthRpcError::ServiceUrlHostMissing)?
-        .to_string();
+    let host = service_host(&service_url)?;
     // if SERVICE_HOSTS_ALLOWLIST.with(|a| !a.borrow().contains(&host.as_str())) {
     //     log!(INFO, "host not allowed {}", host);
     //     inc_metric!(json_rpc_request_err_service_url_host_not_allowed);
@@ -334,13 +3547,7 @@ async fn json_rpc_request_internal(
         };
         let cost = json_rpc_cycles_cost(&json_rpc_payload, &service_url, max_response_bytes)
             + provider_cost;
-        if cycles_available < cost {
-            return Err(EthRpcError::TooFewCycles(format!(
-                "requires {} cycles, got {} cycles",
-                cost, cycles_available
-            )));
-        }
-        ic_cdk::api::call::msg_cycles_accept128(cost);
+        charge_cycles(cost)?;
         if let Some(mut provider) = provider {
             provider.cycles_owed += provider_cost;
             PROVIDERS.with(|p| {
@@ -350,9 +3557,33 @@ async fn json_rpc_request_internal(
                     .expect("unable to update Provider");
             });
         }
-        add_metric!(json_rpc_request_cycles_charged, cost);
-        add_metric!(json_rpc_request_cycles_refunded, cycles_available - cost);
     }
+    send_json_rpc_request(
+        json_rpc_payload,
+        service_url,
+        host,
+        max_response_bytes,
+        response_transform,
+    )
+    .await
+}
+
+fn service_host(service_url: &str) -> Result<String, EthRpcError> {
+    let parsed_url = url::Url::parse(service_url).or(Err(EthRpcError::ServiceUrlParseError))?;
+    Ok(parsed_url
+        .host_str()
+        .ok_or(EthRpcError::ServiceUrlHostMissing)?
+        .to_string())
+}
+
+// Makes the HTTPS outcall of a request that has already been paid for.
+async fn send_json_rpc_request(
+    json_rpc_payload: String,
+    service_url: String,
+    host: String,
+    max_response_bytes: u64,
+    response_transform: ResponseTransform,
+) -> Result<Vec<u8>, EthRpcError> {
     inc_metric_entry!(json_rpc_host_requests, host);
     let request_headers = vec![
         HttpHeader {
@@ -370,10 +3601,13 @@ async fn json_rpc_request_internal(
         method: HttpMethod::POST,
         headers: request_headers,
         body: Some(json_rpc_payload.as_bytes().to_vec()),
-        transform: Some(TransformContext::new(transform, vec![])),
+        transform: Some(TransformContext::new(
+            transform,
+            Encode!(&response_transform).unwrap(),
+        )),
     };
     match make_http_request(request).await {
-        Ok((result,)) => Ok(result.body),
+        Ok((result,)) => json_rpc_response(result),
         Err((r, m)) => {
             inc_metric!(json_rpc_request_err_http_request_error);
             Err(EthRpcError::HttpRequestError {
@@ -384,6 +3618,40 @@ async fn json_rpc_request_internal(
     }
 }
 
+fn json_rpc_response(response: HttpResponse) -> Result<Vec<u8>, EthRpcError> {
+    let status = response.status.0.to_u16().unwrap_or(u16::MAX);
+    if status != 200 {
+        inc_metric!(json_rpc_request_err_http_status);
+        return Err(EthRpcError::HttpStatus {
+            status,
+            body: String::from_utf8_lossy(&response.body).into_owned(),
+        });
+    }
+    if let Some(error) = json_rpc::response_error(&response.body) {
+        inc_metric!(json_rpc_request_err_json_rpc_error);
+        return Err(EthRpcError::JsonRpcError {
+            code: error.code,
+            data: error.data_text(),
+            message: error.message,
+        });
+    }
+    Ok(response.body)
+}
+
+fn charge_cycles(cost: u128) -> Result<(), EthRpcError> {
+    let cycles_available = ic_cdk::api::call::msg_cycles_available128();
+    if cycles_available < cost {
+        return Err(EthRpcError::TooFewCycles(format!(
+            "requires {} cycles, got {} cycles",
+            cost, cycles_available
+        )));
+    }
+    ic_cdk::api::call::msg_cycles_accept128(cost);
+    add_metric!(json_rpc_request_cycles_charged, cost);
+    add_metric!(json_rpc_request_cycles_refunded, cycles_available - cost);
+    Ok(())
+}
+
 fn json_rpc_cycles_cost(
     json_rpc_payload: &str,
     service_url: &str,
@@ -397,6 +3665,14 @@ fn json_rpc_cycles_cost(
         + HTTP_OUTCALL_BYTE_RECEIEVED_COST * (ingress_bytes + max_response_bytes as u128)
 }
 
+fn json_rpc_cache_hit_cycles_cost(json_rpc_payload: &str, result_bytes: usize) -> u128 {
+    let ingress_bytes = json_rpc_payload.len() as u128 + INGRESS_OVERHEAD_BYTES;
+    INGRESS_MESSAGE_RECEIVED_COST
+        + INGRESS_MESSAGE_BYTE_RECEIVED_COST * ingress_bytes
+        + RESPONSE_CACHE_HIT_COST
+        + RESPONSE_CACHE_BYTE_COST * result_bytes as u128
+}
+
 fn json_rpc_provider_cycles_cost(
     json_rpc_payload: &str,
     provider_cycles_per_call: u64,
@@ -407,20 +3683,251 @@ fn json_rpc_provider_cycles_cost(
         + json_rpc_payload.len() as u128
 }
 
+fn next_response_cache_sequence() -> u64 {
+    RESPONSE_CACHE_STATE.with(|s| {
+        let mut state = s.borrow().get().clone();
+        state.next_sequence += 1;
+        s.borrow_mut().set(state.clone()).unwrap();
+        state.next_sequence - 1
+    })
+}
+
+fn is_expired(entry: &ResponseCacheEntry) -> bool {
+    entry
+        .expires_at_ns
+        .map_or(false, |expires_at_ns| expires_at_ns <= ic_cdk::api::time())
+}
+
+fn response_cache_get(key: &CacheKey) -> Option<Vec<u8>> {
+    let key = CacheKeyStorable(*key);
+    let mut entry = RESPONSE_CACHE.with(|c| c.borrow().get(&key))?;
+    if is_expired(&entry) {
+        response_cache_remove(&key);
+        return None;
+    }
+    let sequence = next_response_cache_sequence();
+    RESPONSE_CACHE_LRU.with(|l| {
+        let mut lru = l.borrow_mut();
+        lru.remove(&entry.last_used);
+        lru.insert(sequence, key.clone());
+    });
+    entry.last_used = sequence;
+    RESPONSE_CACHE.with(|c| c.borrow_mut().insert(key, entry.clone()));
+    Some(entry.result)
+}
+
+fn response_cache_insert(key: CacheKey, result: Vec<u8>, expires_at_ns: Option<u64>) {
+    let max_entries = RESPONSE_CACHE_STATE.with(|s| s.borrow().get().max_entries);
+    if max_entries == 0 {
+        return;
+    }
+    let entry = ResponseCacheEntry {
+        result,
+        last_used: next_response_cache_sequence(),
+        expires_at_ns,
+    };
+    if Encode!(&entry).unwrap().len() > RESPONSE_CACHE_ENTRY_MAX_SIZE as usize {
+        return;
+    }
+    update_certified_data(|c| c.insert_response(&key, &entry.result));
+    if let Some(expires_at_ns) = expires_at_ns {
+        RESPONSE_CACHE_EXPIRY.with(|e| e.borrow_mut().insert((expires_at_ns, key)));
+    }
+    let key = CacheKeyStorable(key);
+    let sequence = entry.last_used;
+    if let Some(previous) = RESPONSE_CACHE.with(|c| c.borrow_mut().insert(key.clone(), entry)) {
+        RESPONSE_CACHE_LRU.with(|l| l.borrow_mut().remove(&previous.last_used));
+        if let Some(expires_at_ns) = previous.expires_at_ns {
+            RESPONSE_CACHE_EXPIRY.with(|e| e.borrow_mut().remove(&(expires_at_ns, key.0)));
+        }
+    }
+    RESPONSE_CACHE_LRU.with(|l| l.borrow_mut().insert(sequence, key));
+    response_cache_evict(max_entries);
+}
+
+// Removes expired entries, so that they are no longer certified, then evicts least recently
+// used entries until at most `max_entries` remain.
+fn response_cache_evict(max_entries: u64) {
+    let now = ic_cdk::api::time();
+    while let Some((_, key)) = RESPONSE_CACHE_EXPIRY.with(|e| {
+        let first = e.borrow().first().copied();
+        first.filter(|(expires_at_ns, _)| *expires_at_ns <= now)
+    }) {
+        response_cache_remove(&CacheKeyStorable(key));
+    }
+    while RESPONSE_CACHE.with(|c| c.borrow().len()) > max_entries {
+        let (_, key) = RESPONSE_CACHE_LRU
+            .with(|l| l.borrow().iter().next())
+            .expect("response cache LRU out of sync");
+        response_cache_remove(&key);
+        inc_metric!(json_rpc_cache_evictions);
+    }
+}
+
+fn response_cache_remove(key: &CacheKeyStorable) {
+    let entry = RESPONSE_CACHE
+        .with(|c| c.borrow_mut().remove(key))
+        .expect("response cache out of sync");
+    RESPONSE_CACHE_LRU.with(|l| l.borrow_mut().remove(&entry.last_used));
+    if let Some(expires_at_ns) = entry.expires_at_ns {
+        RESPONSE_CACHE_EXPIRY.with(|e| e.borrow_mut().remove(&(expires_at_ns, key.0)));
+    }
+    update_certified_data(|c| c.remove_response(&key.0));
+}
+
+#[ic_cdk::update(guard = "is_authorized")]
+#[candid_method]
+fn set_response_cache_max_entries(max_entries: u64) {
+    RESPONSE_CACHE_STATE.with(|s| {
+        let mut state = s.borrow().get().clone();
+        state.max_entries = max_entries;
+        s.borrow_mut().set(state).unwrap();
+    });
+    response_cache_evict(max_entries);
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_certified_response(
+    chain_id: u64,
+    json_rpc_payload: String,
+) -> Result<Option<CertifiedResponse>, EthRpcError> {
+    let requests = check_json_rpc_request(&json_rpc_payload, None)?;
+    let key = match requests.as_slice() {
+        [request] => cache::cache_key(chain_id, request),
+        _ => None,
+    };
+    let Some(key) = key else {
+        return Ok(None);
+    };
+    let entry = RESPONSE_CACHE
+        .with(|c| c.borrow().get(&CacheKeyStorable(key)))
+        .filter(|entry| !is_expired(entry));
+    Ok(entry.map(|entry| CertifiedResponse {
+        cache_key: key.to_vec(),
+        result: entry.result,
+        certificate: ic_cdk::api::data_certificate(),
+        witness: CERTIFIED_DATA.with(|c| c.borrow().response_witness(&key)),
+    }))
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_certified_providers() -> CertifiedProviders {
+    CertifiedProviders {
+        providers: get_providers(),
+        certificate: ic_cdk::api::data_certificate(),
+        witness: CERTIFIED_DATA.with(|c| c.borrow().providers_witness()),
+    }
+}
+
+fn update_certified_data(f: impl FnOnce(&mut CertifiedData)) {
+    CERTIFIED_DATA.with(|c| {
+        let mut certified_data = c.borrow_mut();
+        f(&mut certified_data);
+        ic_cdk::api::set_certified_data(&certified_data.root_hash());
+    });
+}
+
+fn certify_provider(certified_data: &mut CertifiedData, provider: Provider) {
+    let provider_id = provider.provider_id;
+    let registered_provider = Encode!(&registered_provider(provider)).unwrap();
+    certified_data.insert_provider(provider_id, &registered_provider);
+}
+
+fn registered_provider(provider: Provider) -> RegisteredProvider {
+    RegisteredProvider {
+        provider_id: provider.provider_id,
+        owner: provider.owner,
+        chain_id: provider.chain_id,
+        service_url: provider.service_url,
+        cycles_per_call: provider.cycles_per_call,
+        cycles_per_message_byte: provider.cycles_per_message_byte,
+    }
+}
+
+#[ic_cdk::update(guard = "is_authorized")]
+#[candid_method]
+fn register_chain(chain: Chain) {
+    if let Err(e) = chain.validate() {
+        ic_cdk::trap(&e);
+    }
+    CHAINS.with(|c| c.borrow_mut().insert(chain.chain_id, chain));
+}
+
+#[ic_cdk::update(guard = "is_authorized")]
+#[candid_method]
+fn unregister_chain(chain_id: u64) {
+    if !chain_providers(chain_id).is_empty() {
+        ic_cdk::trap("Chain has providers");
+    }
+    CHAINS.with(|c| c.borrow_mut().remove(&chain_id));
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_chain(chain_id: u64) -> Option<Chain> {
+    CHAINS.with(|c| c.borrow().get(&chain_id))
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_chains() -> Vec<Chain> {
+    CHAINS.with(|c| c.borrow().iter().map(|(_, chain)| chain).collect())
+}
+
+// The chain of the background tasks, which provides their defaults and must have providers.
+fn registered_chain(chain_id: u64) -> Result<Chain, EthRpcError> {
+    let chain = get_chain(chain_id).ok_or(EthRpcError::ChainNotFound)?;
+    if chain_providers(chain_id).is_empty() {
+        return Err(EthRpcError::ProviderNotFound);
+    }
+    Ok(chain)
+}
+
+// Registers the chains of providers registered before the chain registry.
+fn migrate_chains() {
+    let chain_ids: BTreeSet<u64> = PROVIDERS.with(|p| {
+        p.borrow()
+            .iter()
+            .map(|(_, provider)| provider.chain_id)
+            .collect()
+    });
+    for chain_id in chain_ids {
+        if get_chain(chain_id).is_some() {
+            continue;
+        }
+        let chain = Chain {
+            chain_id,
+            name: format!("Chain {}", chain_id),
+            native_currency: chains::NativeCurrency {
+                name: "Ether".to_string(),
+                symbol: "ETH".to_string(),
+                decimals: 18,
+            },
+            block_time_seconds: 12,
+            finality_depth: 64,
+            default_max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            supported_methods: None,
+            multicall_address: None,
+        };
+        CHAINS.with(|c| c.borrow_mut().insert(chain_id, chain));
+    }
+}
+
+fn default_max_response_bytes(chain_id: u64) -> u64 {
+    get_chain(chain_id).map_or(DEFAULT_MAX_RESPONSE_BYTES, |chain| {
+        chain.default_max_response_bytes
+    })
+}
+
 #[ic_cdk::query]
 #[candid_method(query)]
 fn get_providers() -> Vec<RegisteredProvider> {
     PROVIDERS.with(|p| {
         p.borrow()
             .iter()
-            .map(|(_, e)| RegisteredProvider {
-                provider_id: e.provider_id,
-                owner: e.owner,
-                chain_id: e.chain_id,
-                service_url: e.service_url,
-                cycles_per_call: e.cycles_per_call,
-                cycles_per_message_byte: e.cycles_per_message_byte,
-            })
+            .map(|(_, e)| registered_provider(e))
             .collect::<Vec<RegisteredProvider>>()
     })
 }
@@ -428,36 +3935,39 @@ fn get_providers() -> Vec<RegisteredProvider> {
 #[ic_cdk::update(guard = "is_authorized_register_provider")]
 #[candid_method]
 fn register_provider(provider: RegisterProvider) {
+    if get_chain(provider.chain_id).is_none() {
+        ic_cdk::trap("Chain not found");
+    }
     let provider_id = METADATA.with(|m| {
         let mut metadata = m.borrow().get().clone();
         metadata.next_provider_id += 1;
         m.borrow_mut().set(metadata.clone()).unwrap();
         metadata.next_provider_id - 1
     });
-    PROVIDERS.with(|p| {
-        p.borrow_mut().insert(
-            provider_id,
-            Provider {
-                provider_id,
-                owner: ic_cdk::caller(),
-                chain_id: provider.chain_id,
-                service_url: provider.service_url,
-                api_key: provider.api_key,
-                cycles_per_call: provider.cycles_per_call,
-                cycles_per_message_byte: provider.cycles_per_message_byte,
-                cycles_owed: 0,
-            },
-        )
-    });
+    let provider = Provider {
+        provider_id,
+        owner: ic_cdk::caller(),
+        chain_id: provider.chain_id,
+        service_url: provider.service_url,
+        api_key: provider.api_key,
+        cycles_per_call: provider.cycles_per_call,
+        cycles_per_message_byte: provider.cycles_per_message_byte,
+        cycles_owed: 0,
+    };
+    PROVIDERS.with(|p| p.borrow_mut().insert(provider_id, provider.clone()));
+    update_certified_data(|c| certify_provider(c, provider));
 }
 
 #[ic_cdk::update(guard = "is_authorized_register_provider")]
 #[candid_method]
 fn unregister_provider(provider_id: u64) {
     PROVIDERS.with(|p| {
-        if let Some(provider) = p.borrow().get(&provider_id) {
+        let provider = p.borrow().get(&provider_id);
+        if let Some(provider) = provider {
             if provider.owner == ic_cdk::caller() || authorized(Auth::Admin) {
                 p.borrow_mut().remove(&provider_id);
+                METHOD_POLICIES.with(|m| m.borrow_mut().remove(&provider_id));
+                update_certified_data(|c| c.remove_provider(provider_id));
             } else {
                 ic_cdk::trap("Not authorized");
             }
@@ -465,6 +3975,33 @@ fn unregister_provider(provider_id: u64) {
     });
 }
 
+#[ic_cdk::update(guard = "is_authorized")]
+#[candid_method]
+fn set_method_policy(provider_id: u64, method_policy: Option<MethodPolicy>) {
+    if !PROVIDERS.with(|p| p.borrow().contains_key(&provider_id)) {
+        ic_cdk::trap("Provider not found");
+    }
+    METHOD_POLICIES.with(|m| match method_policy {
+        Some(method_policy) => {
+            if Encode!(&method_policy).unwrap().len() > METHOD_POLICY_MAX_SIZE as usize {
+                ic_cdk::trap("Method policy too large");
+            }
+            m.borrow_mut().insert(provider_id, method_policy);
+        }
+        None => {
+            m.borrow_mut().remove(&provider_id);
+        }
+    });
+}
+
+#[ic_cdk::query]
+#[candid_method(query)]
+fn get_method_policy(provider_id: u64) -> MethodPolicy {
+    METHOD_POLICIES
+        .with(|m| m.borrow().get(&provider_id))
+        .unwrap_or_default()
+}
+
 #[ic_cdk::query(guard = "is_authorized_register_provider")]
 #[candid_method(query)]
 fn get_owed_cycles(provider_id: u64) -> u128 {
@@ -521,9 +4058,20 @@ async fn withdraw_owed_cycles(provider_id: u64, canister_id: Principal) {
 
 #[ic_cdk_macros::query(name = "transform")]
 fn transform(args: TransformArgs) -> HttpResponse {
+    let response_transform =
+        Decode!(&args.context, ResponseTransform).unwrap_or(ResponseTransform::Raw);
+    let status = args.response.status;
+    let body = match response_transform {
+        // Providers word errors differently, so only the normalized status is kept.
+        ResponseTransform::SendRawTransaction if status.0.to_u16() == Some(200) => Encode!(
+            &transaction::send_raw_transaction_status(&args.response.body)
+        )
+        .unwrap(),
+        _ => args.response.body,
+    };
     HttpResponse {
-        status: args.response.status.clone(),
-        body: args.response.body,
+        status,
+        body,
         // Strip headers as they contain the Date which is not necessarily the same
         // and will prevent consensus on the result.
         headers: Vec::<HttpHeader>::new(),
@@ -538,6 +4086,7 @@ fn init() {
 #[ic_cdk_macros::post_upgrade]
 fn post_upgrade() {
     initialize();
+    migrate_chains();
     // Remove these lines when ic_cdk::api::is_controller is supported.
     authorize(ic_cdk::caller(), Auth::Admin);
     authorize(ic_cdk::caller(), Auth::RegisterProvider);
@@ -562,6 +4111,60 @@ fn initialize() {
     for principal in AUTHORIZED_ADMIN.iter() {
         authorize(to_principal(principal), Auth::Admin);
     }
+
+    HEADER_TRACKERS.with(|h| {
+        for (_, config) in h.borrow().iter() {
+            start_header_tracker(&config);
+        }
+    });
+    SUBSCRIPTIONS.with(|subscriptions| {
+        SUBSCRIPTION_SCHEDULE.with(|s| {
+            s.borrow_mut().extend(
+                subscriptions
+                    .borrow()
+                    .iter()
+                    .filter(|(_, subscription)| subscription.status == SubscriptionStatus::Active)
+                    .map(|(subscription_id, subscription)| {
+                        (subscription.next_poll_at_ns, subscription_id)
+                    }),
+            )
+        })
+    });
+    TRACKED_TRANSACTIONS.with(|t| {
+        TRACKED_TRANSACTION_SCHEDULE.with(|s| {
+            s.borrow_mut().extend(
+                t.borrow()
+                    .iter()
+                    .map(|(tracking_id, t)| (t.next_poll_at_ns, tracking_id)),
+            )
+        })
+    });
+    DEPOSIT_WATCHERS.with(|w| {
+        DEPOSIT_WATCHER_SCHEDULE.with(|s| {
+            s.borrow_mut().extend(
+                w.borrow()
+                    .iter()
+                    .map(|(watcher_id, watcher)| (watcher.next_poll_at_ns, watcher_id)),
+            )
+        })
+    });
+    start_background_timer();
+
+    update_certified_data(|c| {
+        PROVIDERS.with(|p| {
+            for (_, provider) in p.borrow().iter() {
+                certify_provider(c, provider);
+            }
+        });
+        RESPONSE_CACHE.with(|r| {
+            for (key, entry) in r.borrow().iter() {
+                c.insert_response(&key.0, &entry.result);
+                if let Some(expires_at_ns) = entry.expires_at_ns {
+                    RESPONSE_CACHE_EXPIRY.with(|e| e.borrow_mut().insert((expires_at_ns, key.0)));
+                }
+            }
+        });
+    });
 }
 
 fn to_principal(principal: &str) -> Principal {
@@ -709,6 +4312,86 @@ fn encode_metrics(w: &mut ic_metrics_encoder::MetricsEncoder<Vec<u8>>) -> std::i
         get_metric!(json_rpc_request_cycles_refunded) as f64,
         "Cycles refunded by json_rpc_request() calls.",
     )?;
+    w.encode_counter(
+        "json_rpc_request_err_http_status",
+        get_metric!(json_rpc_request_err_http_status) as f64,
+        "Number of json_rpc_request() calls answered with an HTTP status other than 200.",
+    )?;
+    w.encode_counter(
+        "json_rpc_request_err_json_rpc_error",
+        get_metric!(json_rpc_request_err_json_rpc_error) as f64,
+        "Number of json_rpc_request() calls answered with a JSON-RPC error.",
+    )?;
+    w.encode_counter(
+        "json_rpc_cache_hits",
+        get_metric!(json_rpc_cache_hits) as f64,
+        "Number of json_rpc_cached_request() calls served from the response cache.",
+    )?;
+    w.encode_counter(
+        "json_rpc_cache_misses",
+        get_metric!(json_rpc_cache_misses) as f64,
+        "Number of cacheable json_rpc_cached_request() calls not found in the response cache.",
+    )?;
+    w.encode_counter(
+        "json_rpc_cache_evictions",
+        get_metric!(json_rpc_cache_evictions) as f64,
+        "Number of entries evicted from the response cache.",
+    )?;
+    w.encode_counter(
+        "header_tracker_reorgs",
+        get_metric!(header_tracker_reorgs) as f64,
+        "Number of chain reorganizations detected by the header tracker.",
+    )?;
+    w.encode_counter(
+        "header_tracker_resets",
+        get_metric!(header_tracker_resets) as f64,
+        "Number of times the header tracker restarted from the latest block of a chain.",
+    )?;
+    w.encode_counter(
+        "header_tracker_errors",
+        get_metric!(header_tracker_errors) as f64,
+        "Number of header tracker polls that failed.",
+    )?;
+    w.encode_counter(
+        "header_tracker_cycles_spent",
+        get_metric!(header_tracker_cycles_spent) as f64,
+        "Cycles paid by the canister for the requests of the header tracker.",
+    )?;
+    w.encode_counter(
+        "log_subscription_deliveries",
+        get_metric!(log_subscription_deliveries) as f64,
+        "Number of log batches delivered to subscribers.",
+    )?;
+    w.encode_counter(
+        "log_subscription_errors",
+        get_metric!(log_subscription_errors) as f64,
+        "Number of failed log subscription polls and deliveries.",
+    )?;
+    w.encode_counter(
+        "tracked_transaction_errors",
+        get_metric!(tracked_transaction_errors) as f64,
+        "Number of failed tracked transaction polls.",
+    )?;
+    w.encode_counter(
+        "tracked_transaction_replacements",
+        get_metric!(tracked_transaction_replacements) as f64,
+        "Number of tracked transactions replaced with bumped fees.",
+    )?;
+    w.encode_counter(
+        "deposits_recorded",
+        get_metric!(deposits_recorded) as f64,
+        "Number of deposits recorded by deposit watchers.",
+    )?;
+    w.encode_counter(
+        "deposit_watcher_errors",
+        get_metric!(deposit_watcher_errors) as f64,
+        "Number of failed deposit watcher polls and deliveries.",
+    )?;
+    w.encode_gauge(
+        "json_rpc_cache_entries",
+        RESPONSE_CACHE.with(|c| c.borrow().len()) as f64,
+        "Number of entries in the response cache.",
+    )?;
     METRICS.with(|m| {
         m.borrow()
             .json_rpc_host_requests
@@ -771,3 +4454,12 @@ fn check_json_rpc_cycles_cost() {
         base_cost_s10
     )
 }
+
+#[test]
+fn check_json_rpc_cache_hit_cycles_cost() {
+    let payload = "{\"jsonrpc\":\"2.0\",\"method\":\"eth_chainId\",\"params\":[],\"id\":1}";
+    assert!(
+        json_rpc_cache_hit_cycles_cost(payload, 1000)
+            < json_rpc_cycles_cost(payload, "https://cloudflare-eth.com", 1000)
+    );
+}
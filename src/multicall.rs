@@ -0,0 +1,197 @@
+//! Batching of contract calls into one call of the `aggregate3` function of Multicall3.
+
+use crate::abi::{self, AbiType, AbiValue, RevertReason, Signature};
+use crate::eth::{from_hex, to_hex};
+use candid::{CandidType, Deserialize};
+
+/// The address Multicall3 is deployed at on most chains.
+pub const DEFAULT_ADDRESS: [u8; 20] = [
+    0xca, 0x11, 0xbd, 0xe0, 0x59, 0x77, 0xb3, 0x63, 0x11, 0x67, 0x02, 0x88, 0x62, 0xbe, 0x2a, 0x17,
+    0x39, 0x76, 0xca, 0x11,
+];
+pub const MAX_CALLS: usize = 256;
+const AGGREGATE3: &str = "aggregate3((address,bool,bytes)[])";
+const AGGREGATE3_RETURNS: &str = "((bool,bytes)[])";
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct MulticallCall {
+    pub target: String,
+    // Solidity function signature, e.g. `balanceOf(address)`.
+    pub function: String,
+    pub args: Vec<AbiValue>,
+    // Parenthesized return types, e.g. `(uint256)`.
+    pub returns: String,
+    // Otherwise the whole batch reverts if the call reverts.
+    pub allow_failure: bool,
+    // Signatures of the custom errors the call may revert with.
+    pub errors: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum CallResult {
+    Ok(Vec<AbiValue>),
+    Reverted(RevertReason),
+    // The call succeeded but its return data does not match the return types.
+    InvalidReturnData { return_data: String, error: String },
+}
+
+/// How to decode the result of a call.
+pub struct PreparedCall {
+    returns: Vec<AbiType>,
+    errors: Vec<Signature>,
+}
+
+/// The calldata of `aggregate3` for the calls.
+pub fn encode(calls: &[MulticallCall]) -> Result<(Vec<u8>, Vec<PreparedCall>), String> {
+    if calls.is_empty() || calls.len() > MAX_CALLS {
+        return Err(format!("expected between 1 and {} calls", MAX_CALLS));
+    }
+    let mut prepared = Vec::with_capacity(calls.len());
+    let mut encoded_calls = Vec::with_capacity(calls.len());
+    for (i, call) in calls.iter().enumerate() {
+        let prepare = || -> Result<(AbiValue, PreparedCall), String> {
+            let data = Signature::parse(&call.function)?.encode_call(&call.args)?;
+            let errors = call
+                .errors
+                .iter()
+                .map(|error| Signature::parse(error))
+                .collect::<Result<_, _>>()?;
+            Ok((
+                AbiValue::Tuple(vec![
+                    AbiValue::Address(call.target.clone()),
+                    AbiValue::Bool(call.allow_failure),
+                    AbiValue::Bytes(to_hex(&data)),
+                ]),
+                PreparedCall {
+                    returns: abi::parse_type_list(&call.returns)?,
+                    errors,
+                },
+            ))
+        };
+        let (encoded_call, prepared_call) = prepare().map_err(|e| format!("call {}: {}", i, e))?;
+        encoded_calls.push(encoded_call);
+        prepared.push(prepared_call);
+    }
+    let data = Signature::parse(AGGREGATE3)
+        .expect("invalid aggregate3 signature")
+        .encode_call(&[AbiValue::Array(encoded_calls)])?;
+    Ok((data, prepared))
+}
+
+/// Decodes the return data of `aggregate3` into the results of the calls.
+pub fn decode(output: &[u8], prepared: &[PreparedCall]) -> Result<Vec<CallResult>, String> {
+    let returns = abi::parse_type_list(AGGREGATE3_RETURNS).expect("invalid aggregate3 returns");
+    let results = match abi::decode(&returns, output)?.pop() {
+        Some(AbiValue::Array(results)) => results,
+        _ => unreachable!("decoded an array"),
+    };
+    if results.len() != prepared.len() {
+        return Err(format!(
+            "expected {} results, found {}",
+            prepared.len(),
+            results.len()
+        ));
+    }
+    results
+        .into_iter()
+        .zip(prepared)
+        .map(|(result, call)| {
+            let (success, return_data) = match result {
+                AbiValue::Tuple(values) => match values.as_slice() {
+                    [AbiValue::Bool(success), AbiValue::Bytes(return_data)] => {
+                        (*success, return_data.clone())
+                    }
+                    _ => unreachable!("decoded a (bool,bytes) tuple"),
+                },
+                _ => unreachable!("decoded a (bool,bytes) tuple"),
+            };
+            let data = from_hex(&return_data)?;
+            if !success {
+                return Ok(CallResult::Reverted(abi::decode_revert(
+                    &data,
+                    &call.errors,
+                )));
+            }
+            Ok(match abi::decode(&call.returns, &data) {
+                Ok(values) => CallResult::Ok(values),
+                Err(error) => CallResult::InvalidReturnData { return_data, error },
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn check_multicall() {
+    use candid::Nat;
+    let token = format!("0x{}", "11".repeat(20));
+    let owner = format!("0x{}", "22".repeat(20));
+    let call = |function: &str, returns: &str| MulticallCall {
+        target: token.clone(),
+        function: function.to_string(),
+        args: vec![AbiValue::Address(owner.clone())],
+        returns: returns.to_string(),
+        allow_failure: true,
+        errors: vec![],
+    };
+    let calls = vec![
+        call("balanceOf(address)", "(uint256)"),
+        call("balanceOf(address)", "(uint256)"),
+        call("balanceOf(address)", "(string)"),
+    ];
+    let (data, prepared) = encode(&calls).unwrap();
+    assert_eq!(data[..4], [0x82, 0xad, 0x56, 0xcb]);
+    let args = abi::decode(
+        &abi::parse_type_list("((address,bool,bytes)[])").unwrap(),
+        &data[4..],
+    )
+    .unwrap();
+    let AbiValue::Array(encoded_calls) = &args[0] else {
+        panic!("expected an array");
+    };
+    assert_eq!(encoded_calls.len(), 3);
+    assert_eq!(
+        encoded_calls[0],
+        AbiValue::Tuple(vec![
+            AbiValue::Address(token.clone()),
+            AbiValue::Bool(true),
+            AbiValue::Bytes(to_hex(
+                &Signature::parse("balanceOf(address)")
+                    .unwrap()
+                    .encode_call(&[AbiValue::Address(owner.clone())])
+                    .unwrap()
+            )),
+        ])
+    );
+
+    let balance = abi::encode(&[AbiType::Uint(256)], &[AbiValue::Uint(Nat::from(5u64))]).unwrap();
+    let revert = abi::encode(
+        &[AbiType::String],
+        &[AbiValue::String("paused".to_string())],
+    )
+    .unwrap();
+    let revert = [vec![0x08, 0xc3, 0x79, 0xa0], revert].concat();
+    let result = |success: bool, data: &[u8]| {
+        AbiValue::Tuple(vec![AbiValue::Bool(success), AbiValue::Bytes(to_hex(data))])
+    };
+    let output = abi::encode(
+        &abi::parse_type_list(AGGREGATE3_RETURNS).unwrap(),
+        &[AbiValue::Array(vec![
+            result(true, &balance),
+            result(false, &revert),
+            result(true, &balance),
+        ])],
+    )
+    .unwrap();
+    let results = decode(&output, &prepared).unwrap();
+    assert_eq!(
+        results[0],
+        CallResult::Ok(vec![AbiValue::Uint(Nat::from(5u64))])
+    );
+    assert_eq!(
+        results[1],
+        CallResult::Reverted(RevertReason::Error("paused".to_string()))
+    );
+    assert!(matches!(results[2], CallResult::InvalidReturnData { .. }));
+    assert!(decode(&output, &prepared[..2]).is_err());
+    assert!(encode(&[]).is_err());
+}
@@ -0,0 +1,137 @@
+//! Nonces of the addresses the canister signs for, reserved so that concurrent calls do not
+//! send transactions with the same nonce.
+
+use candid::{CandidType, Deserialize};
+
+// Time after which the on-chain pending nonce is requested again before a reservation.
+pub const RECONCILE_INTERVAL_NS: u64 = 60_000_000_000;
+// Time after the last reservation after which reserved nonces the chain does not know
+// are considered lost, e.g., because signing failed or the transaction was dropped.
+pub const RESERVATION_TIMEOUT_NS: u64 = 300_000_000_000;
+const MAX_RELEASED_NONCES: usize = 64;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub struct NonceState {
+    // The nonce following all reserved nonces.
+    pub next_nonce: u64,
+    // Reserved nonces that were released, lowest first, reserved again before `next_nonce`.
+    pub released: Vec<u64>,
+    pub last_reserved_at_ns: u64,
+    pub last_reconciled_at_ns: u64,
+}
+
+impl NonceState {
+    pub fn needs_reconcile(&self, now_ns: u64) -> bool {
+        now_ns.saturating_sub(self.last_reconciled_at_ns) >= RECONCILE_INTERVAL_NS
+    }
+
+    /// Adjusts the state to the number of transactions of the address including the pending
+    /// ones, as returned by `eth_getTransactionCount`.
+    pub fn reconcile(&mut self, pending_count: u64, now_ns: u64) {
+        if pending_count >= self.next_nonce
+            || now_ns.saturating_sub(self.last_reserved_at_ns) >= RESERVATION_TIMEOUT_NS
+        {
+            // Either transactions were sent without reservations, or reserved nonces were
+            // lost.
+            self.next_nonce = pending_count;
+            self.released.clear();
+        } else {
+            // Released nonces the chain knows were used by other transactions.
+            self.released.retain(|nonce| *nonce >= pending_count);
+        }
+        self.last_reconciled_at_ns = now_ns;
+    }
+
+    pub fn reserve(&mut self, now_ns: u64) -> u64 {
+        self.last_reserved_at_ns = now_ns;
+        if self.released.is_empty() {
+            self.next_nonce += 1;
+            self.next_nonce - 1
+        } else {
+            self.released.remove(0)
+        }
+    }
+
+    /// Makes a reserved nonce available again, e.g., because its transaction was not sent.
+    pub fn release(&mut self, nonce: u64) -> Result<(), String> {
+        if nonce >= self.next_nonce || self.released.contains(&nonce) {
+            return Err(format!("nonce {} is not reserved", nonce));
+        }
+        if nonce + 1 == self.next_nonce {
+            self.next_nonce = nonce;
+            while let Some(last) = self.released.last() {
+                if last + 1 != self.next_nonce {
+                    break;
+                }
+                self.next_nonce = *last;
+                self.released.pop();
+            }
+            return Ok(());
+        }
+        if self.released.len() >= MAX_RELEASED_NONCES {
+            return Err(format!(
+                "at most {} nonces can be released",
+                MAX_RELEASED_NONCES
+            ));
+        }
+        let index = self.released.partition_point(|n| *n < nonce);
+        self.released.insert(index, nonce);
+        Ok(())
+    }
+
+    /// Forces the next reservation to reconcile with the chain, e.g., after a nonce was
+    /// reported as too low.
+    pub fn invalidate(&mut self) {
+        self.last_reconciled_at_ns = 0;
+    }
+}
+
+#[test]
+fn check_reserve_and_release() {
+    let mut state = NonceState::default();
+    assert!(state.needs_reconcile(RECONCILE_INTERVAL_NS));
+    state.reconcile(5, 1);
+    assert!(!state.needs_reconcile(2));
+    assert_eq!(
+        (1..=4).map(|_| state.reserve(2)).collect::<Vec<_>>(),
+        vec![5, 6, 7, 8]
+    );
+    state.release(6).unwrap();
+    state.release(7).unwrap();
+    assert!(state.release(7).is_err());
+    assert!(state.release(9).is_err());
+    assert_eq!(state.released, vec![6, 7]);
+    state.release(8).unwrap();
+    assert_eq!(state.next_nonce, 6);
+    assert!(state.released.is_empty());
+    assert_eq!(state.reserve(3), 6);
+
+    state.release(5).unwrap();
+    assert_eq!(state.reserve(3), 5);
+    assert_eq!(state.reserve(3), 7);
+}
+
+#[test]
+fn check_reconcile() {
+    let mut state = NonceState::default();
+    state.reconcile(3, 0);
+    for _ in 0..3 {
+        state.reserve(0);
+    }
+    state.release(3).unwrap();
+    // Pending reservations are kept, released nonces used elsewhere are dropped.
+    state.reconcile(4, 1);
+    assert_eq!((state.next_nonce, state.released.clone()), (6, vec![]));
+    state.release(4).unwrap();
+    state.reconcile(4, 2);
+    assert_eq!((state.next_nonce, state.released.clone()), (6, vec![4]));
+    // Transactions sent without reservations.
+    state.reconcile(10, 3);
+    assert_eq!((state.next_nonce, state.released.clone()), (10, vec![]));
+    // Lost reservations.
+    state.reserve(4);
+    state.reconcile(10, 4 + RESERVATION_TIMEOUT_NS);
+    assert_eq!(state.next_nonce, 10);
+    state.invalidate();
+    assert!(state.needs_reconcile(5 + RESERVATION_TIMEOUT_NS));
+}
@@ -0,0 +1,979 @@
+//! Verification of provider responses against block headers, so that values are only
+//! trusted as much as the block hash they were verified against.
+
+use crate::ecdsa;
+use crate::eth::{from_hex, keccak256, to_hex};
+use crate::rlp::{self, Rlp};
+use crate::rpc_types::{Block, JsonObject, Log, TransactionReceipt, TransactionStatus};
+use crate::trie::{self, EMPTY_TRIE_ROOT};
+use candid::{CandidType, Deserialize, Nat};
+use num::BigUint;
+use serde_json::Value;
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct StorageSlot {
+    pub key: String,
+    pub value: Nat,
+}
+
+/// Account state proven against the state root of a block.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct VerifiedAccount {
+    pub block_number: Nat,
+    pub block_hash: String,
+    pub address: String,
+    pub nonce: Nat,
+    pub balance: Nat,
+    pub storage_hash: String,
+    pub code_hash: String,
+    pub storage: Vec<StorageSlot>,
+}
+
+/// Checks that the hash of a block is the hash of its header fields.
+pub fn verify_block_hash(block: &Block) -> Result<(), String> {
+    if to_hex(&block_header_hash(block)?) != block.hash {
+        return Err(format!(
+            "header of block {} does not match its hash",
+            block.hash
+        ));
+    }
+    Ok(())
+}
+
+/// The hash of the RLP encoded header of a block.
+pub fn block_header_hash(block: &Block) -> Result<[u8; 32], String> {
+    let mut fields = vec![
+        from_hex(&block.parent_hash)?,
+        from_hex(&block.sha3_uncles)?,
+        from_hex(&block.miner)?,
+        from_hex(&block.state_root)?,
+        from_hex(&block.transactions_root)?,
+        from_hex(&block.receipts_root)?,
+        from_hex(&block.logs_bloom)?,
+        scalar(&block.difficulty),
+        scalar(&block.number),
+        scalar(&block.gas_limit),
+        scalar(&block.gas_used),
+        scalar(&block.timestamp),
+        from_hex(&block.extra_data)?,
+        from_hex(&block.mix_hash)?,
+        from_hex(&block.nonce)?,
+    ];
+    // Fields added by later forks, in the order they were introduced.
+    let fork_fields = [
+        block.base_fee_per_gas.as_ref().map(|fee| Ok(scalar(fee))),
+        block.withdrawals_root.as_deref().map(from_hex),
+        block.blob_gas_used.as_ref().map(|gas| Ok(scalar(gas))),
+        block.excess_blob_gas.as_ref().map(|gas| Ok(scalar(gas))),
+        block.parent_beacon_block_root.as_deref().map(from_hex),
+        block.requests_hash.as_deref().map(from_hex),
+    ];
+    for field in fork_fields.into_iter().flatten() {
+        fields.push(field?);
+    }
+    let header = Rlp::List(fields.iter().map(|field| Rlp::Bytes(field)).collect());
+    Ok(keccak256(&rlp::encode(&header)))
+}
+
+/// Verifies an `eth_getProof` result for the given address and storage keys against the
+/// state root of the block and returns the proven account state.
+pub fn verify_account_proof(
+    block: &Block,
+    address: &str,
+    storage_keys: &[String],
+    proof: &Value,
+) -> Result<VerifiedAccount, String> {
+    let proof = JsonObject::new(proof)?;
+    let address_bytes = from_hex(address)?;
+    if address_bytes.len() != 20 {
+        return Err(format!("invalid address {}", address));
+    }
+    let account_proof = proof_nodes(proof.array("accountProof")?)?;
+    let account = trie::verify_proof(
+        &hash(&block.state_root)?,
+        &keccak256(&address_bytes),
+        &account_proof,
+    )?;
+    let (nonce, balance, storage_hash, code_hash) = match account {
+        Some(account) => {
+            let account = rlp::decode(&account)?;
+            match account.as_list()? {
+                [nonce, balance, storage_hash, code_hash] => (
+                    nat(nonce.as_bytes()?),
+                    nat(balance.as_bytes()?),
+                    to_hash(storage_hash.as_bytes()?)?,
+                    to_hash(code_hash.as_bytes()?)?,
+                ),
+                _ => return Err("invalid account".to_string()),
+            }
+        }
+        // Accounts that do not exist are empty.
+        None => (
+            Nat::from(0u64),
+            Nat::from(0u64),
+            EMPTY_TRIE_ROOT,
+            keccak256(b""),
+        ),
+    };
+    let storage_proofs = proof.array("storageProof")?;
+    if storage_proofs.len() != storage_keys.len() {
+        return Err(format!(
+            "expected {} storage proofs, found {}",
+            storage_keys.len(),
+            storage_proofs.len()
+        ));
+    }
+    let storage = storage_keys
+        .iter()
+        .zip(storage_proofs)
+        .map(|(key, storage_proof)| {
+            let slot = storage_slot(key)?;
+            let nodes = proof_nodes(JsonObject::new(storage_proof)?.array("proof")?)?;
+            let value = match trie::verify_proof(&storage_hash, &keccak256(&slot), &nodes)? {
+                Some(value) => nat(rlp::decode(&value)?.as_bytes()?),
+                None => Nat::from(0u64),
+            };
+            Ok(StorageSlot {
+                key: to_hex(&slot),
+                value,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(VerifiedAccount {
+        block_number: block.number.clone(),
+        block_hash: block.hash.clone(),
+        address: to_hex(&address_bytes),
+        nonce,
+        balance,
+        storage_hash: to_hex(&storage_hash),
+        code_hash: to_hex(&code_hash),
+        storage,
+    })
+}
+
+/// Verifies that the receipts, ordered by transaction index, are all the receipts of the
+/// block by rebuilding the receipts trie and comparing its root with the header.
+pub fn verify_receipts_root(block: &Block, receipts: &[TransactionReceipt]) -> Result<(), String> {
+    let mut entries = Vec::with_capacity(receipts.len());
+    for (index, receipt) in receipts.iter().enumerate() {
+        if receipt.transaction_index != index as u64 || receipt.block_hash != block.hash {
+            return Err(format!(
+                "receipt of transaction {} is not at index {} of block {}",
+                receipt.transaction_hash, index, block.hash
+            ));
+        }
+        let key = rlp::encode(&Rlp::Bytes(&scalar(&receipt.transaction_index)));
+        entries.push((key, receipt_encoding(receipt)?));
+    }
+    if to_hex(&trie::trie_root(&entries)) != block.receipts_root {
+        return Err(format!(
+            "receipts do not match the receipts root of block {}",
+            block.hash
+        ));
+    }
+    Ok(())
+}
+
+/// The consensus encoding of a receipt as stored in the receipts trie.
+pub fn receipt_encoding(receipt: &TransactionReceipt) -> Result<Vec<u8>, String> {
+    let status_or_root = match (&receipt.status, &receipt.root) {
+        (Some(TransactionStatus::Success), _) => vec![1],
+        (Some(TransactionStatus::Failure), _) => vec![],
+        (None, Some(root)) => from_hex(root)?,
+        (None, None) => return Err("receipt has neither status nor root".to_string()),
+    };
+    let cumulative_gas_used = scalar(&receipt.cumulative_gas_used);
+    let logs_bloom = from_hex(&receipt.logs_bloom)?;
+    let logs = receipt
+        .logs
+        .iter()
+        .map(|log| {
+            let topics = log
+                .topics
+                .iter()
+                .map(|topic| from_hex(topic))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((from_hex(&log.address)?, topics, from_hex(&log.data)?))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let encoded = rlp::encode(&Rlp::List(vec![
+        Rlp::Bytes(&status_or_root),
+        Rlp::Bytes(&cumulative_gas_used),
+        Rlp::Bytes(&logs_bloom),
+        Rlp::List(
+            logs.iter()
+                .map(|(address, topics, data)| {
+                    Rlp::List(vec![
+                        Rlp::Bytes(address),
+                        Rlp::List(topics.iter().map(|topic| Rlp::Bytes(topic)).collect()),
+                        Rlp::Bytes(data),
+                    ])
+                })
+                .collect(),
+        ),
+    ]));
+    // Typed receipts (EIP-2718) are prefixed with the transaction type.
+    match scalar(&receipt.transaction_type).as_slice() {
+        [] => Ok(encoded),
+        [tx_type @ 0x01..=0x7f] => Ok([vec![*tx_type], encoded].concat()),
+        _ => Err(format!(
+            "invalid transaction type {}",
+            receipt.transaction_type
+        )),
+    }
+}
+
+/// A transaction of a block, re-encoded from its JSON representation as stored in the
+/// transactions trie.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockTransaction {
+    pub hash: [u8; 32],
+    pub transaction_type: u8,
+    pub nonce: Nat,
+    // `None` for contract creations.
+    pub to: Option<[u8; 20]>,
+    // Set for legacy and EIP-2930 transactions.
+    pub gas_price: Option<Nat>,
+    // Set for EIP-1559 and later transaction types.
+    pub max_fee_per_gas: Option<Nat>,
+    pub max_priority_fee_per_gas: Option<Nat>,
+    pub blob_versioned_hashes: Option<Vec<[u8; 32]>>,
+    encoding: Vec<u8>,
+    signing_hash: [u8; 32],
+    signature: [u8; 64],
+    y_parity: bool,
+}
+
+impl BlockTransaction {
+    /// Recovers the sender from the signature.
+    pub fn sender(&self) -> Result<[u8; 20], String> {
+        ecdsa::recover_address(&self.signing_hash, &self.signature, self.y_parity)
+    }
+
+    /// The price per gas paid by the sender in a block with the given base fee.
+    pub fn effective_gas_price(&self, base_fee_per_gas: Option<&Nat>) -> Result<Nat, String> {
+        match (
+            &self.gas_price,
+            &self.max_fee_per_gas,
+            &self.max_priority_fee_per_gas,
+            base_fee_per_gas,
+        ) {
+            (Some(gas_price), _, _, _) => Ok(gas_price.clone()),
+            (None, Some(max_fee), Some(priority_fee), Some(base_fee)) => {
+                Ok(Nat(max_fee.0.clone().min(&base_fee.0 + &priority_fee.0)))
+            }
+            _ => Err(format!(
+                "no gas price for transaction {}",
+                to_hex(&self.hash)
+            )),
+        }
+    }
+}
+
+/// Re-encodes the transactions of a block, ordered by index, checks each against its hash
+/// and checks the transactions trie they form against the transactions root of the header.
+pub fn verify_transactions_root(
+    block: &Block,
+    transactions: &[Value],
+) -> Result<Vec<BlockTransaction>, String> {
+    let transactions = transactions
+        .iter()
+        .map(block_transaction)
+        .collect::<Result<Vec<_>, _>>()?;
+    let entries: Vec<_> = transactions
+        .iter()
+        .enumerate()
+        .map(|(index, tx)| {
+            let key = rlp::encode(&Rlp::Bytes(&scalar(&Nat::from(index as u64))));
+            (key, tx.encoding.clone())
+        })
+        .collect();
+    if to_hex(&trie::trie_root(&entries)) != block.transactions_root {
+        return Err(format!(
+            "transactions do not match the transactions root of block {}",
+            block.hash
+        ));
+    }
+    Ok(transactions)
+}
+
+/// Returns the receipt of the given transaction of a block, after checking all receipts
+/// of the block against its receipts root. Fields not committed to by the receipts trie are
+/// derived from the verified transactions and header, except for the blob gas price, which
+/// depends on fork parameters of the chain.
+pub fn verified_receipt(
+    block: &Block,
+    transactions: &[BlockTransaction],
+    receipts: &[TransactionReceipt],
+    transaction_hash: &str,
+) -> Result<TransactionReceipt, String> {
+    verify_receipts_root(block, receipts)?;
+    if receipts.len() != transactions.len() {
+        return Err(format!(
+            "block {} has {} transactions and {} receipts",
+            block.hash,
+            transactions.len(),
+            receipts.len()
+        ));
+    }
+    let hash = from_hex(transaction_hash)?;
+    let index = transactions
+        .iter()
+        .position(|tx| tx.hash[..] == hash[..])
+        .ok_or_else(|| {
+            format!(
+                "transaction {} is not included in block {}",
+                transaction_hash, block.hash
+            )
+        })?;
+    let tx = &transactions[index];
+    let receipt = &receipts[index];
+    let from = tx.sender()?;
+    let previous_gas_used = match index {
+        0 => BigUint::from(0u8),
+        _ => receipts[index - 1].cumulative_gas_used.0.clone(),
+    };
+    if receipt.cumulative_gas_used.0 < previous_gas_used {
+        return Err(format!(
+            "cumulative gas used decreases at transaction {}",
+            transaction_hash
+        ));
+    }
+    let contract_address = match tx.to {
+        Some(_) => None,
+        None => {
+            let nonce = scalar(&tx.nonce);
+            let hash = keccak256(&rlp::encode(&Rlp::List(vec![
+                Rlp::Bytes(&from),
+                Rlp::Bytes(&nonce),
+            ])));
+            Some(to_hex(&hash[12..]))
+        }
+    };
+    let first_log_index = receipts[..index]
+        .iter()
+        .map(|receipt| receipt.logs.len() as u64)
+        .sum::<u64>();
+    let transaction_index = Nat::from(index as u64);
+    let transaction_hash = to_hex(&tx.hash);
+    let logs = receipt
+        .logs
+        .iter()
+        .zip(first_log_index..)
+        .map(|(log, log_index)| Log {
+            block_number: Some(block.number.clone()),
+            block_hash: Some(block.hash.clone()),
+            transaction_hash: Some(transaction_hash.clone()),
+            transaction_index: Some(transaction_index.clone()),
+            log_index: Some(Nat::from(log_index)),
+            removed: false,
+            ..log.clone()
+        })
+        .collect();
+    let blob_gas_used = tx
+        .blob_versioned_hashes
+        .as_ref()
+        .map(|hashes| Nat::from(hashes.len() as u64 * GAS_PER_BLOB));
+    Ok(TransactionReceipt {
+        transaction_hash,
+        transaction_index,
+        transaction_type: Nat::from(tx.transaction_type),
+        block_hash: block.hash.clone(),
+        block_number: block.number.clone(),
+        from: to_hex(&from),
+        to: tx.to.map(|to| to_hex(&to)),
+        contract_address,
+        gas_used: Nat(&receipt.cumulative_gas_used.0 - previous_gas_used),
+        effective_gas_price: tx.effective_gas_price(block.base_fee_per_gas.as_ref())?,
+        logs,
+        blob_gas_price: blob_gas_used.as_ref().and(receipt.blob_gas_price.clone()),
+        blob_gas_used,
+        blob_versioned_hashes: tx
+            .blob_versioned_hashes
+            .as_ref()
+            .map(|hashes| hashes.iter().map(|hash| to_hex(hash)).collect()),
+        ..receipt.clone()
+    })
+}
+
+// Blob gas used by each blob of an EIP-4844 transaction.
+const GAS_PER_BLOB: u64 = 1 << 17;
+
+// Fields of a transaction as an RLP list, owning their encoded values.
+#[derive(Clone)]
+enum Field {
+    Bytes(Vec<u8>),
+    List(Vec<Field>),
+}
+
+impl Field {
+    fn rlp(&self) -> Rlp<'_> {
+        match self {
+            Field::Bytes(bytes) => Rlp::Bytes(bytes),
+            Field::List(fields) => Rlp::List(fields.iter().map(Field::rlp).collect()),
+        }
+    }
+}
+
+/// Re-encodes a legacy, EIP-2930, EIP-1559, EIP-4844 or EIP-7702 transaction of a block
+/// from its JSON representation and checks it against its hash.
+pub fn block_transaction(value: &Value) -> Result<BlockTransaction, String> {
+    let tx = JsonObject::new(value)?;
+    let transaction_type = match tx.opt_quantity("type")? {
+        None => 0,
+        Some(tx_type) => match u8::try_from(&tx_type.0) {
+            Ok(tx_type @ 0..=4) => tx_type,
+            _ => return Err(format!("unsupported transaction type {}", tx_type)),
+        },
+    };
+    let quantity = |field: &str| Ok::<_, String>(Field::Bytes(scalar(&tx.quantity(field)?)));
+    let nonce = tx.quantity("nonce")?;
+    let to = tx.opt_data("to")?.map(|to| address(&to)).transpose()?;
+    let (gas_price, max_fee_per_gas, max_priority_fee_per_gas) = match transaction_type {
+        0 | 1 => (Some(tx.quantity("gasPrice")?), None, None),
+        _ => (
+            None,
+            Some(tx.quantity("maxFeePerGas")?),
+            Some(tx.quantity("maxPriorityFeePerGas")?),
+        ),
+    };
+    let blob_versioned_hashes = match transaction_type {
+        3 => Some(
+            tx.array("blobVersionedHashes")?
+                .iter()
+                .map(|hash| {
+                    hash.as_str()
+                        .ok_or_else(|| format!("invalid hash {}", hash))
+                        .and_then(self::hash)
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+        _ => None,
+    };
+    let mut fields = vec![];
+    if transaction_type != 0 {
+        fields.push(quantity("chainId")?);
+    }
+    fields.push(Field::Bytes(scalar(&nonce)));
+    for fee in [&gas_price, &max_priority_fee_per_gas, &max_fee_per_gas]
+        .into_iter()
+        .flatten()
+    {
+        fields.push(Field::Bytes(scalar(fee)));
+    }
+    fields.push(quantity("gas")?);
+    fields.push(Field::Bytes(to.map_or(vec![], |to| to.to_vec())));
+    fields.push(quantity("value")?);
+    fields.push(Field::Bytes(from_hex(&tx.data("input")?)?));
+    if transaction_type != 0 {
+        fields.push(access_list(tx.array("accessList")?)?);
+    }
+    if let Some(hashes) = &blob_versioned_hashes {
+        fields.push(quantity("maxFeePerBlobGas")?);
+        fields.push(Field::List(
+            hashes
+                .iter()
+                .map(|hash| Field::Bytes(hash.to_vec()))
+                .collect(),
+        ));
+    }
+    if transaction_type == 4 {
+        fields.push(authorization_list(tx.array("authorizationList")?)?);
+    }
+
+    let r = tx.quantity("r")?;
+    let s = tx.quantity("s")?;
+    let v = match tx.opt_quantity("yParity")? {
+        Some(y_parity) if transaction_type != 0 => y_parity,
+        _ => tx.quantity("v")?,
+    };
+    let mut signature = [0; 64];
+    for (value, offset) in [(&r, 0), (&s, 32)] {
+        let bytes = scalar(value);
+        if bytes.len() > 32 {
+            return Err(format!("invalid signature value {}", value));
+        }
+        signature[offset + 32 - bytes.len()..offset + 32].copy_from_slice(&bytes);
+    }
+    // Legacy transactions sign the chain id in place of the signature (EIP-155).
+    let (chain_id_fields, y_parity) = match (transaction_type, u64::try_from(&v.0)) {
+        (0, Ok(v @ (27 | 28))) => (vec![], v == 28),
+        (0, Ok(v)) if v >= 35 => (
+            vec![
+                Field::Bytes(scalar(&Nat::from((v - 35) / 2))),
+                Field::Bytes(vec![]),
+                Field::Bytes(vec![]),
+            ],
+            (v - 35) % 2 == 1,
+        ),
+        (1..=4, Ok(v @ (0 | 1))) => (vec![], v == 1),
+        _ => return Err(format!("invalid signature v {}", v)),
+    };
+    let typed = |payload: Vec<u8>| match transaction_type {
+        0 => payload,
+        tx_type => [vec![tx_type], payload].concat(),
+    };
+    let mut unsigned = fields.clone();
+    unsigned.extend(chain_id_fields);
+    let signing_hash = keccak256(&typed(rlp::encode(&Field::List(unsigned).rlp())));
+    fields.extend([v, r, s].iter().map(|value| Field::Bytes(scalar(value))));
+    let encoding = typed(rlp::encode(&Field::List(fields).rlp()));
+    let hash = keccak256(&encoding);
+    if self::hash(&tx.data("hash")?)? != hash {
+        return Err(format!(
+            "transaction {} does not match its hash",
+            tx.data("hash")?
+        ));
+    }
+    Ok(BlockTransaction {
+        hash,
+        transaction_type,
+        nonce,
+        to,
+        gas_price,
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+        blob_versioned_hashes,
+        encoding,
+        signing_hash,
+        signature,
+        y_parity,
+    })
+}
+
+fn access_list(items: &[Value]) -> Result<Field, String> {
+    items
+        .iter()
+        .map(|item| {
+            let item = JsonObject::new(item)?;
+            let storage_keys = item
+                .array("storageKeys")?
+                .iter()
+                .map(|key| {
+                    key.as_str()
+                        .ok_or_else(|| format!("invalid storage key {}", key))
+                        .and_then(hash)
+                        .map(|key| Field::Bytes(key.to_vec()))
+                })
+                .collect::<Result<_, String>>()?;
+            Ok(Field::List(vec![
+                Field::Bytes(address(&item.data("address")?)?.to_vec()),
+                Field::List(storage_keys),
+            ]))
+        })
+        .collect::<Result<_, String>>()
+        .map(Field::List)
+}
+
+fn authorization_list(items: &[Value]) -> Result<Field, String> {
+    items
+        .iter()
+        .map(|item| {
+            let item = JsonObject::new(item)?;
+            let quantity =
+                |field: &str| Ok::<_, String>(Field::Bytes(scalar(&item.quantity(field)?)));
+            Ok(Field::List(vec![
+                quantity("chainId")?,
+                Field::Bytes(address(&item.data("address")?)?.to_vec()),
+                quantity("nonce")?,
+                quantity("yParity")?,
+                quantity("r")?,
+                quantity("s")?,
+            ]))
+        })
+        .collect::<Result<_, String>>()
+        .map(Field::List)
+}
+
+fn address(hex: &str) -> Result<[u8; 20], String> {
+    from_hex(hex)?
+        .try_into()
+        .map_err(|_| format!("invalid address {}", hex))
+}
+
+/// A storage key as a 32-byte slot, accepting keys with leading zeros omitted.
+fn storage_slot(key: &str) -> Result<[u8; 32], String> {
+    let bytes = match key.strip_prefix("0x") {
+        Some(digits) if digits.len() % 2 == 1 => from_hex(&format!("0x0{}", digits))?,
+        _ => from_hex(key)?,
+    };
+    if bytes.len() > 32 {
+        return Err(format!("invalid storage key {}", key));
+    }
+    let mut slot = [0; 32];
+    slot[32 - bytes.len()..].copy_from_slice(&bytes);
+    Ok(slot)
+}
+
+fn proof_nodes(nodes: &[Value]) -> Result<Vec<Vec<u8>>, String> {
+    nodes
+        .iter()
+        .map(|node| {
+            node.as_str()
+                .ok_or_else(|| format!("proof node is not a string: {}", node))
+                .and_then(from_hex)
+        })
+        .collect()
+}
+
+fn hash(hex: &str) -> Result<[u8; 32], String> {
+    to_hash(&from_hex(hex)?)
+}
+
+fn to_hash(bytes: &[u8]) -> Result<[u8; 32], String> {
+    bytes
+        .try_into()
+        .map_err(|_| format!("invalid hash {}", to_hex(bytes)))
+}
+
+// Big-endian scalar without leading zeros, as encoded in RLP.
+fn scalar(n: &Nat) -> Vec<u8> {
+    if n.0 == BigUint::from(0u8) {
+        vec![]
+    } else {
+        n.0.to_bytes_be()
+    }
+}
+
+fn nat(bytes: &[u8]) -> Nat {
+    Nat(BigUint::from_bytes_be(bytes))
+}
+
+#[cfg(test)]
+fn mainnet_genesis_block() -> Block {
+    use crate::rpc_types::BlockTransactions;
+    let zero_hash = to_hex(&[0; 32]);
+    Block {
+        number: Nat::from(0u64),
+        hash: "0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3".to_string(),
+        parent_hash: zero_hash.clone(),
+        sha3_uncles: "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347"
+            .to_string(),
+        miner: to_hex(&[0; 20]),
+        state_root: "0xd7f8974fb5ac78d9ac099b9ad5018bedc2ce0a72dad1827a1709da30580f0544"
+            .to_string(),
+        transactions_root: to_hex(&EMPTY_TRIE_ROOT),
+        receipts_root: to_hex(&EMPTY_TRIE_ROOT),
+        logs_bloom: to_hex(&[0; 256]),
+        difficulty: Nat::from(0x400000000u64),
+        total_difficulty: None,
+        gas_limit: Nat::from(5000u64),
+        gas_used: Nat::from(0u64),
+        timestamp: Nat::from(0u64),
+        extra_data: "0x11bbe8db4e347b4e8c937c1c8370e4b5ed33adb3db69cbdb7a38e1e50b1b82fa"
+            .to_string(),
+        mix_hash: zero_hash,
+        nonce: "0x0000000000000042".to_string(),
+        size: Nat::from(540u64),
+        base_fee_per_gas: None,
+        withdrawals_root: None,
+        blob_gas_used: None,
+        excess_blob_gas: None,
+        parent_beacon_block_root: None,
+        requests_hash: None,
+        uncles: vec![],
+        transactions: BlockTransactions::Hashes(vec![]),
+    }
+}
+
+#[test]
+fn check_verify_block_hash() {
+    let block = mainnet_genesis_block();
+    assert_eq!(verify_block_hash(&block), Ok(()));
+    let mut forged = block.clone();
+    forged.state_root = to_hex(&[1; 32]);
+    assert!(verify_block_hash(&forged).is_err());
+    let mut forged = block;
+    forged.base_fee_per_gas = Some(Nat::from(7u64));
+    assert!(verify_block_hash(&forged).is_err());
+}
+
+#[test]
+fn check_verify_receipts_root() {
+    let mut block = mainnet_genesis_block();
+    let receipt = |index: u64, transaction_type: u64| TransactionReceipt {
+        transaction_hash: to_hex(&[index as u8; 32]),
+        transaction_index: Nat::from(index),
+        transaction_type: Nat::from(transaction_type),
+        block_hash: block.hash.clone(),
+        block_number: Nat::from(0u64),
+        from: to_hex(&[1; 20]),
+        to: Some(to_hex(&[2; 20])),
+        contract_address: None,
+        cumulative_gas_used: Nat::from(21_000 * (index + 1)),
+        gas_used: Nat::from(21_000u64),
+        effective_gas_price: Nat::from(1u64),
+        status: Some(TransactionStatus::Success),
+        root: None,
+        logs: vec![Log {
+            address: to_hex(&[3; 20]),
+            topics: vec![to_hex(&[4; 32])],
+            data: to_hex(&[5; 64]),
+            block_number: None,
+            block_hash: None,
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: None,
+            removed: false,
+        }],
+        logs_bloom: to_hex(&[0; 256]),
+        blob_gas_used: None,
+        blob_gas_price: None,
+        blob_versioned_hashes: None,
+    };
+    let receipts: Vec<_> = (0..130).map(|index| receipt(index, index % 3)).collect();
+    let entries: Vec<_> = receipts
+        .iter()
+        .map(|receipt| {
+            (
+                rlp::encode(&Rlp::Bytes(&scalar(&receipt.transaction_index))),
+                receipt_encoding(receipt).unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(entries[0].0, vec![0x80]);
+    assert_eq!(entries[129].0, vec![0x81, 0x81]);
+    assert_eq!(entries[1].1[0], 0x01);
+    block.receipts_root = to_hex(&trie::trie_root(&entries));
+    assert_eq!(verify_receipts_root(&block, &receipts), Ok(()));
+
+    assert!(verify_receipts_root(&block, &receipts[..129]).is_err());
+    let mut forged = receipts.clone();
+    forged[7].logs[0].data = to_hex(&[6; 64]);
+    assert!(verify_receipts_root(&block, &forged).is_err());
+    let mut reordered = receipts;
+    reordered.swap(1, 2);
+    assert!(verify_receipts_root(&block, &reordered).is_err());
+}
+
+#[test]
+fn check_verify_account_proof() {
+    use serde_json::json;
+    let address = [0xaa; 20];
+    let storage_root_leaf = rlp::encode(&Rlp::List(vec![
+        Rlp::Bytes(&trie::encode_path(
+            &trie::nibbles(&keccak256(&storage_slot("0x1").unwrap())),
+            true,
+        )),
+        Rlp::Bytes(&rlp::encode(&Rlp::Bytes(&[0x2a]))),
+    ]));
+    let storage_hash = keccak256(&storage_root_leaf);
+    let account = rlp::encode(&Rlp::List(vec![
+        Rlp::Bytes(&[0x05]),
+        Rlp::Bytes(&[0x03, 0xe8]),
+        Rlp::Bytes(&storage_hash),
+        Rlp::Bytes(&keccak256(b"code")),
+    ]));
+    let state_root_leaf = rlp::encode(&Rlp::List(vec![
+        Rlp::Bytes(&trie::encode_path(
+            &trie::nibbles(&keccak256(&address)),
+            true,
+        )),
+        Rlp::Bytes(&account),
+    ]));
+    let mut block = mainnet_genesis_block();
+    block.state_root = to_hex(&keccak256(&state_root_leaf));
+    let proof = json!({
+        "accountProof": [to_hex(&state_root_leaf)],
+        "storageProof": [
+            {"key": "0x1", "value": "0x2a", "proof": [to_hex(&storage_root_leaf)]},
+            {"key": "0x2", "value": "0x0", "proof": [to_hex(&storage_root_leaf)]}
+        ]
+    });
+    let keys = ["0x01".to_string(), "0x02".to_string()];
+    let account = verify_account_proof(&block, &to_hex(&address), &keys, &proof).unwrap();
+    assert_eq!(account.nonce, Nat::from(5u64));
+    assert_eq!(account.balance, Nat::from(1000u64));
+    assert_eq!(account.storage_hash, to_hex(&storage_hash));
+    assert_eq!(
+        account.storage,
+        vec![
+            StorageSlot {
+                key: to_hex(&storage_slot("0x1").unwrap()),
+                value: Nat::from(42u64)
+            },
+            StorageSlot {
+                key: to_hex(&storage_slot("0x2").unwrap()),
+                value: Nat::from(0u64)
+            }
+        ]
+    );
+
+    // The same proof shows that another account does not exist.
+    let absent_proof = json!({"accountProof": [to_hex(&state_root_leaf)], "storageProof": []});
+    let absent = verify_account_proof(&block, &to_hex(&[0xbb; 20]), &[], &absent_proof).unwrap();
+    assert_eq!(absent.balance, Nat::from(0u64));
+    assert_eq!(absent.code_hash, to_hex(&keccak256(b"")));
+    assert!(verify_account_proof(&block, &to_hex(&address), &keys[..1], &proof).is_err());
+    let mut forged = block;
+    forged.state_root = to_hex(&[1; 32]);
+    assert!(verify_account_proof(&forged, &to_hex(&address), &keys, &proof).is_err());
+}
+
+#[test]
+fn check_verified_receipt() {
+    use crate::transaction::Eip1559Transaction;
+    use k256::ecdsa::SigningKey;
+    use serde_json::json;
+    let sender = from_hex("0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f").unwrap();
+    // The signed transaction of the EIP-155 example.
+    let legacy_raw = from_hex(
+        "0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+         8025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f\
+         761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83",
+    )
+    .unwrap();
+    let legacy = json!({
+        "hash": to_hex(&keccak256(&legacy_raw)),
+        "nonce": "0x9",
+        "gasPrice": "0x4a817c800",
+        "gas": "0x5208",
+        "to": "0x3535353535353535353535353535353535353535",
+        "value": "0xde0b6b3a7640000",
+        "input": "0x",
+        "v": "0x25",
+        "r": "0x28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276",
+        "s": "0x67cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83",
+    });
+    // A contract creation by the same key, encoded by the transaction signer.
+    let key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+    let public_key = key.verifying_key().to_encoded_point(true);
+    let creation = Eip1559Transaction {
+        chain_id: 1,
+        nonce: Nat::from(10u64),
+        max_priority_fee_per_gas: Nat::from(2_000_000_000u64),
+        max_fee_per_gas: Nat::from(40_000_000_000u64),
+        gas: Nat::from(100_000u64),
+        to: None,
+        value: Nat::from(0u64),
+        data: vec![0x60, 0x00],
+        access_list: vec![([0x36; 20], vec![[0x01; 32]])],
+    };
+    let (signature, _) = key
+        .sign_prehash_recoverable(&creation.signing_hash())
+        .unwrap();
+    let signature = ecdsa::recoverable_signature(
+        &creation.signing_hash(),
+        &signature.to_bytes(),
+        public_key.as_bytes(),
+    )
+    .unwrap();
+    let creation_raw = creation.encode(Some(&signature));
+    let creation_hash = to_hex(&keccak256(&creation_raw));
+    let y_parity = if signature.y_parity { "0x1" } else { "0x0" };
+    let transactions = vec![
+        legacy,
+        json!({
+            "type": "0x2",
+            "hash": creation_hash,
+            "chainId": "0x1",
+            "nonce": "0xa",
+            "maxPriorityFeePerGas": "0x77359400",
+            "maxFeePerGas": "0x9502f9000",
+            "gas": "0x186a0",
+            "to": null,
+            "value": "0x0",
+            "input": "0x6000",
+            "accessList": [{"address": to_hex(&[0x36; 20]), "storageKeys": [to_hex(&[0x01; 32])]}],
+            "yParity": y_parity,
+            "v": y_parity,
+            "r": to_hex(&signature.r),
+            "s": to_hex(&signature.s),
+        }),
+    ];
+    let mut block = mainnet_genesis_block();
+    block.base_fee_per_gas = Some(Nat::from(1_000_000_000u64));
+    block.transactions_root = to_hex(&trie::trie_root(&[
+        (vec![0x80], legacy_raw),
+        (vec![0x01], creation_raw),
+    ]));
+    let verified = verify_transactions_root(&block, &transactions).unwrap();
+    assert_eq!(verified[0].sender().unwrap()[..], sender[..]);
+    assert_eq!(verified[1].sender().unwrap()[..], sender[..]);
+    assert!(verify_transactions_root(&block, &transactions[..1]).is_err());
+    let mut forged = transactions.clone();
+    forged[0]["value"] = json!("0x1");
+    assert!(verify_transactions_root(&block, &forged).is_err());
+
+    // The provider labels the receipts with swapped hashes and wrong derived fields.
+    let receipt =
+        |index: u64, transaction_type: u64, cumulative_gas_used: u64| TransactionReceipt {
+            transaction_hash: transactions[1 - index as usize]["hash"]
+                .as_str()
+                .unwrap()
+                .to_string(),
+            transaction_index: Nat::from(index),
+            transaction_type: Nat::from(transaction_type),
+            block_hash: block.hash.clone(),
+            block_number: Nat::from(7u64),
+            from: to_hex(&[9; 20]),
+            to: Some(to_hex(&[9; 20])),
+            contract_address: None,
+            cumulative_gas_used: Nat::from(cumulative_gas_used),
+            gas_used: Nat::from(1u64),
+            effective_gas_price: Nat::from(1u64),
+            status: Some(TransactionStatus::Success),
+            root: None,
+            logs: vec![Log {
+                address: to_hex(&[3; 20]),
+                topics: vec![],
+                data: "0x".to_string(),
+                block_number: None,
+                block_hash: None,
+                transaction_hash: None,
+                transaction_index: None,
+                log_index: None,
+                removed: true,
+            }],
+            logs_bloom: to_hex(&[0; 256]),
+            blob_gas_used: None,
+            blob_gas_price: None,
+            blob_versioned_hashes: None,
+        };
+    let receipts = vec![receipt(0, 0, 21_000), receipt(1, 2, 74_000)];
+    block.receipts_root = to_hex(&trie::trie_root(&[
+        (vec![0x80], receipt_encoding(&receipts[0]).unwrap()),
+        (vec![0x01], receipt_encoding(&receipts[1]).unwrap()),
+    ]));
+    let creation_receipt = verified_receipt(&block, &verified, &receipts, &creation_hash).unwrap();
+    assert_eq!(creation_receipt.transaction_hash, creation_hash);
+    assert_eq!(creation_receipt.transaction_index, Nat::from(1u64));
+    assert_eq!(creation_receipt.block_number, block.number);
+    assert_eq!(creation_receipt.from, to_hex(&sender));
+    assert_eq!(creation_receipt.to, None);
+    let nonce = [10];
+    let contract_address = keccak256(&rlp::encode(&Rlp::List(vec![
+        Rlp::Bytes(&sender),
+        Rlp::Bytes(&nonce),
+    ])));
+    assert_eq!(
+        creation_receipt.contract_address,
+        Some(to_hex(&contract_address[12..]))
+    );
+    assert_eq!(creation_receipt.gas_used, Nat::from(53_000u64));
+    assert_eq!(
+        creation_receipt.effective_gas_price,
+        Nat::from(3_000_000_000u64)
+    );
+    assert_eq!(creation_receipt.logs[0].log_index, Some(Nat::from(1u64)));
+    assert_eq!(
+        creation_receipt.logs[0].transaction_hash,
+        Some(creation_hash)
+    );
+    assert!(!creation_receipt.logs[0].removed);
+
+    let legacy_receipt = verified_receipt(
+        &block,
+        &verified,
+        &receipts,
+        transactions[0]["hash"].as_str().unwrap(),
+    )
+    .unwrap();
+    assert_eq!(legacy_receipt.gas_used, Nat::from(21_000u64));
+    assert_eq!(
+        legacy_receipt.effective_gas_price,
+        Nat::from(20_000_000_000u64)
+    );
+    assert_eq!(legacy_receipt.to, Some(to_hex(&[0x35; 20])));
+    assert!(verified_receipt(&block, &verified, &receipts, &to_hex(&[1; 32])).is_err());
+}
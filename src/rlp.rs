@@ -0,0 +1,161 @@
+//! Recursive Length Prefix (RLP) serialization as specified in the Ethereum Yellow Paper.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rlp<'a> {
+    Bytes(&'a [u8]),
+    List(Vec<Rlp<'a>>),
+}
+
+impl<'a> Rlp<'a> {
+    pub fn as_bytes(&self) -> Result<&'a [u8], String> {
+        match self {
+            Rlp::Bytes(bytes) => Ok(bytes),
+            Rlp::List(_) => Err("expected RLP bytes, found list".to_string()),
+        }
+    }
+
+    pub fn as_list(&self) -> Result<&[Rlp<'a>], String> {
+        match self {
+            Rlp::List(items) => Ok(items),
+            Rlp::Bytes(_) => Err("expected RLP list, found bytes".to_string()),
+        }
+    }
+
+    /// Decodes a big-endian scalar without leading zeros.
+    pub fn as_u64(&self) -> Result<u64, String> {
+        let bytes = self.as_bytes()?;
+        if bytes.len() > 8 {
+            return Err("RLP scalar exceeds 64 bits".to_string());
+        }
+        if bytes.first() == Some(&0) {
+            return Err("RLP scalar has leading zeros".to_string());
+        }
+        Ok(bytes.iter().fold(0, |n, b| (n << 8) | *b as u64))
+    }
+}
+
+/// Decodes data consisting of exactly one canonically encoded item.
+pub fn decode(data: &[u8]) -> Result<Rlp<'_>, String> {
+    let (item, rest) = decode_item(data)?;
+    if !rest.is_empty() {
+        return Err("trailing bytes after RLP item".to_string());
+    }
+    Ok(item)
+}
+
+fn decode_item(data: &[u8]) -> Result<(Rlp<'_>, &[u8]), String> {
+    let prefix = *data
+        .first()
+        .ok_or_else(|| "unexpected end of RLP data".to_string())?;
+    match prefix {
+        0x00..=0x7f => Ok((Rlp::Bytes(&data[..1]), &data[1..])),
+        0x80..=0xbf => {
+            let (payload, rest) = split_payload(data, 0x80)?;
+            if payload.len() == 1 && payload[0] < 0x80 {
+                return Err("non-canonical RLP single byte".to_string());
+            }
+            Ok((Rlp::Bytes(payload), rest))
+        }
+        0xc0..=0xff => {
+            let (mut payload, rest) = split_payload(data, 0xc0)?;
+            let mut items = vec![];
+            while !payload.is_empty() {
+                let (item, remaining) = decode_item(payload)?;
+                items.push(item);
+                payload = remaining;
+            }
+            Ok((Rlp::List(items), rest))
+        }
+    }
+}
+
+// Splits an item with the given prefix offset (0x80 for bytes, 0xc0 for lists) into its
+// payload and the remaining data.
+fn split_payload(data: &[u8], offset: u8) -> Result<(&[u8], &[u8]), String> {
+    let short = (data[0] - offset) as usize;
+    let (len, data) = if short <= 55 {
+        (short, &data[1..])
+    } else {
+        let len_of_len = short - 55;
+        let len_bytes = data
+            .get(1..1 + len_of_len)
+            .ok_or_else(|| "unexpected end of RLP data".to_string())?;
+        if len_bytes[0] == 0 || len_of_len > 8 {
+            return Err("non-canonical RLP length".to_string());
+        }
+        let len = len_bytes.iter().fold(0u64, |n, b| (n << 8) | *b as u64);
+        if len <= 55 {
+            return Err("non-canonical RLP length".to_string());
+        }
+        (
+            usize::try_from(len).map_err(|_| "RLP length overflow".to_string())?,
+            &data[1 + len_of_len..],
+        )
+    };
+    if data.len() < len {
+        return Err("unexpected end of RLP data".to_string());
+    }
+    Ok(data.split_at(len))
+}
+
+pub fn encode(item: &Rlp<'_>) -> Vec<u8> {
+    match item {
+        Rlp::Bytes(bytes) if bytes.len() == 1 && bytes[0] < 0x80 => bytes.to_vec(),
+        Rlp::Bytes(bytes) => [encode_length(bytes.len(), 0x80), bytes.to_vec()].concat(),
+        Rlp::List(items) => {
+            let payload = items.iter().flat_map(encode).collect::<Vec<u8>>();
+            [encode_length(payload.len(), 0xc0), payload].concat()
+        }
+    }
+}
+
+fn encode_length(len: usize, offset: u8) -> Vec<u8> {
+    if len <= 55 {
+        vec![offset + len as u8]
+    } else {
+        let len_bytes = (len as u64).to_be_bytes();
+        let len_bytes = &len_bytes[len_bytes.iter().take_while(|b| **b == 0).count()..];
+        [
+            vec![offset + 55 + len_bytes.len() as u8],
+            len_bytes.to_vec(),
+        ]
+        .concat()
+    }
+}
+
+#[test]
+fn check_rlp_round_trip() {
+    let long = [0xaa; 60];
+    let cases: Vec<(Rlp, Vec<u8>)> = vec![
+        (Rlp::Bytes(b""), vec![0x80]),
+        (Rlp::Bytes(&[0x0f]), vec![0x0f]),
+        (Rlp::Bytes(&[0x80]), vec![0x81, 0x80]),
+        (Rlp::Bytes(b"dog"), b"\x83dog".to_vec()),
+        (Rlp::List(vec![]), vec![0xc0]),
+        (
+            Rlp::List(vec![Rlp::Bytes(b"cat"), Rlp::Bytes(b"dog")]),
+            b"\xc8\x83cat\x83dog".to_vec(),
+        ),
+        (Rlp::Bytes(&long), [vec![0xb8, 60], long.to_vec()].concat()),
+    ];
+    for (item, encoded) in cases {
+        assert_eq!(encode(&item), encoded);
+        assert_eq!(decode(&encoded).unwrap(), item);
+    }
+}
+
+#[test]
+fn check_rlp_rejects_non_canonical() {
+    // Single byte below 0x80 encoded as a string.
+    assert!(decode(&[0x81, 0x05]).is_err());
+    // Short string encoded with a long length.
+    assert!(decode(&[0xb8, 0x03, b'd', b'o', b'g']).is_err());
+    // Truncated and trailing data.
+    assert!(decode(&[0x83, b'd', b'o']).is_err());
+    assert!(decode(&[0x80, 0x80]).is_err());
+    assert_eq!(
+        Rlp::Bytes(&[0x00, 0x01]).as_u64(),
+        Err("RLP scalar has leading zeros".to_string())
+    );
+    assert_eq!(Rlp::Bytes(&[0x01, 0x00]).as_u64(), Ok(256));
+}
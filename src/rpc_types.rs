@@ -0,0 +1,610 @@
+//! Candid types returned by the typed endpoints and their decoding from JSON-RPC results.
+//!
+//! Quantities are returned as `nat`, hashes, addresses and byte arrays as `0x` prefixed
+//! lowercase hex.
+
+use candid::{CandidType, Deserialize, Nat};
+use serde_json::{Map, Value};
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum TransactionStatus {
+    Success,
+    Failure,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct Log {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    // Not set for pending logs.
+    pub block_number: Option<Nat>,
+    pub block_hash: Option<String>,
+    pub transaction_hash: Option<String>,
+    pub transaction_index: Option<Nat>,
+    pub log_index: Option<Nat>,
+    pub removed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct TransactionReceipt {
+    pub transaction_hash: String,
+    pub transaction_index: Nat,
+    pub transaction_type: Nat,
+    pub block_hash: String,
+    pub block_number: Nat,
+    pub from: String,
+    pub to: Option<String>,
+    pub contract_address: Option<String>,
+    pub cumulative_gas_used: Nat,
+    pub gas_used: Nat,
+    pub effective_gas_price: Nat,
+    // `None` for pre-Byzantium receipts, which carry the post-transaction state root instead.
+    pub status: Option<TransactionStatus>,
+    pub root: Option<String>,
+    pub logs: Vec<Log>,
+    pub logs_bloom: String,
+    // EIP-4844 blob transactions only.
+    pub blob_gas_used: Option<Nat>,
+    pub blob_gas_price: Option<Nat>,
+    pub blob_versioned_hashes: Option<Vec<String>>,
+}
+
+/// Selects a block by tag, number or hash.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum BlockTag {
+    Latest,
+    Safe,
+    Finalized,
+    Number(Nat),
+    Hash(String),
+}
+
+impl BlockTag {
+    /// The JSON-RPC method fetching the block and its block parameter.
+    pub fn get_block_request(&self) -> (&'static str, Value) {
+        match self {
+            BlockTag::Hash(hash) => ("eth_getBlockByHash", Value::String(hash.clone())),
+            tag => ("eth_getBlockByNumber", tag.to_param()),
+        }
+    }
+
+    /// The block parameter of methods like `eth_call`, see EIP-1898 for blocks selected
+    /// by hash.
+    pub fn to_param(&self) -> Value {
+        match self {
+            BlockTag::Latest => Value::from("latest"),
+            BlockTag::Safe => Value::from("safe"),
+            BlockTag::Finalized => Value::from("finalized"),
+            BlockTag::Number(number) => Value::from(format!("{:#x}", number.0)),
+            BlockTag::Hash(hash) => serde_json::json!({ "blockHash": hash }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct Transaction {
+    pub hash: String,
+    pub transaction_type: Nat,
+    pub chain_id: Option<Nat>,
+    pub nonce: Nat,
+    pub from: String,
+    pub to: Option<String>,
+    pub value: Nat,
+    pub gas: Nat,
+    // Legacy and EIP-2930 transactions, and the effective gas price of mined EIP-1559
+    // transactions on most clients.
+    pub gas_price: Option<Nat>,
+    pub max_fee_per_gas: Option<Nat>,
+    pub max_priority_fee_per_gas: Option<Nat>,
+    pub max_fee_per_blob_gas: Option<Nat>,
+    pub blob_versioned_hashes: Option<Vec<String>>,
+    // Not set for legacy transactions.
+    pub access_list: Option<Vec<AccessListItem>>,
+    pub input: String,
+    // Not set for pending transactions.
+    pub block_hash: Option<String>,
+    pub block_number: Option<Nat>,
+    pub transaction_index: Option<Nat>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct AccessListItem {
+    pub address: String,
+    pub storage_keys: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub enum BlockTransactions {
+    Hashes(Vec<String>),
+    Full(Vec<Transaction>),
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct Block {
+    pub number: Nat,
+    pub hash: String,
+    pub parent_hash: String,
+    pub sha3_uncles: String,
+    pub miner: String,
+    pub state_root: String,
+    pub transactions_root: String,
+    pub receipts_root: String,
+    pub logs_bloom: String,
+    pub difficulty: Nat,
+    pub total_difficulty: Option<Nat>,
+    pub gas_limit: Nat,
+    pub gas_used: Nat,
+    pub timestamp: Nat,
+    pub extra_data: String,
+    pub mix_hash: String,
+    pub nonce: String,
+    pub size: Nat,
+    // Set from the London (EIP-1559), Shanghai (EIP-4895), Cancun (EIP-4844, EIP-4788) and
+    // Prague (EIP-7685) forks on.
+    pub base_fee_per_gas: Option<Nat>,
+    pub withdrawals_root: Option<String>,
+    pub blob_gas_used: Option<Nat>,
+    pub excess_blob_gas: Option<Nat>,
+    pub parent_beacon_block_root: Option<String>,
+    pub requests_hash: Option<String>,
+    pub uncles: Vec<String>,
+    pub transactions: BlockTransactions,
+}
+
+/// A transaction to be estimated, simulated or signed. Unset fields are left to the node.
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
+pub struct TransactionRequest {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub value: Option<Nat>,
+    pub input: Option<String>,
+    pub gas: Option<Nat>,
+    pub gas_price: Option<Nat>,
+    pub max_fee_per_gas: Option<Nat>,
+    pub max_priority_fee_per_gas: Option<Nat>,
+    pub nonce: Option<Nat>,
+}
+
+impl TransactionRequest {
+    /// The JSON-RPC transaction object, e.g. for `eth_estimateGas`.
+    pub fn to_json(&self) -> Result<Value, String> {
+        let mut object = Map::new();
+        let data_fields = [
+            ("from", &self.from),
+            ("to", &self.to),
+            // Some clients do not accept `input` yet.
+            ("data", &self.input),
+        ];
+        for (field, value) in data_fields {
+            if let Some(value) = value {
+                let data = parse_data(&Value::from(value.as_str()))
+                    .map_err(|e| format!("{}: {}", field, e))?;
+                object.insert(field.to_string(), Value::from(data));
+            }
+        }
+        let quantity_fields = [
+            ("value", &self.value),
+            ("gas", &self.gas),
+            ("gasPrice", &self.gas_price),
+            ("maxFeePerGas", &self.max_fee_per_gas),
+            ("maxPriorityFeePerGas", &self.max_priority_fee_per_gas),
+            ("nonce", &self.nonce),
+        ];
+        for (field, value) in quantity_fields {
+            if let Some(value) = value {
+                object.insert(field.to_string(), Value::from(format!("{:#x}", value.0)));
+            }
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+pub fn block(value: &Value) -> Result<Block, String> {
+    let block = JsonObject::new(value)?;
+    let transactions = block.array("transactions")?;
+    Ok(Block {
+        number: block.quantity("number")?,
+        hash: block.data("hash")?,
+        parent_hash: block.data("parentHash")?,
+        sha3_uncles: block.data("sha3Uncles")?,
+        miner: block.data("miner")?,
+        state_root: block.data("stateRoot")?,
+        transactions_root: block.data("transactionsRoot")?,
+        receipts_root: block.data("receiptsRoot")?,
+        logs_bloom: block.data("logsBloom")?,
+        difficulty: block.quantity("difficulty")?,
+        total_difficulty: block.opt_quantity("totalDifficulty")?,
+        gas_limit: block.quantity("gasLimit")?,
+        gas_used: block.quantity("gasUsed")?,
+        timestamp: block.quantity("timestamp")?,
+        extra_data: block.data("extraData")?,
+        mix_hash: block.data("mixHash")?,
+        nonce: block.data("nonce")?,
+        size: block.quantity("size")?,
+        base_fee_per_gas: block.opt_quantity("baseFeePerGas")?,
+        withdrawals_root: block.opt_data("withdrawalsRoot")?,
+        blob_gas_used: block.opt_quantity("blobGasUsed")?,
+        excess_blob_gas: block.opt_quantity("excessBlobGas")?,
+        parent_beacon_block_root: block.opt_data("parentBeaconBlockRoot")?,
+        requests_hash: block.opt_data("requestsHash")?,
+        uncles: block
+            .array("uncles")?
+            .iter()
+            .map(parse_data)
+            .collect::<Result<_, _>>()?,
+        // Blocks without transactions are decoded as hashes.
+        transactions: if transactions.iter().all(Value::is_string) {
+            BlockTransactions::Hashes(
+                transactions
+                    .iter()
+                    .map(parse_data)
+                    .collect::<Result<_, _>>()?,
+            )
+        } else {
+            BlockTransactions::Full(
+                transactions
+                    .iter()
+                    .map(transaction)
+                    .collect::<Result<_, _>>()?,
+            )
+        },
+    })
+}
+
+pub fn transaction(value: &Value) -> Result<Transaction, String> {
+    let tx = JsonObject::new(value)?;
+    Ok(Transaction {
+        hash: tx.data("hash")?,
+        transaction_type: tx.opt_quantity("type")?.unwrap_or_else(|| Nat::from(0u64)),
+        chain_id: tx.opt_quantity("chainId")?,
+        nonce: tx.quantity("nonce")?,
+        from: tx.data("from")?,
+        to: tx.opt_data("to")?,
+        value: tx.quantity("value")?,
+        gas: tx.quantity("gas")?,
+        gas_price: tx.opt_quantity("gasPrice")?,
+        max_fee_per_gas: tx.opt_quantity("maxFeePerGas")?,
+        max_priority_fee_per_gas: tx.opt_quantity("maxPriorityFeePerGas")?,
+        max_fee_per_blob_gas: tx.opt_quantity("maxFeePerBlobGas")?,
+        blob_versioned_hashes: blob_versioned_hashes(value)?,
+        access_list: access_list(&tx)?,
+        input: tx.data("input")?,
+        block_hash: tx.opt_data("blockHash")?,
+        block_number: tx.opt_quantity("blockNumber")?,
+        transaction_index: tx.opt_quantity("transactionIndex")?,
+    })
+}
+
+pub fn transaction_receipt(value: &Value) -> Result<TransactionReceipt, String> {
+    let receipt = JsonObject::new(value)?;
+    Ok(TransactionReceipt {
+        transaction_hash: receipt.data("transactionHash")?,
+        transaction_index: receipt.quantity("transactionIndex")?,
+        // Receipts of legacy transactions predating EIP-2718 omit the type.
+        transaction_type: receipt
+            .opt_quantity("type")?
+            .unwrap_or_else(|| Nat::from(0u64)),
+        block_hash: receipt.data("blockHash")?,
+        block_number: receipt.quantity("blockNumber")?,
+        from: receipt.data("from")?,
+        to: receipt.opt_data("to")?,
+        contract_address: receipt.opt_data("contractAddress")?,
+        cumulative_gas_used: receipt.quantity("cumulativeGasUsed")?,
+        gas_used: receipt.quantity("gasUsed")?,
+        effective_gas_price: receipt.quantity("effectiveGasPrice")?,
+        status: match receipt.opt_quantity("status")? {
+            None => None,
+            Some(status) if status == 1u64 => Some(TransactionStatus::Success),
+            Some(status) if status == 0u64 => Some(TransactionStatus::Failure),
+            Some(status) => return Err(format!("invalid status {}", status)),
+        },
+        root: receipt.opt_data("root")?,
+        logs: receipt
+            .array("logs")?
+            .iter()
+            .map(log)
+            .collect::<Result<_, _>>()?,
+        logs_bloom: receipt.data("logsBloom")?,
+        blob_gas_used: receipt.opt_quantity("blobGasUsed")?,
+        blob_gas_price: receipt.opt_quantity("blobGasPrice")?,
+        blob_versioned_hashes: None,
+    })
+}
+
+/// The `blobVersionedHashes` of a transaction object.
+pub fn blob_versioned_hashes(transaction: &Value) -> Result<Option<Vec<String>>, String> {
+    let transaction = JsonObject::new(transaction)?;
+    transaction
+        .get("blobVersionedHashes")
+        .map(|hashes| {
+            hashes
+                .as_array()
+                .ok_or_else(|| "blobVersionedHashes is not an array".to_string())?
+                .iter()
+                .map(parse_data)
+                .collect()
+        })
+        .transpose()
+}
+
+fn access_list(transaction: &JsonObject) -> Result<Option<Vec<AccessListItem>>, String> {
+    transaction
+        .get("accessList")
+        .map(|items| {
+            items
+                .as_array()
+                .ok_or_else(|| "accessList is not an array".to_string())?
+                .iter()
+                .map(|item| {
+                    let item = JsonObject::new(item)?;
+                    Ok(AccessListItem {
+                        address: item.data("address")?,
+                        storage_keys: item
+                            .array("storageKeys")?
+                            .iter()
+                            .map(parse_data)
+                            .collect::<Result<_, _>>()?,
+                    })
+                })
+                .collect()
+        })
+        .transpose()
+}
+
+pub fn log(value: &Value) -> Result<Log, String> {
+    let log = JsonObject::new(value)?;
+    Ok(Log {
+        address: log.data("address")?,
+        topics: log
+            .array("topics")?
+            .iter()
+            .map(parse_data)
+            .collect::<Result<_, _>>()?,
+        data: log.data("data")?,
+        block_number: log.opt_quantity("blockNumber")?,
+        block_hash: log.opt_data("blockHash")?,
+        transaction_hash: log.opt_data("transactionHash")?,
+        transaction_index: log.opt_quantity("transactionIndex")?,
+        log_index: log.opt_quantity("logIndex")?,
+        removed: log.get("removed").and_then(Value::as_bool).unwrap_or(false),
+    })
+}
+
+/// Parses a hex encoded quantity such as `0x1a`.
+pub fn parse_quantity(value: &Value) -> Result<Nat, String> {
+    let s = value
+        .as_str()
+        .ok_or_else(|| format!("quantity is not a string: {}", value))?;
+    s.strip_prefix("0x")
+        .filter(|digits| !digits.is_empty())
+        .and_then(|digits| num::BigUint::parse_bytes(digits.as_bytes(), 16))
+        .map(Nat)
+        .ok_or_else(|| format!("invalid quantity {}", s))
+}
+
+/// Parses and normalizes hex encoded data such as a hash or an address.
+pub fn parse_data(value: &Value) -> Result<String, String> {
+    let s = value
+        .as_str()
+        .ok_or_else(|| format!("data is not a string: {}", value))?;
+    match s.strip_prefix("0x") {
+        Some(digits) if digits.len() % 2 == 0 && digits.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(s.to_ascii_lowercase())
+        }
+        _ => Err(format!("invalid data {}", s)),
+    }
+}
+
+/// Field access for a JSON object, treating `null` members as absent.
+pub struct JsonObject<'a>(&'a Map<String, Value>);
+
+impl<'a> JsonObject<'a> {
+    pub fn new(value: &'a Value) -> Result<Self, String> {
+        value
+            .as_object()
+            .map(JsonObject)
+            .ok_or_else(|| format!("expected an object: {}", value))
+    }
+
+    pub fn get(&self, field: &str) -> Option<&'a Value> {
+        self.0.get(field).filter(|value| !value.is_null())
+    }
+
+    fn required(&self, field: &str) -> Result<&'a Value, String> {
+        self.get(field)
+            .ok_or_else(|| format!("missing field {}", field))
+    }
+
+    pub fn quantity(&self, field: &str) -> Result<Nat, String> {
+        parse_quantity(self.required(field)?).map_err(|e| format!("{}: {}", field, e))
+    }
+
+    pub fn opt_quantity(&self, field: &str) -> Result<Option<Nat>, String> {
+        self.get(field)
+            .map(|value| parse_quantity(value).map_err(|e| format!("{}: {}", field, e)))
+            .transpose()
+    }
+
+    pub fn data(&self, field: &str) -> Result<String, String> {
+        parse_data(self.required(field)?).map_err(|e| format!("{}: {}", field, e))
+    }
+
+    pub fn opt_data(&self, field: &str) -> Result<Option<String>, String> {
+        self.get(field)
+            .map(|value| parse_data(value).map_err(|e| format!("{}: {}", field, e)))
+            .transpose()
+    }
+
+    pub fn array(&self, field: &str) -> Result<&'a Vec<Value>, String> {
+        self.required(field)?
+            .as_array()
+            .ok_or_else(|| format!("{} is not an array", field))
+    }
+}
+
+#[test]
+fn check_parse_quantity() {
+    use serde_json::json;
+    assert_eq!(parse_quantity(&json!("0x0")), Ok(Nat::from(0u64)));
+    assert_eq!(parse_quantity(&json!("0x1A")), Ok(Nat::from(26u64)));
+    assert_eq!(
+        parse_quantity(&json!("0xffffffffffffffffffffffffffffffff")),
+        Ok(Nat::from(u128::MAX))
+    );
+    assert!(parse_quantity(&json!("0x")).is_err());
+    assert!(parse_quantity(&json!("12")).is_err());
+    assert!(parse_quantity(&json!(12)).is_err());
+}
+
+#[test]
+fn check_transaction_receipt() {
+    let value = serde_json::json!({
+        "blockHash": "0xA7",
+        "blockNumber": "0x10",
+        "contractAddress": null,
+        "cumulativeGasUsed": "0x5208",
+        "effectiveGasPrice": "0x3b9aca00",
+        "from": "0x01",
+        "gasUsed": "0x5208",
+        "logs": [{
+            "address": "0x02",
+            "topics": ["0xddf2", "0x00"],
+            "data": "0x",
+            "blockNumber": "0x10",
+            "blockHash": "0xa7",
+            "transactionHash": "0xb1",
+            "transactionIndex": "0x0",
+            "logIndex": "0x3",
+            "removed": false
+        }],
+        "logsBloom": "0x00",
+        "status": "0x1",
+        "to": "0x02",
+        "transactionHash": "0xb1",
+        "transactionIndex": "0x0",
+        "type": "0x3",
+        "blobGasUsed": "0x20000",
+        "blobGasPrice": "0x1"
+    });
+    let receipt = transaction_receipt(&value).unwrap();
+    assert_eq!(receipt.block_hash, "0xa7");
+    assert_eq!(receipt.block_number, Nat::from(16u64));
+    assert_eq!(receipt.transaction_type, Nat::from(3u64));
+    assert_eq!(receipt.status, Some(TransactionStatus::Success));
+    assert_eq!(receipt.contract_address, None);
+    assert_eq!(receipt.blob_gas_used, Some(Nat::from(0x20000u64)));
+    assert_eq!(receipt.logs.len(), 1);
+    assert_eq!(receipt.logs[0].topics, vec!["0xddf2", "0x00"]);
+    assert_eq!(receipt.logs[0].log_index, Some(Nat::from(3u64)));
+
+    let mut value = value;
+    value["status"] = serde_json::json!("0x2");
+    assert!(transaction_receipt(&value).is_err());
+}
+
+#[test]
+fn check_block() {
+    use serde_json::json;
+    let mut value = json!({
+        "number": "0x1b4",
+        "hash": "0xdc0818cf78f21a8e70579cb46a43643f78291264dda342ae31049421c82d21ae",
+        "parentHash": "0xe99e022112df268087ea7eafaf4790497fd21dbeeb6bd7a1721df161a6657a54",
+        "sha3Uncles": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+        "miner": "0x01",
+        "stateRoot": "0x02",
+        "transactionsRoot": "0x03",
+        "receiptsRoot": "0x04",
+        "logsBloom": "0x00",
+        "difficulty": "0x0",
+        "gasLimit": "0x1c9c380",
+        "gasUsed": "0x5208",
+        "timestamp": "0x6553f100",
+        "extraData": "0x",
+        "mixHash": "0x05",
+        "nonce": "0x0000000000000000",
+        "size": "0x220",
+        "baseFeePerGas": "0x7",
+        "withdrawalsRoot": "0x06",
+        "blobGasUsed": "0x0",
+        "excessBlobGas": "0x0",
+        "parentBeaconBlockRoot": "0x07",
+        "uncles": [],
+        "transactions": ["0xB1"]
+    });
+    let decoded = block(&value).unwrap();
+    assert_eq!(decoded.number, Nat::from(0x1b4u64));
+    assert_eq!(decoded.base_fee_per_gas, Some(Nat::from(7u64)));
+    assert_eq!(decoded.total_difficulty, None);
+    assert_eq!(decoded.requests_hash, None);
+    assert_eq!(
+        decoded.transactions,
+        BlockTransactions::Hashes(vec!["0xb1".to_string()])
+    );
+
+    value["transactions"] = json!([{
+        "hash": "0xb1",
+        "type": "0x2",
+        "chainId": "0x1",
+        "nonce": "0x0",
+        "from": "0x01",
+        "to": null,
+        "value": "0x0",
+        "gas": "0x5208",
+        "gasPrice": "0x8",
+        "maxFeePerGas": "0x10",
+        "maxPriorityFeePerGas": "0x1",
+        "input": "0x60",
+        "blockHash": "0xdc",
+        "blockNumber": "0x1b4",
+        "transactionIndex": "0x0"
+    }]);
+    match block(&value).unwrap().transactions {
+        BlockTransactions::Full(transactions) => {
+            assert_eq!(transactions.len(), 1);
+            assert_eq!(transactions[0].to, None);
+            assert_eq!(transactions[0].max_fee_per_gas, Some(Nat::from(16u64)));
+            assert_eq!(transactions[0].blob_versioned_hashes, None);
+        }
+        transactions => panic!("unexpected transactions {:?}", transactions),
+    }
+}
+
+#[test]
+fn check_transaction_request() {
+    let request = TransactionRequest {
+        to: Some("0xAB".to_string()),
+        value: Some(Nat::from(1000u64)),
+        input: Some("0x".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        request.to_json(),
+        Ok(serde_json::json!({"to": "0xab", "value": "0x3e8", "data": "0x"}))
+    );
+    let request = TransactionRequest {
+        from: Some("0x1".to_string()),
+        ..Default::default()
+    };
+    assert!(request.to_json().is_err());
+}
+
+#[test]
+fn check_block_tag() {
+    assert_eq!(
+        BlockTag::Number(Nat::from(255u64)).get_block_request(),
+        ("eth_getBlockByNumber", Value::from("0xff"))
+    );
+    assert_eq!(
+        BlockTag::Finalized.get_block_request(),
+        ("eth_getBlockByNumber", Value::from("finalized"))
+    );
+    assert_eq!(
+        BlockTag::Hash("0x01".to_string()).get_block_request(),
+        ("eth_getBlockByHash", Value::from("0x01"))
+    );
+    assert_eq!(
+        BlockTag::Hash("0x01".to_string()).to_param(),
+        serde_json::json!({"blockHash": "0x01"})
+    );
+}
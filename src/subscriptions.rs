@@ -0,0 +1,286 @@
+//! Subscriptions of canisters to the logs of a chain, delivered in batches to a callback
+//! method of the subscriber.
+
+use crate::eth::from_hex;
+use crate::rpc_types::Log;
+use candid::{CandidType, Deserialize, Principal};
+use serde_json::Value;
+
+const MAX_ADDRESSES: usize = 32;
+const MAX_TOPICS: usize = 4;
+const MAX_TOPIC_VALUES: usize = 8;
+const MAX_CALLBACK_METHOD_LENGTH: usize = 64;
+// Limits the logs and the cost of a single `eth_getLogs` request.
+pub const MAX_BLOCK_RANGE: u64 = 500;
+// Consecutive failed polls or deliveries before a subscription is paused.
+pub const MAX_FAILED_ATTEMPTS: u32 = 8;
+// Keeps the errors of paused subscriptions within their bounded stored size.
+pub const MAX_ERROR_LENGTH: usize = 256;
+
+/// Logs matching any of the addresses, or all addresses if empty, and at each position the
+/// topic matching any of the values, or all topics if `null`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub struct LogFilter {
+    pub addresses: Vec<String>,
+    pub topics: Vec<Option<Vec<String>>>,
+}
+
+impl LogFilter {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.addresses.len() > MAX_ADDRESSES {
+            return Err(format!("at most {} addresses are allowed", MAX_ADDRESSES));
+        }
+        for address in &self.addresses {
+            check_length(address, 20).map_err(|e| format!("address: {}", e))?;
+        }
+        if self.topics.len() > MAX_TOPICS {
+            return Err(format!("at most {} topics are allowed", MAX_TOPICS));
+        }
+        for values in self.topics.iter().flatten() {
+            if values.is_empty() || values.len() > MAX_TOPIC_VALUES {
+                return Err(format!(
+                    "topics must have between 1 and {} values",
+                    MAX_TOPIC_VALUES
+                ));
+            }
+            for topic in values {
+                check_length(topic, 32).map_err(|e| format!("topic: {}", e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Params of the `eth_getLogs` request for the given blocks.
+    pub fn params(&self, from_block: u64, to_block: u64) -> Value {
+        let mut filter = serde_json::json!({
+            "fromBlock": format!("{:#x}", from_block),
+            "toBlock": format!("{:#x}", to_block),
+            "topics": self.topics,
+        });
+        if !self.addresses.is_empty() {
+            filter["address"] = Value::from(self.addresses.clone());
+        }
+        serde_json::json!([filter])
+    }
+}
+
+fn check_length(data: &str, len: usize) -> Result<(), String> {
+    let bytes = from_hex(data)?;
+    if bytes.len() != len {
+        return Err(format!("expected {} bytes, got {}", len, bytes.len()));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct SubscribeLogs {
+    pub chain_id: u64,
+    pub filter: LogFilter,
+    // Method of the calling canister taking a `LogBatch`.
+    pub callback_method: String,
+    // Number of blocks on top of a block before its logs are delivered.
+    pub confirmations: u64,
+    pub poll_interval_seconds: u64,
+    // Defaults to the first block not yet confirmed when the subscription is first polled.
+    pub from_block: Option<u64>,
+    pub max_response_bytes: Option<u64>,
+}
+
+impl SubscribeLogs {
+    pub fn validate(&self) -> Result<(), String> {
+        self.filter.validate()?;
+        if self.callback_method.is_empty()
+            || self.callback_method.len() > MAX_CALLBACK_METHOD_LENGTH
+        {
+            return Err(format!(
+                "callback_method must have between 1 and {} characters",
+                MAX_CALLBACK_METHOD_LENGTH
+            ));
+        }
+        if self.poll_interval_seconds == 0 {
+            return Err("poll_interval_seconds must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum SubscriptionStatus {
+    Active,
+    Paused(String),
+}
+
+impl SubscriptionStatus {
+    pub fn paused(error: String) -> Self {
+        Self::Paused(truncate_error(error))
+    }
+}
+
+/// The error truncated to at most `MAX_ERROR_LENGTH` bytes.
+pub fn truncate_error(mut error: String) -> String {
+    if error.len() > MAX_ERROR_LENGTH {
+        let mut end = MAX_ERROR_LENGTH;
+        while !error.is_char_boundary(end) {
+            end -= 1;
+        }
+        error.truncate(end);
+    }
+    error
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Subscription {
+    pub subscription_id: u64,
+    pub owner: Principal,
+    pub chain_id: u64,
+    pub filter: LogFilter,
+    pub callback_method: String,
+    pub confirmations: u64,
+    pub poll_interval_seconds: u64,
+    pub max_response_bytes: Option<u64>,
+    // The first block whose logs are not delivered yet.
+    pub next_block: Option<u64>,
+    // Prepaid cycles for the requests and deliveries.
+    pub balance: u128,
+    pub status: SubscriptionStatus,
+    pub failed_attempts: u32,
+    pub next_poll_at_ns: u64,
+}
+
+impl Subscription {
+    /// The confirmed blocks whose logs are to be delivered next, given the latest block
+    /// number, or `None` if there are none yet.
+    pub fn block_range(&self, latest_block: u64) -> Option<(u64, u64)> {
+        let confirmed = latest_block.checked_sub(self.confirmations)?;
+        let from_block = self.next_block?;
+        if from_block > confirmed {
+            return None;
+        }
+        Some((
+            from_block,
+            confirmed.min(from_block.saturating_add(MAX_BLOCK_RANGE - 1)),
+        ))
+    }
+
+    /// Starts the subscription after the confirmed blocks unless it has a start block.
+    pub fn init_cursor(&mut self, latest_block: u64) {
+        if self.next_block.is_none() {
+            self.next_block = Some((latest_block + 1).saturating_sub(self.confirmations));
+        }
+    }
+
+    /// Records a failed poll or delivery, backing off exponentially before the next attempt.
+    pub fn attempt_failed(&mut self, now_ns: u64, error: String) {
+        self.failed_attempts += 1;
+        if self.failed_attempts >= MAX_FAILED_ATTEMPTS {
+            self.status = SubscriptionStatus::paused(error);
+            return;
+        }
+        let delay_seconds = self.poll_interval_seconds << self.failed_attempts.min(6);
+        self.next_poll_at_ns = now_ns.saturating_add(delay_seconds.saturating_mul(1_000_000_000));
+    }
+
+    pub fn delivered(&mut self, now_ns: u64, to_block: u64) {
+        self.next_block = Some(to_block + 1);
+        self.failed_attempts = 0;
+        self.schedule_next_poll(now_ns);
+    }
+
+    pub fn schedule_next_poll(&mut self, now_ns: u64) {
+        self.next_poll_at_ns =
+            now_ns.saturating_add(self.poll_interval_seconds.saturating_mul(1_000_000_000));
+    }
+}
+
+/// Argument of the callback method of a subscriber.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize)]
+pub struct LogBatch {
+    pub subscription_id: u64,
+    pub chain_id: u64,
+    pub from_block: u64,
+    pub to_block: u64,
+    pub logs: Vec<Log>,
+}
+
+#[cfg(test)]
+fn subscription() -> Subscription {
+    Subscription {
+        subscription_id: 1,
+        owner: Principal::anonymous(),
+        chain_id: 1,
+        filter: LogFilter::default(),
+        callback_method: "on_logs".to_string(),
+        confirmations: 12,
+        poll_interval_seconds: 10,
+        max_response_bytes: None,
+        next_block: None,
+        balance: 0,
+        status: SubscriptionStatus::Active,
+        failed_attempts: 0,
+        next_poll_at_ns: 0,
+    }
+}
+
+#[test]
+fn check_block_range() {
+    let mut s = subscription();
+    assert_eq!(s.block_range(10), None);
+    s.init_cursor(100);
+    assert_eq!(s.next_block, Some(89));
+    assert_eq!(s.block_range(100), None);
+    s.next_block = Some(80);
+    assert_eq!(s.block_range(100), Some((80, 88)));
+    assert_eq!(s.block_range(91), None);
+    s.next_block = Some(0);
+    assert_eq!(s.block_range(10_000), Some((0, MAX_BLOCK_RANGE - 1)));
+
+    s.delivered(0, 88);
+    assert_eq!(s.next_block, Some(89));
+    assert_eq!(s.next_poll_at_ns, 10_000_000_000);
+    s.attempt_failed(0, "rejected".to_string());
+    s.attempt_failed(0, "rejected".to_string());
+    assert_eq!(s.next_poll_at_ns, 40_000_000_000);
+    for _ in 2..MAX_FAILED_ATTEMPTS {
+        s.attempt_failed(0, "rejected".to_string());
+    }
+    assert_eq!(s.status, SubscriptionStatus::Paused("rejected".to_string()));
+
+    let SubscriptionStatus::Paused(error) = SubscriptionStatus::paused("é".repeat(200)) else {
+        unreachable!()
+    };
+    assert_eq!(error, "é".repeat(MAX_ERROR_LENGTH / 2));
+    assert_eq!(
+        truncate_error(format!("{}é", "a".repeat(255))),
+        "a".repeat(255)
+    );
+}
+
+#[test]
+fn check_log_filter() {
+    let address = format!("0x{}", "ab".repeat(20));
+    let topic = format!("0x{}", "cd".repeat(32));
+    let filter = LogFilter {
+        addresses: vec![address.clone()],
+        topics: vec![None, Some(vec![topic.clone()])],
+    };
+    assert!(filter.validate().is_ok());
+    assert_eq!(
+        filter.params(16, 31),
+        serde_json::json!([{
+            "fromBlock": "0x10",
+            "toBlock": "0x1f",
+            "address": [address],
+            "topics": [null, [topic]],
+        }])
+    );
+    let filter = LogFilter {
+        addresses: vec!["0xab".to_string()],
+        topics: vec![],
+    };
+    assert!(filter.validate().is_err());
+    let filter = LogFilter {
+        addresses: vec![],
+        topics: vec![Some(vec![])],
+    };
+    assert!(filter.validate().is_err());
+}
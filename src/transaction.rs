@@ -0,0 +1,385 @@
+//! Encoding and decoding of signed Ethereum transactions and classification of
+//! `eth_sendRawTransaction` responses.
+
+use crate::eth::keccak256;
+use crate::json_rpc;
+use crate::rlp::{self, Rlp};
+use candid::{CandidType, Nat};
+use serde::Deserialize;
+
+const LEGACY_FIELDS: usize = 9;
+const EIP2930_FIELDS: usize = 11;
+const EIP1559_FIELDS: usize = 12;
+const EIP4844_FIELDS: usize = 14;
+// Blob transactions are broadcast wrapped with their blobs, commitments and proofs.
+const EIP4844_NETWORK_FIELDS: usize = 4;
+
+/// A signed transaction as broadcast with `eth_sendRawTransaction`.
+#[derive(Debug)]
+pub struct RawTransaction {
+    /// `None` for legacy transactions without replay protection (EIP-155).
+    pub chain_id: Option<u64>,
+    pub hash: [u8; 32],
+}
+
+/// Decodes a signed legacy, EIP-2930, EIP-1559 or EIP-4844 transaction.
+pub fn decode_raw_transaction(raw: &[u8]) -> Result<RawTransaction, String> {
+    match raw.first() {
+        None => Err("empty transaction".to_string()),
+        Some(0xc0..=0xff) => {
+            let item = rlp::decode(raw)?;
+            let fields = expect_fields(&item, LEGACY_FIELDS)?;
+            let v = fields[6].as_u64()?;
+            let chain_id = match v {
+                27 | 28 => None,
+                v if v >= 35 => Some((v - 35) / 2),
+                v => return Err(format!("invalid signature v {}", v)),
+            };
+            Ok(RawTransaction {
+                chain_id,
+                hash: keccak256(raw),
+            })
+        }
+        Some(&tx_type @ (0x01 | 0x02)) => {
+            let item = rlp::decode(&raw[1..])?;
+            let field_count = if tx_type == 0x01 {
+                EIP2930_FIELDS
+            } else {
+                EIP1559_FIELDS
+            };
+            let fields = expect_fields(&item, field_count)?;
+            Ok(RawTransaction {
+                chain_id: Some(fields[0].as_u64()?),
+                hash: keccak256(raw),
+            })
+        }
+        Some(0x03) => {
+            let item = rlp::decode(&raw[1..])?;
+            let (fields, hash) = match item.as_list()?.first() {
+                Some(body @ Rlp::List(_)) => {
+                    expect_fields(&item, EIP4844_NETWORK_FIELDS)?;
+                    // The hash commits to the transaction without the blobs.
+                    let hash = keccak256(&[&[0x03], rlp::encode(body).as_slice()].concat());
+                    (expect_fields(body, EIP4844_FIELDS)?, hash)
+                }
+                _ => (expect_fields(&item, EIP4844_FIELDS)?, keccak256(raw)),
+            };
+            Ok(RawTransaction {
+                chain_id: Some(fields[0].as_u64()?),
+                hash,
+            })
+        }
+        Some(tx_type) => Err(format!("unsupported transaction type {}", tx_type)),
+    }
+}
+
+/// An EIP-1559 transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Eip1559Transaction {
+    pub chain_id: u64,
+    pub nonce: Nat,
+    pub max_priority_fee_per_gas: Nat,
+    pub max_fee_per_gas: Nat,
+    pub gas: Nat,
+    // `None` for contract creations.
+    pub to: Option<[u8; 20]>,
+    pub value: Nat,
+    pub data: Vec<u8>,
+    // Addresses and storage keys, see EIP-2930.
+    pub access_list: Vec<([u8; 20], Vec<[u8; 32]>)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransactionSignature {
+    pub y_parity: bool,
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+}
+
+impl Eip1559Transaction {
+    /// The hash to sign.
+    pub fn signing_hash(&self) -> [u8; 32] {
+        keccak256(&self.encode(None))
+    }
+
+    /// The typed transaction envelope, signed or not.
+    pub fn encode(&self, signature: Option<&TransactionSignature>) -> Vec<u8> {
+        let chain_id = self.chain_id.to_be_bytes();
+        let quantities: Vec<Vec<u8>> = [
+            &self.nonce,
+            &self.max_priority_fee_per_gas,
+            &self.max_fee_per_gas,
+            &self.gas,
+            &self.value,
+        ]
+        .iter()
+        .map(|n| quantity_bytes(n))
+        .collect();
+        let access_list = self
+            .access_list
+            .iter()
+            .map(|(address, storage_keys)| {
+                Rlp::List(vec![
+                    Rlp::Bytes(address),
+                    Rlp::List(storage_keys.iter().map(|key| Rlp::Bytes(key)).collect()),
+                ])
+            })
+            .collect();
+        let mut fields = vec![
+            Rlp::Bytes(trim_leading_zeros(&chain_id)),
+            Rlp::Bytes(&quantities[0]),
+            Rlp::Bytes(&quantities[1]),
+            Rlp::Bytes(&quantities[2]),
+            Rlp::Bytes(&quantities[3]),
+            Rlp::Bytes(self.to.as_ref().map_or(&[][..], |to| &to[..])),
+            Rlp::Bytes(&quantities[4]),
+            Rlp::Bytes(&self.data),
+            Rlp::List(access_list),
+        ];
+        if let Some(signature) = signature {
+            fields.extend([
+                Rlp::Bytes(if signature.y_parity { &[1] } else { &[] }),
+                Rlp::Bytes(trim_leading_zeros(&signature.r)),
+                Rlp::Bytes(trim_leading_zeros(&signature.s)),
+            ]);
+        }
+        [vec![0x02], rlp::encode(&Rlp::List(fields))].concat()
+    }
+}
+
+// Big-endian bytes without leading zeros, as RLP encodes scalars.
+fn quantity_bytes(n: &Nat) -> Vec<u8> {
+    trim_leading_zeros(&n.0.to_bytes_be()).to_vec()
+}
+
+fn trim_leading_zeros(bytes: &[u8]) -> &[u8] {
+    &bytes[bytes.iter().take_while(|b| **b == 0).count()..]
+}
+
+fn expect_fields<'a, 'b>(item: &'b Rlp<'a>, count: usize) -> Result<&'b [Rlp<'a>], String> {
+    let fields = item.as_list()?;
+    if fields.len() != count {
+        return Err(format!(
+            "expected {} transaction fields, found {}",
+            count,
+            fields.len()
+        ));
+    }
+    Ok(fields)
+}
+
+/// Outcome of broadcasting a transaction, normalized across the error messages of
+/// different Ethereum clients.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum SendRawTransactionStatus {
+    Ok,
+    AlreadyKnown,
+    NonceTooLow,
+    NonceTooHigh,
+    ReplacementUnderpriced,
+    Underpriced,
+    InsufficientFunds,
+    Rejected,
+}
+
+/// Combines the statuses reported by several providers: the transaction was accepted if
+/// any provider accepted it or already knew it.
+pub fn combine_send_raw_transaction_statuses<'a>(
+    statuses: impl Iterator<Item = &'a SendRawTransactionStatus> + Clone,
+) -> Option<SendRawTransactionStatus> {
+    [
+        SendRawTransactionStatus::Ok,
+        SendRawTransactionStatus::AlreadyKnown,
+    ]
+    .into_iter()
+    .find(|accepted| statuses.clone().any(|status| status == accepted))
+    .or_else(|| statuses.clone().next().cloned())
+}
+
+/// Classifies the body of an `eth_sendRawTransaction` response.
+pub fn send_raw_transaction_status(body: &[u8]) -> SendRawTransactionStatus {
+    let Some(error) = json_rpc::response_error(body) else {
+        return match json_rpc::response_result(body) {
+            Some(_) => SendRawTransactionStatus::Ok,
+            None => SendRawTransactionStatus::Rejected,
+        };
+    };
+    let message = error.message.to_lowercase();
+    let contains_any = |patterns: &[&str]| patterns.iter().any(|p| message.contains(p));
+    if contains_any(&["already known", "known transaction", "already imported"]) {
+        SendRawTransactionStatus::AlreadyKnown
+    } else if contains_any(&["nonce too low", "nonce is too low"]) {
+        SendRawTransactionStatus::NonceTooLow
+    } else if contains_any(&["nonce too high", "nonce is too high"]) {
+        SendRawTransactionStatus::NonceTooHigh
+    } else if contains_any(&[
+        "replacement transaction underpriced",
+        "replacement fee too low",
+    ]) {
+        SendRawTransactionStatus::ReplacementUnderpriced
+    } else if contains_any(&["underpriced", "less than block base fee", "fee too low"]) {
+        SendRawTransactionStatus::Underpriced
+    } else if contains_any(&["insufficient funds"]) {
+        SendRawTransactionStatus::InsufficientFunds
+    } else {
+        SendRawTransactionStatus::Rejected
+    }
+}
+
+#[test]
+fn check_decode_legacy_transaction() {
+    // The EIP-155 example transaction.
+    let raw = crate::eth::from_hex("0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83").unwrap();
+    let tx = decode_raw_transaction(&raw).unwrap();
+    assert_eq!(tx.chain_id, Some(1));
+    assert_eq!(
+        crate::eth::to_hex(&tx.hash),
+        "0x33469b22e9f636356c4160a87eb19df52b7412e8eac32a4a55ffe88ea8350788"
+    );
+}
+
+#[test]
+fn check_decode_typed_transactions() {
+    let chain_id = [0x05];
+    let empty = Rlp::Bytes(b"");
+    let body = |count: usize| {
+        let mut fields = vec![Rlp::Bytes(&chain_id)];
+        fields.resize(count, empty.clone());
+        Rlp::List(fields)
+    };
+    for (tx_type, count) in [
+        (0x01, EIP2930_FIELDS),
+        (0x02, EIP1559_FIELDS),
+        (0x03, EIP4844_FIELDS),
+    ] {
+        let raw = [vec![tx_type], rlp::encode(&body(count))].concat();
+        let tx = decode_raw_transaction(&raw).unwrap();
+        assert_eq!(tx.chain_id, Some(5));
+        assert_eq!(tx.hash, keccak256(&raw));
+        let raw = [vec![tx_type], rlp::encode(&body(count - 1))].concat();
+        assert!(decode_raw_transaction(&raw).is_err());
+    }
+
+    let network = Rlp::List(vec![
+        body(EIP4844_FIELDS),
+        Rlp::List(vec![]),
+        Rlp::List(vec![]),
+        Rlp::List(vec![]),
+    ]);
+    let tx = decode_raw_transaction(&[vec![0x03], rlp::encode(&network)].concat()).unwrap();
+    assert_eq!(tx.chain_id, Some(5));
+    assert_eq!(
+        tx.hash,
+        keccak256(&[vec![0x03], rlp::encode(&body(EIP4844_FIELDS))].concat())
+    );
+    assert!(decode_raw_transaction(&[0x04, 0xc0]).is_err());
+}
+
+#[test]
+fn check_encode_eip1559_transaction() {
+    let tx = Eip1559Transaction {
+        chain_id: 1,
+        nonce: Nat::from(0u64),
+        max_priority_fee_per_gas: Nat::from(1_000_000_000u64),
+        max_fee_per_gas: Nat::from(30_000_000_000u64),
+        gas: Nat::from(21_000u64),
+        to: Some([0x35; 20]),
+        value: Nat::from(1_000_000_000_000_000_000u64),
+        data: vec![],
+        access_list: vec![],
+    };
+    let signature = TransactionSignature {
+        y_parity: true,
+        r: [0x11; 32],
+        s: [0x22; 32],
+    };
+    let raw = tx.encode(Some(&signature));
+    let decoded = decode_raw_transaction(&raw).unwrap();
+    assert_eq!(decoded.chain_id, Some(1));
+    let item = rlp::decode(&raw[1..]).unwrap();
+    let fields = item.as_list().unwrap();
+    assert_eq!(fields[1].as_bytes().unwrap(), b"");
+    assert_eq!(fields[4].as_u64().unwrap(), 21_000);
+    assert_eq!(fields[9].as_u64().unwrap(), 1);
+    assert_eq!(tx.signing_hash(), keccak256(&tx.encode(None)));
+    assert_eq!(
+        rlp::decode(&tx.encode(None)[1..])
+            .unwrap()
+            .as_list()
+            .unwrap()
+            .len(),
+        9
+    );
+
+    let tx = Eip1559Transaction {
+        access_list: vec![([0x36; 20], vec![[0x01; 32], [0x02; 32]])],
+        ..tx
+    };
+    let raw = tx.encode(None);
+    let item = rlp::decode(&raw[1..]).unwrap();
+    let access_list = item.as_list().unwrap()[8].as_list().unwrap();
+    let entry = access_list[0].as_list().unwrap();
+    assert_eq!(entry[0].as_bytes().unwrap(), [0x36; 20]);
+    assert_eq!(
+        entry[1].as_list().unwrap()[1].as_bytes().unwrap(),
+        [0x02; 32]
+    );
+}
+
+#[test]
+fn check_send_raw_transaction_status() {
+    let error = |message: &str| {
+        format!(
+            r#"{{"jsonrpc":"2.0","id":1,"error":{{"code":-32000,"message":"{}"}}}}"#,
+            message
+        )
+    };
+    assert_eq!(
+        send_raw_transaction_status(br#"{"jsonrpc":"2.0","id":1,"result":"0x01"}"#),
+        SendRawTransactionStatus::Ok
+    );
+    for (message, status) in [
+        ("already known", SendRawTransactionStatus::AlreadyKnown),
+        (
+            "Known transaction: 0x01",
+            SendRawTransactionStatus::AlreadyKnown,
+        ),
+        ("nonce too low", SendRawTransactionStatus::NonceTooLow),
+        (
+            "replacement transaction underpriced",
+            SendRawTransactionStatus::ReplacementUnderpriced,
+        ),
+        (
+            "transaction underpriced",
+            SendRawTransactionStatus::Underpriced,
+        ),
+        (
+            "insufficient funds for gas * price + value",
+            SendRawTransactionStatus::InsufficientFunds,
+        ),
+        ("intrinsic gas too low", SendRawTransactionStatus::Rejected),
+    ] {
+        assert_eq!(
+            send_raw_transaction_status(error(message).as_bytes()),
+            status
+        );
+    }
+}
+
+#[test]
+fn check_combine_send_raw_transaction_statuses() {
+    use SendRawTransactionStatus::*;
+    assert_eq!(
+        combine_send_raw_transaction_statuses([NonceTooLow, AlreadyKnown, Ok].iter()),
+        Some(Ok)
+    );
+    assert_eq!(
+        combine_send_raw_transaction_statuses([NonceTooLow, AlreadyKnown].iter()),
+        Some(AlreadyKnown)
+    );
+    assert_eq!(
+        combine_send_raw_transaction_statuses([Underpriced, Rejected].iter()),
+        Some(Underpriced)
+    );
+    assert_eq!(combine_send_raw_transaction_statuses([].iter()), None);
+}
@@ -0,0 +1,278 @@
+//! Merkle Patricia tries as used for the Ethereum state, storage, transactions and receipts.
+
+use crate::eth::keccak256;
+use crate::rlp::{self, Rlp};
+
+/// Root hash of the empty trie, `keccak256(rlp(""))`.
+pub const EMPTY_TRIE_ROOT: [u8; 32] = [
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+];
+
+/// Verifies a proof, i.e., the RLP encoded nodes on the path from the root to the key, and
+/// returns the value stored under the key or `None` if the proof shows that there is none.
+pub fn verify_proof(
+    root: &[u8; 32],
+    key: &[u8],
+    proof: &[Vec<u8>],
+) -> Result<Option<Vec<u8>>, String> {
+    if proof.is_empty() {
+        if *root == EMPTY_TRIE_ROOT {
+            return Ok(None);
+        }
+        return Err("empty proof for a non-empty trie".to_string());
+    }
+    let key = nibbles(key);
+    let mut path = key.as_slice();
+    let mut expected_hash = *root;
+    for (i, encoded) in proof.iter().enumerate() {
+        if keccak256(encoded) != expected_hash {
+            return Err(format!("proof node {} does not match its hash", i));
+        }
+        let done = |value: Option<&[u8]>| {
+            if i + 1 != proof.len() {
+                return Err("unexpected proof nodes after the end of the path".to_string());
+            }
+            Ok(value.map(<[u8]>::to_vec))
+        };
+        let mut node = rlp::decode(encoded)?;
+        // Follows the path through the node and the nodes embedded in it.
+        loop {
+            let items = node.as_list()?;
+            let child = match items.len() {
+                17 => match path.split_first() {
+                    None => return done(Some(items[16].as_bytes()?).filter(|v| !v.is_empty())),
+                    Some((nibble, rest)) => {
+                        path = rest;
+                        items[*nibble as usize].clone()
+                    }
+                },
+                2 => {
+                    let (node_path, is_leaf) = decode_path(items[0].as_bytes()?)?;
+                    let rest = path.strip_prefix(node_path.as_slice());
+                    match (rest, is_leaf) {
+                        (Some([]), true) => return done(Some(items[1].as_bytes()?)),
+                        (Some(rest), false) => {
+                            path = rest;
+                            items[1].clone()
+                        }
+                        // The path diverges from the trie.
+                        _ => return done(None),
+                    }
+                }
+                n => return Err(format!("invalid trie node with {} items", n)),
+            };
+            match child {
+                Rlp::Bytes([]) => return done(None),
+                Rlp::Bytes(hash) if hash.len() == 32 => {
+                    expected_hash.copy_from_slice(hash);
+                    break;
+                }
+                Rlp::List(_) => node = child,
+                Rlp::Bytes(_) => return Err("invalid trie node reference".to_string()),
+            }
+        }
+    }
+    Err("proof ends before the path".to_string())
+}
+
+/// Root hash of the trie with the given distinct keys and their values.
+pub fn trie_root(entries: &[(Vec<u8>, Vec<u8>)]) -> [u8; 32] {
+    if entries.is_empty() {
+        return EMPTY_TRIE_ROOT;
+    }
+    let mut entries: Vec<(Vec<u8>, &[u8])> = entries
+        .iter()
+        .map(|(key, value)| (nibbles(key), value.as_slice()))
+        .collect();
+    entries.sort();
+    keccak256(&encode_node(&entries, 0))
+}
+
+// Encodes the node holding the entries, sorted by path, whose paths share the first
+// `depth` nibbles.
+fn encode_node(entries: &[(Vec<u8>, &[u8])], depth: usize) -> Vec<u8> {
+    if let [(path, value)] = entries {
+        return rlp::encode(&Rlp::List(vec![
+            Rlp::Bytes(&encode_path(&path[depth..], true)),
+            Rlp::Bytes(value),
+        ]));
+    }
+    let first = &entries[0].0[depth..];
+    let last = &entries[entries.len() - 1].0[depth..];
+    let prefix_len = first.iter().zip(last).take_while(|(a, b)| a == b).count();
+    if prefix_len > 0 {
+        let child = encode_node(entries, depth + prefix_len);
+        let child = child_reference(&child);
+        return rlp::encode(&Rlp::List(vec![
+            Rlp::Bytes(&encode_path(&first[..prefix_len], false)),
+            child.as_rlp(),
+        ]));
+    }
+    // Sorting puts an entry ending at this node first.
+    let (value, entries) = match entries.split_first() {
+        Some(((path, value), rest)) if path.len() == depth => (*value, rest),
+        _ => (&[][..], entries),
+    };
+    let children: Vec<ChildReference> = (0..16)
+        .map(|nibble| {
+            let start = entries.partition_point(|(path, _)| path[depth] < nibble);
+            let end = entries.partition_point(|(path, _)| path[depth] <= nibble);
+            if start == end {
+                ChildReference::Hash(vec![])
+            } else {
+                child_reference(&encode_node(&entries[start..end], depth + 1))
+            }
+        })
+        .collect();
+    let mut items: Vec<Rlp> = children.iter().map(ChildReference::as_rlp).collect();
+    items.push(Rlp::Bytes(value));
+    rlp::encode(&Rlp::List(items))
+}
+
+enum ChildReference {
+    // Nodes shorter than a hash are embedded in their parent.
+    Embedded(Vec<u8>),
+    Hash(Vec<u8>),
+}
+
+impl ChildReference {
+    fn as_rlp(&self) -> Rlp<'_> {
+        match self {
+            ChildReference::Embedded(node) => rlp::decode(node).expect("encoded trie node"),
+            ChildReference::Hash(hash) => Rlp::Bytes(hash),
+        }
+    }
+}
+
+fn child_reference(node: &[u8]) -> ChildReference {
+    if node.len() < 32 {
+        ChildReference::Embedded(node.to_vec())
+    } else {
+        ChildReference::Hash(keccak256(node).to_vec())
+    }
+}
+
+pub fn nibbles(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect()
+}
+
+/// Hex-prefix encoding of a path.
+pub fn encode_path(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+    let flag = if is_leaf { 0x20 } else { 0x00 };
+    let mut encoded = if nibbles.len() % 2 == 1 {
+        vec![flag | 0x10 | nibbles[0]]
+    } else {
+        vec![flag]
+    };
+    let even = &nibbles[nibbles.len() % 2..];
+    encoded.extend(even.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    encoded
+}
+
+fn decode_path(encoded: &[u8]) -> Result<(Vec<u8>, bool), String> {
+    let first = *encoded
+        .first()
+        .ok_or_else(|| "empty trie node path".to_string())?;
+    let mut path = match first >> 4 {
+        0 | 2 if first & 0x0f == 0 => vec![],
+        1 | 3 => vec![first & 0x0f],
+        _ => return Err(format!("invalid trie node path prefix {:#x}", first)),
+    };
+    path.extend(nibbles(&encoded[1..]));
+    Ok((path, first >> 4 >= 2))
+}
+
+#[test]
+fn check_encode_path() {
+    assert_eq!(encode_path(&[1, 2, 3, 4, 5], false), vec![0x11, 0x23, 0x45]);
+    assert_eq!(
+        encode_path(&[0, 1, 2, 3, 4, 5], false),
+        vec![0x00, 0x01, 0x23, 0x45]
+    );
+    assert_eq!(
+        encode_path(&[0x0f, 1, 0x0c, 0x0b, 8], true),
+        vec![0x3f, 0x1c, 0xb8]
+    );
+    assert_eq!(encode_path(&[], true), vec![0x20]);
+    for (path, is_leaf) in [(vec![1, 2, 3], true), (vec![0, 0xf], false)] {
+        assert_eq!(
+            decode_path(&encode_path(&path, is_leaf)),
+            Ok((path, is_leaf))
+        );
+    }
+    assert!(decode_path(&[0x01]).is_err());
+    assert!(decode_path(&[0x40]).is_err());
+}
+
+#[test]
+fn check_trie_root() {
+    let entries = |pairs: &[(&str, &str)]| {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(trie_root(&[]), EMPTY_TRIE_ROOT);
+    // Test vectors from the Ethereum trie tests.
+    assert_eq!(
+        crate::eth::to_hex(&trie_root(&entries(&[
+            ("doe", "reindeer"),
+            ("dog", "puppy"),
+            ("dogglesworth", "cat")
+        ]))),
+        "0x8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"
+    );
+    assert_eq!(
+        crate::eth::to_hex(&trie_root(&entries(&[
+            ("do", "verb"),
+            ("horse", "stallion"),
+            ("doge", "coin"),
+            ("dog", "puppy")
+        ]))),
+        "0x5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84"
+    );
+}
+
+#[test]
+fn check_verify_proof() {
+    let leaf = |key: &[u8], value: &[u8]| {
+        rlp::encode(&Rlp::List(vec![
+            Rlp::Bytes(&encode_path(&nibbles(key)[1..], true)),
+            Rlp::Bytes(value),
+        ]))
+    };
+    let value1 = [0x01; 40];
+    let value2 = [0x02; 40];
+    let leaf1 = leaf(&[0x10; 32], &value1);
+    let leaf2 = leaf(&[0x20; 32], &value2);
+    let (hash1, hash2) = (keccak256(&leaf1), keccak256(&leaf2));
+    let mut children = vec![Rlp::Bytes(b""); 17];
+    children[1] = Rlp::Bytes(&hash1);
+    children[2] = Rlp::Bytes(&hash2);
+    let branch = rlp::encode(&Rlp::List(children));
+    let root = keccak256(&branch);
+
+    let proof = vec![branch.clone(), leaf1];
+    assert_eq!(
+        verify_proof(&root, &[0x10; 32], &proof),
+        Ok(Some(value1.to_vec()))
+    );
+    assert_eq!(
+        verify_proof(&root, &[0x20; 32], &[branch.clone(), leaf2]),
+        Ok(Some(value2.to_vec()))
+    );
+    // Absent keys: an empty branch slot and a diverging leaf path.
+    assert_eq!(
+        verify_proof(&root, &[0x30; 32], std::slice::from_ref(&branch)),
+        Ok(None)
+    );
+    assert_eq!(verify_proof(&root, &[0x11; 32], &proof), Ok(None));
+
+    assert!(verify_proof(&root, &[0x10; 32], std::slice::from_ref(&branch)).is_err());
+    assert!(verify_proof(&root, &[0x30; 32], &proof).is_err());
+    let forged = leaf(&[0x10; 32], &[0x03; 40]);
+    assert!(verify_proof(&root, &[0x10; 32], &[branch, forged]).is_err());
+    assert_eq!(verify_proof(&EMPTY_TRIE_ROOT, &[0x10; 32], &[]), Ok(None));
+    assert!(verify_proof(&root, &[0x10; 32], &[]).is_err());
+}
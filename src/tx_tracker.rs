@@ -0,0 +1,579 @@
+//! Tracking of broadcast transactions until they are confirmed or dropped.
+
+use crate::fees::FeeEstimate;
+use crate::rpc_types::{Transaction, TransactionStatus};
+use crate::transaction::Eip1559Transaction;
+use candid::{CandidType, Deserialize, Nat, Principal};
+use num_traits::ToPrimitive;
+
+const MAX_CONFIRMATIONS: u64 = 10_000;
+const MAX_CALLBACK_METHOD_LENGTH: usize = 64;
+// Polls in a row not finding a transaction before it is considered dropped.
+pub const MAX_MISSED_POLLS: u32 = 20;
+// Nodes only accept a replacement with both fees this much higher than the replaced ones.
+pub const MIN_FEE_BUMP_PERCENT: u64 = 10;
+const MAX_FEE_BUMP_PERCENT: u64 = 1_000;
+pub const MAX_REPLACEMENTS: usize = 16;
+// Final transactions are kept this long so that their owners can query the status.
+const FINAL_RETENTION_SECONDS: u64 = 3_600;
+
+/// Replaces a transaction with bumped fees when it is pending for too long.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ReplacementPolicy {
+    pub stuck_after_seconds: u64,
+    pub fee_bump_percent: u64,
+    // No replacement is sent with a higher max fee.
+    pub max_fee_per_gas: Option<Nat>,
+    pub max_replacements: u64,
+}
+
+impl ReplacementPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.stuck_after_seconds == 0 {
+            return Err("stuck_after_seconds must be positive".to_string());
+        }
+        check_fee_bump_percent(self.fee_bump_percent)?;
+        if self.max_replacements as usize > MAX_REPLACEMENTS {
+            return Err(format!(
+                "max_replacements must be at most {}",
+                MAX_REPLACEMENTS
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn check_fee_bump_percent(fee_bump_percent: u64) -> Result<(), String> {
+    if !(MIN_FEE_BUMP_PERCENT..=MAX_FEE_BUMP_PERCENT).contains(&fee_bump_percent) {
+        return Err(format!(
+            "fee_bump_percent must be between {} and {}",
+            MIN_FEE_BUMP_PERCENT, MAX_FEE_BUMP_PERCENT
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct TrackTransaction {
+    pub chain_id: u64,
+    pub transaction_hash: String,
+    // Number of blocks on top of the including block, which counts as the first. Defaults to
+    // the finality depth of the chain.
+    pub confirmations: Option<u64>,
+    // Defaults to the block time of the chain.
+    pub poll_interval_seconds: Option<u64>,
+    // Method of the calling canister taking a `TransactionUpdate`.
+    pub callback_method: Option<String>,
+    pub max_response_bytes: Option<u64>,
+    // Only applies to EIP-1559 transactions sent from the address of the caller, see
+    // `get_eth_address`.
+    pub replacement_policy: Option<ReplacementPolicy>,
+}
+
+impl TrackTransaction {
+    pub fn validate(&self) -> Result<(), String> {
+        match crate::eth::from_hex(&self.transaction_hash) {
+            Ok(hash) if hash.len() == 32 => (),
+            _ => {
+                return Err(format!(
+                    "invalid transaction hash {}",
+                    self.transaction_hash
+                ))
+            }
+        }
+        if self.confirmations > Some(MAX_CONFIRMATIONS) {
+            return Err(format!(
+                "confirmations must be at most {}",
+                MAX_CONFIRMATIONS
+            ));
+        }
+        if self.poll_interval_seconds == Some(0) {
+            return Err("poll_interval_seconds must be positive".to_string());
+        }
+        if let Some(method) = &self.callback_method {
+            if method.is_empty() || method.len() > MAX_CALLBACK_METHOD_LENGTH {
+                return Err(format!(
+                    "callback_method must have between 1 and {} characters",
+                    MAX_CALLBACK_METHOD_LENGTH
+                ));
+            }
+        }
+        if let Some(policy) = &self.replacement_policy {
+            policy.validate()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum TrackedStatus {
+    // Not included in a block yet, or not seen yet.
+    Pending,
+    Included {
+        block_number: u64,
+        block_hash: String,
+        confirmations: u64,
+    },
+    // Final.
+    Confirmed {
+        block_number: u64,
+        block_hash: String,
+        // `None` for pre-Byzantium receipts.
+        status: Option<TransactionStatus>,
+    },
+    // The block including the transaction is no longer canonical.
+    ReorgedOut {
+        block_hash: String,
+    },
+    // Final: the transaction is no longer known, or its nonce was used by another
+    // transaction.
+    Dropped(String),
+}
+
+/// What a poll found out about a transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Observation {
+    Receipt {
+        // The transaction or one of its replacements.
+        transaction_hash: String,
+        block_number: u64,
+        block_hash: String,
+        status: Option<TransactionStatus>,
+        latest_block: u64,
+    },
+    // Known to the node but not included.
+    Pending {
+        from: String,
+        nonce: Nat,
+    },
+    // Unknown to the node, with whether the nonce of the transaction, if known, was used.
+    Missing {
+        nonce_used: bool,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct TrackedTransaction {
+    pub tracking_id: u64,
+    pub owner: Principal,
+    pub chain_id: u64,
+    // The latest broadcast transaction, which replaced the others.
+    pub transaction_hash: String,
+    // Replaced transactions, oldest first.
+    pub replaced_hashes: Vec<String>,
+    // The transaction included in a block, if any.
+    pub mined_transaction_hash: Option<String>,
+    pub confirmations: u64,
+    pub poll_interval_seconds: u64,
+    pub callback_method: Option<String>,
+    pub max_response_bytes: Option<u64>,
+    pub replacement_policy: Option<ReplacementPolicy>,
+    pub status: TrackedStatus,
+    // Sender and nonce, once the transaction was seen.
+    pub from: Option<String>,
+    pub nonce: Option<Nat>,
+    pub missed_polls: u32,
+    pub reorgs: u32,
+    // Prepaid cycles for the requests and callbacks.
+    pub balance: u128,
+    // For final transactions, the time they are removed at.
+    pub next_poll_at_ns: u64,
+    pub broadcast_at_ns: u64,
+}
+
+impl TrackedTransaction {
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self.status,
+            TrackedStatus::Confirmed { .. } | TrackedStatus::Dropped(_)
+        )
+    }
+
+    /// Updates the status and returns whether it changed in a way the owner is notified
+    /// of, i.e., anything but the number of confirmations.
+    pub fn observe(&mut self, observation: Observation) -> bool {
+        let status = match observation {
+            Observation::Receipt {
+                transaction_hash,
+                block_number,
+                block_hash,
+                status,
+                latest_block,
+            } => {
+                self.missed_polls = 0;
+                self.mined_transaction_hash = Some(transaction_hash);
+                let confirmations = (latest_block + 1).saturating_sub(block_number);
+                if let TrackedStatus::Included {
+                    block_hash: previous,
+                    ..
+                } = &self.status
+                {
+                    if *previous != block_hash {
+                        self.reorgs += 1;
+                    }
+                }
+                if confirmations >= self.confirmations.max(1) {
+                    TrackedStatus::Confirmed {
+                        block_number,
+                        block_hash,
+                        status,
+                    }
+                } else {
+                    TrackedStatus::Included {
+                        block_number,
+                        block_hash,
+                        confirmations,
+                    }
+                }
+            }
+            Observation::Pending { from, nonce } => {
+                self.missed_polls = 0;
+                self.seen(from, nonce);
+                match &self.status {
+                    TrackedStatus::Included { block_hash, .. } => {
+                        self.reorgs += 1;
+                        self.mined_transaction_hash = None;
+                        TrackedStatus::ReorgedOut {
+                            block_hash: block_hash.clone(),
+                        }
+                    }
+                    _ => TrackedStatus::Pending,
+                }
+            }
+            Observation::Missing { nonce_used } => {
+                self.missed_polls += 1;
+                match &self.status {
+                    TrackedStatus::Included { block_hash, .. } => {
+                        self.reorgs += 1;
+                        self.mined_transaction_hash = None;
+                        TrackedStatus::ReorgedOut {
+                            block_hash: block_hash.clone(),
+                        }
+                    }
+                    _ if nonce_used => {
+                        TrackedStatus::Dropped("nonce used by another transaction".to_string())
+                    }
+                    _ if self.missed_polls >= MAX_MISSED_POLLS => {
+                        TrackedStatus::Dropped("transaction not found".to_string())
+                    }
+                    status => status.clone(),
+                }
+            }
+        };
+        let notify = match (&self.status, &status) {
+            (
+                TrackedStatus::Included { block_hash, .. },
+                TrackedStatus::Included {
+                    block_hash: new_block_hash,
+                    ..
+                },
+            ) => block_hash != new_block_hash,
+            (previous, status) => previous != status,
+        };
+        self.status = status;
+        notify
+    }
+
+    /// Records the sender and nonce, which the replacements share.
+    pub fn seen(&mut self, from: String, nonce: Nat) {
+        self.from = Some(from);
+        self.nonce = Some(nonce);
+    }
+
+    /// Keeps a final transaction until the retention period is over.
+    pub fn schedule_removal(&mut self, now_ns: u64) {
+        self.next_poll_at_ns = now_ns.saturating_add(FINAL_RETENTION_SECONDS * 1_000_000_000);
+    }
+
+    pub fn schedule_next_poll(&mut self, now_ns: u64) {
+        self.next_poll_at_ns =
+            now_ns.saturating_add(self.poll_interval_seconds.saturating_mul(1_000_000_000));
+    }
+
+    /// The transaction whose receipt is polled.
+    pub fn polled_hash(&self) -> &str {
+        self.mined_transaction_hash
+            .as_deref()
+            .unwrap_or(&self.transaction_hash)
+    }
+
+    pub fn check_replaceable(&self) -> Result<(), String> {
+        if self.status != TrackedStatus::Pending {
+            return Err("the transaction is not pending".to_string());
+        }
+        if self.replaced_hashes.len() >= MAX_REPLACEMENTS {
+            return Err(format!(
+                "a transaction can be replaced at most {} times",
+                MAX_REPLACEMENTS
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether the replacement policy applies to the transaction, which was seen pending.
+    pub fn is_stuck(&self, now_ns: u64) -> bool {
+        let Some(policy) = &self.replacement_policy else {
+            return false;
+        };
+        self.check_replaceable().is_ok()
+            && (self.replaced_hashes.len() as u64) < policy.max_replacements
+            && now_ns.saturating_sub(self.broadcast_at_ns)
+                >= policy.stuck_after_seconds.saturating_mul(1_000_000_000)
+    }
+
+    pub fn replaced(&mut self, transaction_hash: String, now_ns: u64) {
+        let replaced = std::mem::replace(&mut self.transaction_hash, transaction_hash);
+        self.replaced_hashes.push(replaced);
+        self.missed_polls = 0;
+        self.broadcast_at_ns = now_ns;
+    }
+}
+
+/// The transaction replacing a pending EIP-1559 transaction: the same transaction with both
+/// fees bumped by the given percentage, and at least the estimated fees.
+pub fn replacement_transaction(
+    tx: &Transaction,
+    estimate: &FeeEstimate,
+    fee_bump_percent: u64,
+    max_fee_per_gas_cap: Option<&Nat>,
+) -> Result<Eip1559Transaction, String> {
+    if tx.transaction_type != 2u64 {
+        return Err("only EIP-1559 transactions can be replaced".to_string());
+    }
+    let (Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) =
+        (&tx.max_fee_per_gas, &tx.max_priority_fee_per_gas)
+    else {
+        return Err("missing fees".to_string());
+    };
+    let chain_id = tx
+        .chain_id
+        .as_ref()
+        .and_then(|chain_id| chain_id.0.to_u64())
+        .ok_or("missing chain id")?;
+    let to = match &tx.to {
+        Some(to) => Some(
+            crate::eth::from_hex(to)?
+                .try_into()
+                .map_err(|_| format!("invalid to {}", to))?,
+        ),
+        None => None,
+    };
+    let access_list = tx
+        .access_list
+        .iter()
+        .flatten()
+        .map(|item| {
+            let address = crate::eth::from_hex(&item.address)?
+                .try_into()
+                .map_err(|_| format!("invalid access list address {}", item.address))?;
+            let storage_keys = item
+                .storage_keys
+                .iter()
+                .map(|key| {
+                    crate::eth::from_hex(key)?
+                        .try_into()
+                        .map_err(|_| format!("invalid storage key {}", key))
+                })
+                .collect::<Result<_, String>>()?;
+            Ok((address, storage_keys))
+        })
+        .collect::<Result<_, String>>()?;
+    let max_priority_fee_per_gas = bump(max_priority_fee_per_gas, fee_bump_percent)
+        .max(estimate.max_priority_fee_per_gas.clone());
+    let max_fee_per_gas = bump(max_fee_per_gas, fee_bump_percent)
+        .max(estimate.max_fee_per_gas.clone())
+        .max(max_priority_fee_per_gas.clone());
+    if let Some(cap) = max_fee_per_gas_cap {
+        if max_fee_per_gas > *cap {
+            return Err(format!(
+                "max_fee_per_gas {} of the replacement exceeds {}",
+                max_fee_per_gas, cap
+            ));
+        }
+    }
+    Ok(Eip1559Transaction {
+        chain_id,
+        nonce: tx.nonce.clone(),
+        max_priority_fee_per_gas,
+        max_fee_per_gas,
+        gas: tx.gas.clone(),
+        to,
+        value: tx.value.clone(),
+        data: crate::eth::from_hex(&tx.input)?,
+        access_list,
+    })
+}
+
+// Rounds up, and bumps by at least one so that small fees increase.
+fn bump(fee: &Nat, percent: u64) -> Nat {
+    let bumped = (fee.0.clone() * (100 + percent) + 99u32) / 100u32;
+    Nat(bumped.max(fee.0.clone() + 1u32))
+}
+
+/// Argument of the callback method of the owner of a tracked transaction.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct TransactionUpdate {
+    pub tracking_id: u64,
+    pub chain_id: u64,
+    pub transaction_hash: String,
+    pub mined_transaction_hash: Option<String>,
+    pub status: TrackedStatus,
+}
+
+#[cfg(test)]
+fn tracked_transaction() -> TrackedTransaction {
+    TrackedTransaction {
+        tracking_id: 0,
+        owner: Principal::anonymous(),
+        chain_id: 1,
+        transaction_hash: format!("0x{}", "11".repeat(32)),
+        replaced_hashes: vec![],
+        mined_transaction_hash: None,
+        confirmations: 3,
+        poll_interval_seconds: 12,
+        callback_method: None,
+        max_response_bytes: None,
+        replacement_policy: None,
+        status: TrackedStatus::Pending,
+        from: None,
+        nonce: None,
+        missed_polls: 0,
+        reorgs: 0,
+        balance: 0,
+        next_poll_at_ns: 0,
+        broadcast_at_ns: 0,
+    }
+}
+
+#[test]
+fn check_transaction_lifecycle() {
+    let receipt = |block_hash: &str, latest_block: u64| Observation::Receipt {
+        transaction_hash: format!("0x{}", "11".repeat(32)),
+        block_number: 100,
+        block_hash: block_hash.to_string(),
+        status: Some(TransactionStatus::Success),
+        latest_block,
+    };
+    let mut tx = tracked_transaction();
+    let pending = Observation::Pending {
+        from: "0xaa".to_string(),
+        nonce: Nat::from(7u64),
+    };
+    assert!(!tx.observe(pending.clone()));
+    assert_eq!(tx.nonce, Some(Nat::from(7u64)));
+
+    assert!(tx.observe(receipt("0x01", 100)));
+    assert!(!tx.observe(receipt("0x01", 101)));
+    assert_eq!(
+        tx.status,
+        TrackedStatus::Included {
+            block_number: 100,
+            block_hash: "0x01".to_string(),
+            confirmations: 2
+        }
+    );
+    // Reorged out, back in the mempool and included in another block.
+    assert!(tx.observe(pending));
+    assert_eq!(
+        tx.status,
+        TrackedStatus::ReorgedOut {
+            block_hash: "0x01".to_string()
+        }
+    );
+    assert!(tx.observe(receipt("0x02", 101)));
+    assert!(tx.observe(receipt("0x02", 102)));
+    assert!(tx.is_final());
+    assert_eq!(tx.reorgs, 1);
+
+    let mut tx = tracked_transaction();
+    for _ in 1..MAX_MISSED_POLLS {
+        assert!(!tx.observe(Observation::Missing { nonce_used: false }));
+    }
+    assert!(tx.observe(Observation::Missing { nonce_used: false }));
+    assert!(tx.is_final());
+
+    let mut tx = tracked_transaction();
+    assert!(tx.observe(Observation::Missing { nonce_used: true }));
+    assert_eq!(
+        tx.status,
+        TrackedStatus::Dropped("nonce used by another transaction".to_string())
+    );
+    tx.schedule_removal(1_000_000_000);
+    assert_eq!(tx.next_poll_at_ns, 3_601_000_000_000);
+}
+
+#[test]
+fn check_replacement() {
+    let mut tracked = tracked_transaction();
+    tracked.replacement_policy = Some(ReplacementPolicy {
+        stuck_after_seconds: 60,
+        fee_bump_percent: 12,
+        max_fee_per_gas: Some(Nat::from(40u64)),
+        max_replacements: 2,
+    });
+    assert!(tracked
+        .replacement_policy
+        .as_ref()
+        .unwrap()
+        .validate()
+        .is_ok());
+    assert!(!tracked.is_stuck(59_000_000_000));
+    assert!(tracked.is_stuck(60_000_000_000));
+    let original = tracked.transaction_hash.clone();
+    tracked.replaced("0x22".to_string(), 60_000_000_000);
+    assert_eq!(tracked.replaced_hashes, vec![original]);
+    assert!(!tracked.is_stuck(60_000_000_000));
+    tracked.replaced("0x33".to_string(), 60_000_000_000);
+    assert!(!tracked.is_stuck(u64::MAX));
+
+    // A replacement is mined.
+    tracked.observe(Observation::Receipt {
+        transaction_hash: "0x22".to_string(),
+        block_number: 100,
+        block_hash: "0x01".to_string(),
+        status: Some(TransactionStatus::Success),
+        latest_block: 100,
+    });
+    assert_eq!(tracked.polled_hash(), "0x22");
+    assert!(tracked.check_replaceable().is_err());
+
+    let tx = crate::rpc_types::transaction(&serde_json::json!({
+        "hash": "0x22",
+        "type": "0x2",
+        "chainId": "0x1",
+        "nonce": "0x7",
+        "from": format!("0x{}", "aa".repeat(20)),
+        "to": format!("0x{}", "bb".repeat(20)),
+        "value": "0x1",
+        "gas": "0x5208",
+        "maxFeePerGas": "0x1e",
+        "maxPriorityFeePerGas": "0x1",
+        "accessList": [{
+            "address": format!("0x{}", "cc".repeat(20)),
+            "storageKeys": [format!("0x{}", "01".repeat(32))]
+        }],
+        "input": "0x",
+    }))
+    .unwrap();
+    let estimate = |max_fee_per_gas: u64, max_priority_fee_per_gas: u64| FeeEstimate {
+        block_number: Nat::from(100u64),
+        base_fee_per_gas: Nat::from(10u64),
+        max_priority_fee_per_gas: Nat::from(max_priority_fee_per_gas),
+        max_fee_per_gas: Nat::from(max_fee_per_gas),
+        base_fee_per_blob_gas: None,
+        max_fee_per_blob_gas: None,
+    };
+    let replacement = replacement_transaction(&tx, &estimate(20, 1), 10, None).unwrap();
+    assert_eq!(replacement.nonce, Nat::from(7u64));
+    assert_eq!(
+        replacement.access_list,
+        vec![([0xcc; 20], vec![[0x01; 32]])]
+    );
+    assert_eq!(replacement.max_fee_per_gas, Nat::from(33u64));
+    assert_eq!(replacement.max_priority_fee_per_gas, Nat::from(2u64));
+    let replacement = replacement_transaction(&tx, &estimate(36, 3), 10, None).unwrap();
+    assert_eq!(replacement.max_fee_per_gas, Nat::from(36u64));
+    assert_eq!(replacement.max_priority_fee_per_gas, Nat::from(3u64));
+    let cap = Nat::from(32u64);
+    assert!(replacement_transaction(&tx, &estimate(20, 1), 10, Some(&cap)).is_err());
+}